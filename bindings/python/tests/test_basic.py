@@ -1,8 +1,14 @@
 """Basic tests for sqlite-rembed Python bindings."""
 
+import base64
 import json
+import os
 import sqlite3
+import struct
 import sys
+import threading
+import time
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
 from pathlib import Path
 
 # Add parent directory to path for development testing
@@ -65,6 +71,1106 @@ def test_client_registration():
     conn.close()
 
 
+def test_client_name_lookup():
+    """Test that WHERE name = ? is pushed down into rembed_clients."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options) VALUES
+          ('first', 'ollama::nomic-embed-text'),
+          ('second', 'ollama::all-minilm')
+    """)
+
+    result = conn.execute(
+        "SELECT name FROM temp.rembed_clients WHERE name = 'second'"
+    ).fetchall()
+    assert result == [("second",)]
+
+    # The indexed path (idxnum 2) is chosen over the full scan (idxnum 1)
+    plan = conn.execute(
+        "EXPLAIN QUERY PLAN SELECT name FROM temp.rembed_clients WHERE name = 'second'"
+    ).fetchall()
+    assert any("INDEX 2" in row[-1] for row in plan)
+    plan = conn.execute("EXPLAIN QUERY PLAN SELECT name FROM temp.rembed_clients").fetchall()
+    assert any("INDEX 1" in row[-1] for row in plan)
+    print("✓ name lookup uses the indexed path")
+    conn.close()
+
+
+def test_client_timeout_columns():
+    """Test that timeout/retry settings are exposed as rembed_clients columns."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options) VALUES
+          ('tuned', rembed_client_options(
+              'model', 'ollama::nomic-embed-text',
+              'timeout_secs', '5',
+              'max_retries', '2'
+          )),
+          ('plain', 'ollama::nomic-embed-text')
+    """)
+
+    rows = dict(
+        (name, (timeout, retries))
+        for name, timeout, retries in conn.execute(
+            "SELECT name, timeout_secs, max_retries FROM temp.rembed_clients"
+        )
+    )
+    assert rows["tuned"] == (5, 2)
+    assert rows["plain"] == (None, 0)
+    print("✓ timeout_secs/max_retries columns reflect client settings")
+    conn.close()
+
+
+def test_default_client():
+    """Test the one-argument rembed(text) form."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    # Without a 'default' client the error explains how to configure one
+    try:
+        conn.execute("SELECT rembed('hello')").fetchone()
+        assert False, "expected rembed(text) to fail without a default client"
+    except sqlite3.OperationalError as e:
+        assert "No default client configured" in str(e)
+
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('default', 'ollama::nomic-embed-text')
+    """)
+
+    # With a default client registered, the call reaches the provider
+    try:
+        conn.execute("SELECT rembed('hello')").fetchone()
+    except sqlite3.OperationalError as e:
+        assert "No default client configured" not in str(e)
+    print("✓ rembed(text) resolves the default client")
+    conn.close()
+
+
+def test_role_prefixes():
+    """Test the rembed(client, text, role) form."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('e5', rembed_client_options(
+            'model', 'ollama::e5-small',
+            'query_prefix', 'query: ',
+            'document_prefix', 'passage: '
+        ))
+    """)
+
+    # Unknown roles are rejected before any request is sent
+    try:
+        conn.execute("SELECT rembed('e5', 'hello', 'passage')").fetchone()
+        assert False, "expected an invalid role to fail"
+    except sqlite3.OperationalError as e:
+        assert "Invalid role" in str(e)
+    print("✓ rembed validates the role argument")
+    conn.close()
+
+
+def test_model_aliases():
+    """Test that registered aliases resolve to canonical models."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    conn.execute("SELECT rembed_register_alias('small', 'openai::text-embedding-3-small')")
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options) VALUES
+        ('aliased', 'small'),
+        ('plain', 'my-custom-model')
+    """)
+    models = {
+        c["name"]: c["model"]
+        for c in json.loads(conn.execute("SELECT rembed_clients_export()").fetchone()[0])
+    }
+    assert models["aliased"] == "openai::text-embedding-3-small"
+    assert models["plain"] == "my-custom-model"
+
+    try:
+        conn.execute("SELECT rembed_register_alias('bad', 'no-provider')")
+        assert False, "expected a canonical name without a provider to fail"
+    except sqlite3.OperationalError as e:
+        assert "provider::model" in str(e)
+    print("✓ model aliases resolve to canonical names")
+    conn.close()
+
+
+def start_mock_embedding_server(requests, down=None):
+    """Serve OpenAI-style embedding responses of [0.25, 0.5], recording request bodies.
+
+    While the optional `down` event is set, every request gets a 503 instead.
+    """
+
+    class EmbeddingHandler(BaseHTTPRequestHandler):
+        def do_POST(self):
+            requests.append(self.rfile.read(int(self.headers["Content-Length"])))
+            if down is not None and down.is_set():
+                self.send_response(503)
+                self.send_header("Content-Length", "0")
+                self.end_headers()
+                return
+            body = json.dumps({
+                "object": "list",
+                "data": [{"object": "embedding", "index": 0, "embedding": [0.25, 0.5]}],
+                "model": "mock",
+                "usage": {"prompt_tokens": 1, "total_tokens": 1},
+            }).encode()
+            self.send_response(200)
+            self.send_header("Content-Type", "application/json")
+            self.send_header("Content-Length", str(len(body)))
+            self.end_headers()
+            self.wfile.write(body)
+
+        def log_message(self, *args):
+            pass
+
+    server = ThreadingHTTPServer(("127.0.0.1", 0), EmbeddingHandler)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+    return server
+
+
+def connect_mock_client(server):
+    """Open a connection with a 'mock' client pointed at a mock embedding server."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('mock', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key', 'base_url', ?
+        ))
+        """,
+        (f"http://127.0.0.1:{server.server_port}/v1",),
+    )
+    return conn
+
+
+def test_nondeterministic_rembed():
+    """Test that rembed_nd calls the provider once per row."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+
+    rows = conn.execute("""
+        WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3)
+        SELECT rembed_nd('mock', 'same text') FROM n
+    """).fetchall()
+    server.shutdown()
+
+    assert len(rows) == 3
+    assert all(struct.unpack("<2f", row[0]) == (0.25, 0.5) for row in rows)
+    assert len(requests) == 3
+    print("✓ rembed_nd re-invokes the provider for each row")
+    conn.close()
+
+
+def test_rembed_json():
+    """Test that rembed_json matches the decoded blob form."""
+    server = start_mock_embedding_server([])
+    conn = connect_mock_client(server)
+
+    blob = conn.execute("SELECT rembed('mock', 'hello')").fetchone()[0]
+    text = conn.execute("SELECT rembed_json('mock', 'hello')").fetchone()[0]
+    server.shutdown()
+
+    from_blob = struct.unpack(f"<{len(blob) // 4}f", blob)
+    from_json = json.loads(text)
+    # Round the JSON values through float32 as the blob did
+    assert struct.unpack(f"<{len(from_json)}f", struct.pack(f"<{len(from_json)}f", *from_json)) == from_blob
+    print("✓ rembed_json returns the embedding as a JSON array")
+    conn.close()
+
+
+def test_blob_text_input():
+    """Test that rembed accepts UTF-8 text stored as a BLOB."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+
+    from_text = conn.execute("SELECT rembed('mock', 'héllo')").fetchone()[0]
+    from_blob = conn.execute("SELECT rembed('mock', ?)", ("héllo".encode(),)).fetchone()[0]
+    assert from_blob == from_text
+    assert json.loads(requests[-1])["input"] in ("héllo", ["héllo"])
+
+    try:
+        conn.execute("SELECT rembed('mock', X'FFFE')").fetchone()
+        assert False, "invalid UTF-8 should be rejected"
+    except sqlite3.OperationalError as e:
+        assert "not valid UTF-8" in str(e)
+    server.shutdown()
+    print("✓ rembed accepts UTF-8 BLOB input")
+    conn.close()
+
+
+def test_with_usage():
+    """Test that rembed_with_usage surfaces the provider's prompt token count."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    result = json.loads(conn.execute("SELECT rembed_with_usage('mock', 'hello')").fetchone()[0])
+    server.shutdown()
+
+    assert result["tokens"] == 1
+    assert struct.unpack("<2f", base64.b64decode(result["embedding"])) == (0.25, 0.5)
+    print("✓ rembed_with_usage returns the embedding with its token usage")
+    conn.close()
+
+
+def test_meta():
+    """Test that rembed_meta tags the embedding with the client's model."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    result = json.loads(conn.execute("SELECT rembed_meta('mock', 'hello')").fetchone()[0])
+    server.shutdown()
+
+    assert result["model"] == "openai::mock"
+    assert result["provider"] == "openai"
+    assert result["dims"] == 2
+    assert struct.unpack("<2f", base64.b64decode(result["embedding"])) == (0.25, 0.5)
+    print("✓ rembed_meta returns the embedding with its model and provider")
+    conn.close()
+
+
+def test_prefixed_output():
+    """Test that output_format=prefixed blobs carry their dimension count."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('prefixed', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key', 'base_url', ?, 'output_format', 'prefixed'
+        ))
+        """,
+        (f"http://127.0.0.1:{server.server_port}/v1",),
+    )
+    blob, raw = conn.execute(
+        "SELECT rembed('prefixed', 'hello'), rembed_strip_prefix(rembed('prefixed', 'hello'))"
+    ).fetchone()
+    server.shutdown()
+
+    assert struct.unpack("<I2f", blob) == (2, 0.25, 0.5)
+    assert raw == struct.pack("<2f", 0.25, 0.5)
+    print("✓ output_format=prefixed blobs round-trip through rembed_strip_prefix")
+    conn.close()
+
+
+def test_vector_subtype_env():
+    """Test that REMBED_VECTOR_SUBTYPE=0 leaves embedding blobs untagged."""
+    import subprocess
+
+    script = """
+import sqlite3, struct, sys
+sys.path.insert(0, sys.argv[1])
+import sqlite_rembed
+conn = sqlite3.connect(":memory:")
+conn.enable_load_extension(True)
+sqlite_rembed.load(conn)
+blob = struct.pack("<2d", 0.25, 0.5)
+print(conn.execute("SELECT rembed_blob_subtype(rembed_f64_to_f32(?))", (blob,)).fetchone()[0])
+"""
+    package_dir = str(Path(__file__).parent.parent)
+
+    def subtype_with(value):
+        env = dict(os.environ)
+        env.pop("REMBED_VECTOR_SUBTYPE", None)
+        if value is not None:
+            env["REMBED_VECTOR_SUBTYPE"] = value
+        result = subprocess.run(
+            [sys.executable, "-c", script, package_dir], env=env, capture_output=True, text=True
+        )
+        return result.stdout.strip() if result.returncode == 0 else None
+
+    assert subtype_with(None) == "223"
+    assert subtype_with("0") == "0"
+    assert subtype_with("74") == "74"
+    # The f64 subtype is reserved, so loading fails
+    assert subtype_with("227") is None
+    print("✓ REMBED_VECTOR_SUBTYPE=0 attaches no subtype")
+
+
+def test_big_endian_output():
+    """Test that endian=big blobs hold big-endian floats."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('big', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key', 'base_url', ?, 'endian', 'big'
+        ))
+        """,
+        (f"http://127.0.0.1:{server.server_port}/v1",),
+    )
+    big, little = conn.execute("SELECT rembed('big', 'hello'), rembed('mock', 'hello')").fetchone()
+    batch, meta = conn.execute(
+        "SELECT rembed_batch('big', json_array('hello')), rembed_meta('big', 'hello')"
+    ).fetchone()
+    server.shutdown()
+
+    assert big == struct.pack(">2f", 0.25, 0.5)
+    assert little == struct.pack("<2f", 0.25, 0.5)
+
+    assert base64.b64decode(json.loads(batch)[0]) == big
+    assert base64.b64decode(json.loads(meta)["embedding"]) == big
+    print("✓ endian=big blobs hold big-endian floats")
+    conn.close()
+
+
+def test_f64_precision():
+    """Test that precision=f64 blobs keep float64 values and compare against float32."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('wide', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key', 'base_url', ?, 'precision', 'f64'
+        ))
+        """,
+        (f"http://127.0.0.1:{server.server_port}/v1",),
+    )
+    candidate = base64.b64encode(struct.pack("<2f", 0.5, 1.0)).decode()
+    blob, narrowed, scores = conn.execute(
+        "SELECT rembed('wide', 'hello'), rembed_f64_to_f32(rembed('wide', 'hello')), "
+        "rembed_similarity_json(rembed('wide', 'hello'), json_array(?))",
+        (candidate,),
+    ).fetchone()
+    server.shutdown()
+
+    assert len(blob) == 16
+    assert struct.unpack("<2d", blob) == (0.25, 0.5)
+    assert narrowed == struct.pack("<2f", 0.25, 0.5)
+    assert abs(json.loads(scores)[0] - 1.0) < 1e-6
+    print("✓ precision=f64 blobs hold float64 values and compare across widths")
+    conn.close()
+
+
+def test_debug_request():
+    """Test that rembed_debug_request shows the request body without sending it."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+
+    debug = json.loads(conn.execute("SELECT rembed_debug_request('mock', 'hello')").fetchone()[0])
+    # UTF-8 BLOB text is read as rembed reads it
+    blob_debug = json.loads(
+        conn.execute("SELECT rembed_debug_request('mock', ?, 'query')", ("hello".encode(),)).fetchone()[0]
+    )
+    server.shutdown()
+
+    assert debug["body"]["model"] == "mock"
+    assert debug["body"]["input"] in ("hello", ["hello"])
+    assert debug["path"] == "embeddings"
+    assert blob_debug["body"] == debug["body"]
+    assert "test-key" not in json.dumps(debug)
+    assert requests == []
+    print("✓ rembed_debug_request returns the outbound body")
+    conn.close()
+
+
+def test_estimate_cost():
+    """Test rembed_estimate_cost over a known input set without contacting the provider."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('priced', rembed_client_options(
+            'model', 'openai::text-embedding-3-small', 'batch_size', '2', 'price_per_1k', '0.5'
+        ))
+    """)
+
+    texts = json.dumps(["abcdefgh", "abcdefgh", "a b c d e"])
+    estimate = json.loads(conn.execute("SELECT rembed_estimate_cost('priced', ?)", (texts,)).fetchone()[0])
+    assert estimate["texts"] == 3
+    assert estimate["tokens"] == 9
+    assert estimate["requests"] == 2
+    assert abs(estimate["estimated_cost"] - 0.0045) < 1e-12
+    print("✓ rembed_estimate_cost estimates tokens, requests and cost")
+    conn.close()
+
+
+def test_target_dim():
+    """Test that target_dim pads or truncates every embedding to one width."""
+    server = start_mock_embedding_server([])
+    conn = connect_mock_client(server)
+    base_url = f"http://127.0.0.1:{server.server_port}/v1"
+    for name, target_dim in [("padded", 4), ("truncated", 1)]:
+        conn.execute(
+            """
+            INSERT INTO temp.rembed_clients(name, options)
+            VALUES (?, rembed_client_options(
+                'model', 'mock', 'adapter', 'openai', 'key', 'test-key',
+                'base_url', ?, 'target_dim', ?
+            ))
+            """,
+            (name, base_url, str(target_dim)),
+        )
+
+    # The mock server returns [0.25, 0.5]
+    padded = json.loads(conn.execute("SELECT rembed_json('padded', 'hello')").fetchone()[0])
+    truncated = json.loads(conn.execute("SELECT rembed_json('truncated', 'hello')").fetchone()[0])
+    blob = conn.execute("SELECT rembed('padded', 'hello')").fetchone()[0]
+    server.shutdown()
+
+    assert padded == [0.25, 0.5, 0.0, 0.0]
+    assert len(blob) == 4 * 4
+    # Truncated vectors are renormalized to unit length
+    assert truncated == [1.0]
+    print("✓ target_dim pads and truncates embeddings")
+    conn.close()
+
+
+def test_circuit_breaker():
+    """Test that a failing client fails fast once breaker_threshold is crossed."""
+    requests = []
+    down = threading.Event()
+    down.set()
+    server = start_mock_embedding_server(requests, down)
+    conn = connect_mock_client(server)
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('guarded', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key', 'base_url', ?,
+            'breaker_threshold', '2', 'breaker_cooldown_secs', '1'
+        ))
+        """,
+        (f"http://127.0.0.1:{server.server_port}/v1",),
+    )
+
+    for _ in range(2):
+        try:
+            conn.execute("SELECT rembed('guarded', 'hello')").fetchone()
+            assert False, "expected the down provider to fail"
+        except sqlite3.OperationalError as e:
+            assert "Circuit breaker" not in str(e)
+    try:
+        conn.execute("SELECT rembed('guarded', 'hello')").fetchone()
+        assert False, "expected the open circuit to fail fast"
+    except sqlite3.OperationalError as e:
+        assert "Circuit breaker open" in str(e)
+    assert len(requests) == 2
+
+    # After the cooldown a probe request goes through and closes the circuit
+    down.clear()
+    time.sleep(1.1)
+    assert conn.execute("SELECT rembed('guarded', 'hello')").fetchone()[0] is not None
+    server.shutdown()
+    print("✓ circuit breaker fast-fails and recovers after the cooldown")
+    conn.close()
+
+
+def test_fallback_client():
+    """Test that a failing client falls back to the client named by 'fallback'."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+
+    # Bind and release a port so nothing is listening on it
+    import socket
+    with socket.socket() as sock:
+        sock.bind(("127.0.0.1", 0))
+        down_port = sock.getsockname()[1]
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('primary', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key',
+            'base_url', ?, 'fallback', 'mock'
+        ))
+        """,
+        (f"http://127.0.0.1:{down_port}/v1",),
+    )
+
+    blob = conn.execute("SELECT rembed('primary', 'hello')").fetchone()[0]
+    assert struct.unpack("<2f", blob) == (0.25, 0.5)
+    assert len(requests) == 1
+    served_by = conn.execute("SELECT served_by FROM temp.rembed_clients WHERE name = 'primary'").fetchone()[0]
+    assert served_by == "mock"
+
+    # loop -> primary -> mock -> loop would loop forever
+    conn.execute("SELECT rembed_client_drop('mock')")
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('loop', rembed_client_options('model', 'ollama::nomic-embed-text', 'fallback', 'primary'))
+        """
+    )
+    try:
+        conn.execute(
+            """
+            INSERT INTO temp.rembed_clients(name, options)
+            VALUES ('mock', rembed_client_options('model', 'ollama::nomic-embed-text', 'fallback', 'loop'))
+            """
+        )
+        assert False, "expected the fallback cycle to be rejected"
+    except sqlite3.OperationalError as e:
+        assert "Fallback cycle" in str(e)
+    server.shutdown()
+    print("✓ fallback client serves when the primary fails")
+    conn.close()
+
+
+def test_embed_file_matches_rembed():
+    """Test that rembed_embed_file embeds a file exactly as rembed embeds its contents."""
+    import socket
+    import tempfile
+
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    conn.create_function("readfile", 1, lambda path: Path(path).read_text())
+
+    with socket.socket() as sock:
+        sock.bind(("127.0.0.1", 0))
+        down_port = sock.getsockname()[1]
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('primary', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key',
+            'base_url', ?, 'fallback', 'mock', 'document_prefix', 'passage: '
+        ))
+        """,
+        (f"http://127.0.0.1:{down_port}/v1",),
+    )
+
+    with tempfile.NamedTemporaryFile("w", suffix=".txt", delete=False) as f:
+        f.write("file contents")
+        path = f.name
+    try:
+        from_file, from_text = conn.execute(
+            "SELECT rembed_embed_file('primary', ?, 'document'), rembed('primary', readfile(?), 'document')",
+            (path, path),
+        ).fetchone()
+    finally:
+        os.unlink(path)
+    server.shutdown()
+
+    # Both were served by the fallback client, with the document prefix
+    assert from_file == from_text
+    inputs = [json.loads(request)["input"] for request in requests]
+    assert inputs[0] == inputs[1]
+    assert inputs[0] in ("passage: file contents", ["passage: file contents"])
+    print("✓ rembed_embed_file prepares input and falls back like rembed")
+    conn.close()
+
+
+def test_duplicate_client_name():
+    """Test that registering a name twice errors unless REMBED_REPLACE_CLIENTS is set."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    insert = "INSERT INTO temp.rembed_clients(name, options) VALUES ('dup', ?)"
+
+    def dup_model():
+        clients = json.loads(conn.execute("SELECT rembed_clients_export()").fetchone()[0])
+        return next(client["model"] for client in clients if client["name"] == "dup")
+
+    conn.execute(insert, ("ollama::nomic-embed-text",))
+    try:
+        conn.execute(insert, ("ollama::mxbai-embed-large",))
+        assert False, "expected the duplicate name to be rejected"
+    except sqlite3.OperationalError as e:
+        assert "already registered" in str(e)
+    model = dup_model()
+    assert "nomic-embed-text" in model
+
+    os.environ["REMBED_REPLACE_CLIENTS"] = "1"
+    try:
+        conn.execute(insert, ("ollama::mxbai-embed-large",))
+    finally:
+        del os.environ["REMBED_REPLACE_CLIENTS"]
+    model = dup_model()
+    assert "mxbai-embed-large" in model
+    print("✓ duplicate client names are rejected unless replacing is enabled")
+    conn.close()
+
+
+def test_client_provider_column():
+    """Test that rembed_clients.provider reports each client's provider."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    for name, options in [
+        ("local", "ollama::nomic-embed-text"),
+        ("cloud", "text-embedding-3-small"),
+        ("google", "gemini::text-embedding-004"),
+    ]:
+        conn.execute("INSERT INTO temp.rembed_clients(name, options) VALUES (?, ?)", (name, options))
+
+    providers = dict(conn.execute("SELECT name, provider FROM temp.rembed_clients").fetchall())
+    assert providers == {
+        "local": "ollama",
+        "cloud": "openai",
+        "google": "gemini",
+        "ollama-multimodal": "ollama",
+    }
+    print("✓ rembed_clients.provider reports each client's provider")
+    conn.close()
+
+
+def test_client_seq_column():
+    """Test that rembed_clients.seq follows registration order."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    for name in ["zeta", "alpha", "mid"]:
+        conn.execute("INSERT INTO temp.rembed_clients(name, options) VALUES (?, 'ollama::nomic-embed-text')", (name,))
+    names = [row[0] for row in conn.execute("SELECT name FROM temp.rembed_clients ORDER BY seq")]
+    assert names == ["ollama-multimodal", "zeta", "alpha", "mid"]
+
+    # A dropped and re-registered client moves to the end
+    conn.execute("SELECT rembed_client_drop('zeta')")
+    conn.execute("INSERT INTO temp.rembed_clients(name, options) VALUES ('zeta', 'ollama::nomic-embed-text')")
+    names = [row[0] for row in conn.execute("SELECT name FROM temp.rembed_clients ORDER BY seq")]
+    assert names == ["ollama-multimodal", "alpha", "mid", "zeta"]
+    print("✓ rembed_clients.seq follows registration order")
+    conn.close()
+
+
+def test_trace():
+    """Test that rembed_trace lists recent calls in order without their text."""
+    import socket
+
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    with socket.socket() as sock:
+        sock.bind(("127.0.0.1", 0))
+        down_port = sock.getsockname()[1]
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('down', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key', 'base_url', ?
+        ))
+        """,
+        (f"http://127.0.0.1:{down_port}/v1",),
+    )
+    conn.execute("SELECT rembed('mock', 'hi')").fetchone()
+    try:
+        conn.execute("SELECT rembed('down', 'hello')").fetchone()
+        assert False, "expected the unreachable client to fail"
+    except sqlite3.OperationalError:
+        pass
+    conn.execute("SELECT rembed('mock', 'hey there')").fetchone()
+
+    rows = conn.execute(
+        "SELECT client, operation, input_chars, ok, error IS NOT NULL, input_hash FROM rembed_trace"
+    ).fetchall()
+    server.shutdown()
+    assert [row[:5] for row in rows] == [
+        ("mock", "embed", 2, 1, 0),
+        ("down", "embed", 5, 0, 1),
+        ("mock", "embed", 9, 1, 0),
+    ]
+    assert all(len(row[5]) == 16 for row in rows)
+    print("✓ rembed_trace lists recent calls in order")
+    conn.close()
+
+
+def test_health():
+    """Test that rembed_health reports reachable and unreachable clients."""
+    import socket
+
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+    with socket.socket() as sock:
+        sock.bind(("127.0.0.1", 0))
+        down_port = sock.getsockname()[1]
+    conn.execute(
+        """
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('down', rembed_client_options(
+            'model', 'mock', 'adapter', 'openai', 'key', 'test-key', 'base_url', ?
+        ))
+        """,
+        (f"http://127.0.0.1:{down_port}/v1",),
+    )
+    vision_requests = []
+    vision_server = start_mock_vision_server(vision_requests)
+    connect_mock_vision_client(vision_server, conn)
+    # Registering clients sends nothing; only querying rembed_health does
+    assert requests == [] and vision_requests == []
+
+    rows = conn.execute("SELECT name, ok, latency_ms, error FROM rembed_health(2000)").fetchall()
+    server.shutdown()
+    vision_server.shutdown()
+    # The default ollama-multimodal client is listed too, reachable or not
+    assert [row[0] for row in rows] == ["down", "mock", "ollama-multimodal", "vision"]
+    assert [row[1] for row in rows if row[0] != "ollama-multimodal"] == [0, 1, 1]
+    assert rows[0][3] and rows[1][3] is None and rows[3][3] is None
+    assert all(row[2] >= 0 for row in rows)
+    assert len(requests) == 1
+    # Multimodal clients are pinged through their embedding model only
+    assert len(vision_requests) == 1 and "messages" not in vision_requests[0]
+    assert "nomic-embed-text" in vision_requests[0]["model"]
+    print("✓ rembed_health pings every client")
+    conn.close()
+
+
+def test_client_last_error():
+    """Test that a failed embedding is surfaced in rembed_clients.last_error."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    # Without a local Ollama server the embedding request fails
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('down', 'ollama::nomic-embed-text')
+    """)
+    last_error = conn.execute(
+        "SELECT last_error FROM temp.rembed_clients WHERE name = 'down'"
+    ).fetchone()[0]
+    assert last_error is None
+
+    try:
+        conn.execute("SELECT rembed('down', 'hello')").fetchone()
+    except sqlite3.OperationalError:
+        pass
+    else:
+        return  # a local Ollama answered; nothing to assert
+
+    last_error = json.loads(
+        conn.execute("SELECT last_error FROM temp.rembed_clients WHERE name = 'down'").fetchone()[0]
+    )
+    assert "Embedding failed" in last_error["message"]
+    assert last_error["timestamp"] > 0
+    print("✓ last_error reports the most recent failure")
+    conn.close()
+
+
+def test_client_options_not_leaked():
+    """Test that unconsumed rembed_client_options values are freed."""
+    if not sys.platform.startswith("linux"):
+        return  # RSS is read from /proc
+
+    def rss_bytes():
+        with open("/proc/self/statm") as f:
+            return int(f.read().split()[1]) * os.sysconf("SC_PAGE_SIZE")
+
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    query = "SELECT rembed_client_options('format', 'ollama', 'model', 'nomic-embed-text')"
+    for _ in range(500):
+        conn.execute(query).fetchone()
+    before = rss_bytes()
+    for _ in range(5000):
+        conn.execute(query).fetchone()
+    growth = rss_bytes() - before
+    # Each leaked client would keep its HTTP client alive; 5000 of them is far above this
+    assert growth < 16 * 1024 * 1024, f"RSS grew by {growth} bytes"
+    print(f"✓ rembed_client_options values are freed (RSS growth {growth // 1024} KiB)")
+    conn.close()
+
+
+def test_client_drop():
+    """Test that rembed_client_drop unregisters a client."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('temp', 'ollama::nomic-embed-text')
+    """)
+    assert conn.execute("SELECT rembed_client_drop('temp')").fetchone()[0] == 1
+    assert conn.execute("SELECT rembed_client_drop('temp')").fetchone()[0] == 0
+    assert conn.execute("SELECT rembed_client_drop(NULL)").fetchone()[0] == 0
+
+    try:
+        conn.execute("SELECT rembed('temp', 'hello')").fetchone()
+        assert False, "rembed should fail for a dropped client"
+    except sqlite3.OperationalError as e:
+        assert "not registered" in str(e)
+    print("✓ rembed_client_drop removes clients")
+    conn.close()
+
+
+def test_warm():
+    """Test that rembed_warm prepares a client without contacting its provider."""
+    requests = []
+    server = start_mock_embedding_server(requests)
+    conn = connect_mock_client(server)
+
+    assert conn.execute("SELECT rembed_warm('mock')").fetchone()[0] == 1
+    assert requests == []
+    row = conn.execute("SELECT last_error, served_by FROM temp.rembed_clients WHERE name = 'mock'").fetchone()
+    assert row == (None, None)
+
+    try:
+        conn.execute("SELECT rembed_warm('missing')").fetchone()
+        assert False, "rembed_warm should fail for an unknown client"
+    except sqlite3.OperationalError as e:
+        assert "not registered" in str(e)
+
+    assert conn.execute("SELECT rembed('mock', 'hello')").fetchone()[0] is not None
+    server.shutdown()
+    print("✓ rembed_warm prepares a client")
+    conn.close()
+
+
+def test_shutdown_and_reload():
+    """Test that rembed_shutdown clears clients and a fresh load works."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+
+    conn.execute("""
+        INSERT INTO temp.rembed_clients(name, options)
+        VALUES ('temp', 'ollama::nomic-embed-text')
+    """)
+    # 'temp' plus the default ollama-multimodal client
+    assert conn.execute("SELECT rembed_shutdown()").fetchone()[0] == 2
+    assert conn.execute("SELECT count(*) FROM temp.rembed_clients").fetchone()[0] == 0
+    conn.close()
+
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+    names = [row[0] for row in conn.execute("SELECT name FROM temp.rembed_clients")]
+    assert names == ["ollama-multimodal"]
+    print("✓ rembed_shutdown clears state and the extension reloads cleanly")
+    conn.close()
+
+
+def start_mock_vision_server(requests=None):
+    """Serve chat and embedding responses for both Ollama's native and OpenAI-style routes.
+
+    The description of an image is the size of the chat request that carried
+    it, and its embedding is [that number], so larger images embed larger.
+    Request payloads are appended to requests when given.
+    """
+
+    class VisionHandler(BaseHTTPRequestHandler):
+        def do_POST(self):
+            request = self.rfile.read(int(self.headers["Content-Length"]))
+            payload = json.loads(request)
+            if requests is not None:
+                requests.append(payload)
+            if "messages" in payload:
+                message = {"role": "assistant", "content": str(len(request))}
+                response = {
+                    "model": payload["model"],
+                    "message": message,
+                    "done": True,
+                    "choices": [{"index": 0, "message": message, "finish_reason": "stop"}],
+                }
+            else:
+                text = payload["input"] if isinstance(payload["input"], str) else payload["input"][0]
+                # Anything but a description (a health ping, say) embeds as [0.0]
+                vector = [float(text) if text.isdigit() else 0.0]
+                response = {
+                    "model": payload["model"],
+                    "embeddings": [vector],
+                    "object": "list",
+                    "data": [{"object": "embedding", "index": 0, "embedding": vector}],
+                }
+            body = json.dumps(response).encode()
+            self.send_response(200)
+            self.send_header("Content-Type", "application/json")
+            self.send_header("Content-Length", str(len(body)))
+            self.end_headers()
+            self.wfile.write(body)
+
+        def log_message(self, *args):
+            pass
+
+    server = ThreadingHTTPServer(("127.0.0.1", 0), VisionHandler)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+    return server
+
+def connect_mock_vision_client(server, conn=None, **options):
+    """Register a 'vision' Ollama client pointed at a mock vision server.
+
+    Opens a new connection unless one is given; options are added to the
+    client's rembed_client_options.
+    """
+    if conn is None:
+        conn = sqlite3.connect(":memory:")
+        conn.enable_load_extension(True)
+        sqlite_rembed.load(conn)
+        conn.enable_load_extension(False)
+    options = {"format": "ollama", "model": "llava", "embedding_model": "ollama::nomic-embed-text", **options}
+    args = [str(value) for option in options.items() for value in option]
+    # Ollama clients built while OLLAMA_HOST is set talk to the mock server
+    os.environ["OLLAMA_HOST"] = f"127.0.0.1:{server.server_port}"
+    try:
+        conn.execute(
+            f"""
+            INSERT INTO temp.rembed_clients(name, options)
+            VALUES ('vision', rembed_client_options({", ".join("?" * len(args))}))
+            """,
+            args,
+        )
+    finally:
+        del os.environ["OLLAMA_HOST"]
+    return conn
+
+
+def test_images_each():
+    """Test that rembed_images_each keeps ids with their embeddings and reports bad images as rows."""
+    server = start_mock_vision_server()
+    conn = connect_mock_vision_client(server)
+
+    png = b"\x89PNG\r\n\x1a\n"
+    entries = [
+        {"id": 1, "base64_image": base64.b64encode(png + b"\0" * 10).decode()},
+        {"id": "two", "base64_image": base64.b64encode(b"not an image").decode()},
+        {"id": 3, "base64_image": base64.b64encode(png + b"\0" * 300).decode()},
+        {"id": 4, "base64_image": "***"},
+        {"id": 5, "base64_image": base64.b64encode(png + b"\0" * 600).decode()},
+    ]
+    rows = conn.execute(
+        "SELECT id, embedding, error FROM rembed_images_each('vision', ?)", (json.dumps(entries),)
+    ).fetchall()
+    server.shutdown()
+
+    assert [row[0] for row in rows] == [1, "two", 3, 4, 5]
+    assert "not a recognized image" in rows[1][2] and rows[1][1] is None
+    assert "Base64" in rows[3][2] and rows[3][1] is None
+    sizes = [struct.unpack("<f", row[1])[0] for row in rows if row[2] is None]
+    assert len(sizes) == 3
+    assert sizes == sorted(sizes) and len(set(sizes)) == 3
+    print("✓ rembed_images_each returns one row per image")
+    conn.close()
+
+
+def test_image_cache():
+    """Test that cache_size lets a repeated image skip the vision request."""
+    requests = []
+    server = start_mock_vision_server(requests)
+    conn = connect_mock_vision_client(server, cache_size=16)
+
+    png = b"\x89PNG\r\n\x1a\n" + b"\0" * 32
+    first = conn.execute("SELECT rembed_image('vision', ?)", (png,)).fetchone()[0]
+    second = conn.execute("SELECT rembed_image('vision', ?)", (png,)).fetchone()[0]
+    assert first == second
+    assert sum("messages" in payload for payload in requests) == 1
+
+    # The batch, concurrent and per-image table paths share the cache
+    images = json.dumps([base64.b64encode(png).decode()])
+    conn.execute("SELECT rembed_images_batch('vision', ?)", (images,)).fetchone()
+    conn.execute("SELECT rembed_images_concurrent('vision', ?)", (images,)).fetchone()
+    each = json.dumps([{"id": 1, "base64_image": base64.b64encode(png).decode()}])
+    (embedding,) = conn.execute("SELECT embedding FROM rembed_images_each('vision', ?)", (each,)).fetchone()
+    assert embedding == first
+    assert sum("messages" in payload for payload in requests) == 1
+
+    # The prompt is part of the cache key
+    conn.execute("SELECT rembed_image_prompt('vision', ?, 'List the colors')", (png,)).fetchone()
+    conn.execute("SELECT rembed_image_prompt('vision', ?, 'List the colors')", (png,)).fetchone()
+    server.shutdown()
+    assert sum("messages" in payload for payload in requests) == 2
+    print("✓ cache_size reuses embeddings of repeated images")
+    conn.close()
+
+
+def test_vision_sampling_options():
+    """Test that vision_temperature and vision_seed reach the vision request."""
+    requests = []
+    server = start_mock_vision_server(requests)
+    conn = connect_mock_vision_client(server, vision_temperature=0, vision_seed=42)
+
+    png = b"\x89PNG\r\n\x1a\n" + b"\0" * 32
+    conn.execute("SELECT rembed_image('vision', ?)", (png,)).fetchone()
+    server.shutdown()
+
+    chat = next(payload for payload in requests if "messages" in payload)
+    # Ollama's native API nests sampling parameters under "options"
+    sampling = {**chat, **chat.get("options", {})}
+    assert sampling["temperature"] == 0
+    assert sampling["seed"] == 42
+    print("✓ vision_temperature and vision_seed reach the vision request")
+    conn.close()
+
+
+def test_description_cache():
+    """Test that description_cache_size reuses an image's description across embeds."""
+    requests = []
+    server = start_mock_vision_server(requests)
+    conn = connect_mock_vision_client(server, description_cache_size=4)
+
+    png = b"\x89PNG\r\n\x1a\n" + b"\0" * 32
+    first = conn.execute("SELECT rembed_image('vision', ?)", (png,)).fetchone()[0]
+    second = conn.execute("SELECT rembed_image('vision', ?)", (png,)).fetchone()[0]
+    stats = json.loads(conn.execute("SELECT rembed_description_cache_stats('vision')").fetchone()[0])
+    server.shutdown()
+
+    assert first == second
+    assert sum("messages" in payload for payload in requests) == 1
+    assert stats == {"hits": 1, "misses": 1, "entries": 1}
+    print("✓ description_cache_size reuses the description of a repeated image")
+    conn.close()
+
+
+def test_split():
+    """Test that rembed_split yields overlapping character windows without any client."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    rows = conn.execute("SELECT chunk_index, chunk_text FROM rembed_split('abcdefghij', 4, 1)").fetchall()
+    assert rows == [(0, "abcd"), (1, "defg"), (2, "ghij")]
+
+    text = "The quick brown fox jumps over the lazy dog"
+    chunks = [row[0] for row in conn.execute("SELECT chunk_text FROM rembed_split(?, 5)", (text,))]
+    assert "".join(chunks) == text and len(chunks) == 9
+
+    assert conn.execute("SELECT chunk_text FROM rembed_split('short', 100, 10)").fetchall() == [("short",)]
+    assert conn.execute("SELECT count(*) FROM rembed_split('', 4, 1)").fetchone()[0] == 0
+    for args in [("abc", 0, 0), ("abc", 4, 4), ("abc", 4, -1)]:
+        try:
+            conn.execute("SELECT * FROM rembed_split(?, ?, ?)", args).fetchall()
+            assert False, f"expected {args} to be rejected"
+        except sqlite3.OperationalError:
+            pass
+    print("✓ rembed_split chunks text with overlap")
+    conn.close()
+
+
 def test_multimodal_client():
     """Test the default multimodal client."""
     conn = sqlite3.connect(":memory:")
@@ -82,6 +1188,49 @@ def test_multimodal_client():
         # Expected to fail with actual embedding generation
         print(f"✓ rembed_image function exists (failed as expected: {str(e)[:50]}...)")
 
+    # Non-image MIME types are rejected before any request is made
+    try:
+        conn.execute("SELECT rembed_image_typed('ollama-multimodal', X'00', 'text/plain')")
+        assert False, "rembed_image_typed should reject non-image MIME types"
+    except sqlite3.OperationalError as e:
+        assert "MIME type" in str(e)
+    print("✓ rembed_image_typed validates the MIME type")
+
+    # Data URIs must carry an image MIME type and a base64 payload
+    for uri in ["iVBORw0KGgo=", "data:image/png,iVBORw0KGgo=", "data:text/plain;base64,aGk="]:
+        try:
+            conn.execute("SELECT rembed_image_datauri('ollama-multimodal', ?)", (uri,))
+            assert False, f"rembed_image_datauri should reject {uri!r}"
+        except sqlite3.OperationalError as e:
+            assert "data URI" in str(e) or "MIME type" in str(e)
+    print("✓ rembed_image_datauri validates the data URI")
+
+    # Blobs that aren't a recognized image format never reach the vision model
+    try:
+        conn.execute("SELECT rembed_image('ollama-multimodal', CAST('not an image' AS BLOB))")
+        assert False, "rembed_image should reject non-image blobs"
+    except sqlite3.OperationalError as e:
+        assert "not a recognized image" in str(e)
+    print("✓ rembed_image rejects blobs that are not images")
+
+    # Combined embeddings cap the number of images per vision request
+    too_many = json.dumps(["AA=="] * 9)
+    try:
+        conn.execute("SELECT rembed_images_combined('ollama-multimodal', ?)", (too_many,))
+        assert False, "rembed_images_combined should reject more than 8 images"
+    except sqlite3.OperationalError as e:
+        assert "At most 8 images" in str(e)
+
+    pair = json.dumps(["AA==", "AQ=="])
+    try:
+        embedding = conn.execute(
+            "SELECT rembed_images_combined('ollama-multimodal', ?)", (pair,)
+        ).fetchone()[0]
+        assert isinstance(embedding, bytes) and len(embedding) % 4 == 0
+        print("✓ rembed_images_combined returns a single embedding")
+    except sqlite3.OperationalError as e:
+        print(f"✓ rembed_images_combined function exists (failed as expected: {str(e)[:50]}...)")
+
     conn.close()
 
 
@@ -129,6 +1278,95 @@ def test_helper_functions():
     conn.close()
 
 
+def test_blob_inspection():
+    """Test rembed_blob_dimensions and rembed_blob_is_normalized."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    unit = struct.pack("<3f", 0.6, 0.8, 0.0)
+    scaled = struct.pack("<2f", 3.0, 4.0)
+    assert conn.execute("SELECT rembed_blob_dimensions(?)", (unit,)).fetchone()[0] == 3
+    assert conn.execute("SELECT rembed_blob_is_normalized(?)", (unit,)).fetchone()[0] == 1
+    assert conn.execute("SELECT rembed_blob_is_normalized(?)", (scaled,)).fetchone()[0] == 0
+
+    for function in ("rembed_blob_dimensions", "rembed_blob_is_normalized"):
+        try:
+            conn.execute(f"SELECT {function}(?)", (b"\x00" * 6,)).fetchone()
+            assert False, f"{function} should reject misaligned blobs"
+        except sqlite3.OperationalError as e:
+            assert "not a multiple of 4" in str(e)
+    print("✓ blob inspection helpers work")
+    conn.close()
+
+
+def test_pool():
+    """Test rembed_pool over a small known set of vectors."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    vectors = json.dumps([
+        base64.b64encode(struct.pack("<2f", *v)).decode() for v in ([1.0, -2.0], [3.0, 4.0])
+    ])
+    for method, expected in (("mean", (2.0, 1.0)), ("max", (3.0, 4.0)), ("sum", (4.0, 2.0))):
+        blob = conn.execute("SELECT rembed_pool(?, ?)", (vectors, method)).fetchone()[0]
+        assert struct.unpack("<2f", blob) == expected, method
+
+    mismatched = json.dumps([
+        base64.b64encode(struct.pack("<2f", 1.0, 2.0)).decode(),
+        base64.b64encode(struct.pack("<3f", 1.0, 2.0, 3.0)).decode(),
+    ])
+    try:
+        conn.execute("SELECT rembed_pool(?, 'mean')", (mismatched,)).fetchone()
+        assert False, "rembed_pool should reject mismatched dimensions"
+    except sqlite3.OperationalError as e:
+        assert "Dimension mismatch" in str(e)
+    print("✓ rembed_pool combines vectors")
+    conn.close()
+
+
+def test_centroid():
+    """Test the rembed_centroid aggregates over grouped vectors."""
+    conn = sqlite3.connect(":memory:")
+    conn.enable_load_extension(True)
+    sqlite_rembed.load(conn)
+    conn.enable_load_extension(False)
+
+    conn.execute("CREATE TABLE points(cluster TEXT, vec BLOB)")
+    for cluster, v in (("a", [2.0, 0.0]), ("a", [0.0, 2.0]), ("b", [3.0, 4.0]), ("b", [3.0, 4.0])):
+        conn.execute("INSERT INTO points VALUES (?, ?)", (cluster, struct.pack("<2f", *v)))
+
+    rows = conn.execute("""
+        SELECT cluster, rembed_centroid(vec), rembed_centroid_raw(vec), count(*)
+        FROM points GROUP BY cluster ORDER BY cluster
+    """).fetchall()
+    (_, a_centroid, a_raw, a_count), (_, b_centroid, b_raw, b_count) = rows
+    assert struct.unpack("<2f", a_raw) == (1.0, 1.0)
+    assert struct.unpack("<2f", b_raw) == (3.0, 4.0)
+    assert (a_count, b_count) == (2, 2)
+    b = struct.unpack("<2f", b_centroid)
+    assert abs(b[0] - 0.6) < 1e-6 and abs(b[1] - 0.8) < 1e-6
+    for centroid in (a_centroid, b_centroid):
+        assert conn.execute("SELECT rembed_blob_is_normalized(?)", (centroid,)).fetchone()[0] == 1
+
+    # An empty group has no centroid
+    assert conn.execute("SELECT rembed_centroid(vec) FROM points WHERE 0").fetchone()[0] is None
+
+    try:
+        conn.execute(
+            "SELECT rembed_centroid(vec) FROM (SELECT ? AS vec UNION ALL SELECT ?)",
+            (struct.pack("<2f", 1.0, 2.0), struct.pack("<3f", 1.0, 2.0, 3.0)),
+        ).fetchone()
+        assert False, "rembed_centroid should reject mismatched dimensions"
+    except sqlite3.OperationalError as e:
+        assert "Dimension mismatch" in str(e)
+    print("✓ rembed_centroid averages grouped vectors")
+    conn.close()
+
+
 def test_package_version():
     """Test that package version is accessible."""
     version = sqlite_rembed.version()
@@ -150,9 +1388,47 @@ if __name__ == "__main__":
         test_load_extension()
         test_debug_info()
         test_client_registration()
+        test_client_name_lookup()
+        test_client_timeout_columns()
+        test_default_client()
+        test_role_prefixes()
+        test_model_aliases()
+        test_nondeterministic_rembed()
+        test_rembed_json()
+        test_blob_text_input()
+        test_with_usage()
+        test_meta()
+        test_prefixed_output()
+        test_vector_subtype_env()
+        test_big_endian_output()
+        test_f64_precision()
+        test_debug_request()
+        test_estimate_cost()
+        test_target_dim()
+        test_circuit_breaker()
+        test_fallback_client()
+        test_embed_file_matches_rembed()
+        test_duplicate_client_name()
+        test_client_provider_column()
+        test_client_seq_column()
+        test_health()
+        test_trace()
+        test_client_last_error()
+        test_client_options_not_leaked()
+        test_client_drop()
+        test_warm()
+        test_shutdown_and_reload()
+        test_split()
         test_multimodal_client()
+        test_images_each()
+        test_image_cache()
+        test_vision_sampling_options()
+        test_description_cache()
         test_batch_function()
         test_helper_functions()
+        test_blob_inspection()
+        test_pool()
+        test_centroid()
         test_package_version()
         test_load_ext_path()
 
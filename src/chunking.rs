@@ -0,0 +1,68 @@
+/// Character-window text chunking shared by the document and split functions
+
+/// Default chunk width, in characters
+pub const DEFAULT_CHUNK_SIZE: usize = 2000;
+/// Default number of characters shared by consecutive chunks
+pub const DEFAULT_CHUNK_OVERLAP: usize = 200;
+
+/// Split text into windows of `chunk_size` characters, each starting
+/// `chunk_size - overlap` characters after the previous one. The final chunk
+/// holds whatever remains. Callers must ensure `overlap < chunk_size`.
+pub fn chunk_text(text: &str, chunk_size: usize, overlap: usize) -> Vec<&str> {
+    debug_assert!(chunk_size > 0 && overlap < chunk_size);
+
+    // Byte offsets of every char boundary, so slicing never splits a character
+    let boundaries: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect();
+    let char_count = boundaries.len() - 1;
+    let step = chunk_size - overlap;
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < char_count {
+        let end = (start + chunk_size).min(char_count);
+        chunks.push(&text[boundaries[start]..boundaries[end]]);
+        if end == char_count {
+            break;
+        }
+        start += step;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_text_windows_and_overlap() {
+        assert_eq!(chunk_text("abcdefghij", 4, 1), vec!["abcd", "defg", "ghij"]);
+        assert_eq!(chunk_text("abcdefghij", 4, 0), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(chunk_text("abc", 10, 2), vec!["abc"]);
+        assert!(chunk_text("", 4, 1).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_text_reconstructs_without_overlap() {
+        let text = "The quick brown fox jumps over the lazy dog";
+        for chunk_size in [1, 3, 7, 43, 100] {
+            assert_eq!(chunk_text(text, chunk_size, 0).concat(), text, "{}", chunk_size);
+        }
+        // With overlap, dropping each chunk's shared prefix rebuilds the text
+        let chunks = chunk_text(text, 10, 3);
+        let rebuilt: String = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| if i == 0 { *chunk } else { &chunk[3..] })
+            .collect();
+        assert_eq!(rebuilt, text);
+    }
+
+    #[test]
+    fn test_chunk_text_respects_char_boundaries() {
+        assert_eq!(chunk_text("héllo wörld", 5, 0), vec!["héllo", " wörl", "d"]);
+    }
+}
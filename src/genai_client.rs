@@ -1,211 +1,3249 @@
-use genai::Client as GenAiClient;
+use crate::chunking::{DEFAULT_CHUNK_OVERLAP, DEFAULT_CHUNK_SIZE};
+use crate::multimodal::MultimodalClient;
+use crate::runtime::RUNTIME;
+use crate::telemetry;
+use crate::vector::{self, Endian, F32Rounding, OutputFormat, Precision};
+use genai::embed::{EmbedOptions, EmbedResponse};
+use futures::stream::{self, StreamExt};
+use genai::adapter::AdapterKind;
+use genai::resolver::{Endpoint, ServiceTargetResolver};
+use genai::{Client as GenAiClient, ServiceTarget};
 use once_cell::sync::Lazy;
 use sqlite_loadable::{Error, Result};
-use std::sync::Arc;
-use tokio::runtime::Runtime;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// Global tokio runtime for async operations
-static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
-    Runtime::new().expect("Failed to create tokio runtime")
+/// Per-client settings supplied as extra rembed_client_options keys or JSON fields
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientSettings {
+    /// Wire encoding requested from the provider: "float" or "base64"
+    pub encoding_format: Option<String>,
+    /// Per-attempt request timeout; None leaves genai's default in place
+    pub timeout_secs: Option<u64>,
+    /// Additional attempts after a failed or timed out request
+    pub max_retries: u32,
+    /// Element type of emitted embedding blobs
+    pub precision: Precision,
+    /// Whether emitted embedding blobs start with their dimension count
+    pub output_format: OutputFormat,
+    /// Byte order of the elements of emitted embedding blobs
+    pub endian: Endian,
+    /// How the provider's f64 values are narrowed to f32
+    pub f32_round: F32Rounding,
+    /// Chunk width in characters for rembed_document
+    pub chunk_size: Option<usize>,
+    /// Characters shared by consecutive rembed_document chunks
+    pub chunk_overlap: Option<usize>,
+    /// Maximum texts per embed_batch request; None uses the provider's limit
+    pub batch_size: Option<usize>,
+    /// Sub-batch requests in flight at once; None uses DEFAULT_BATCH_CONCURRENCY
+    pub max_concurrency: Option<usize>,
+    /// Embed repeated texts in a batch once and copy the result to each position
+    pub dedup: bool,
+    /// Whether batches use the provider's batch endpoint or concurrent
+    /// single-text requests
+    pub batch_strategy: BatchStrategy,
+    /// Shape of the JSON returned by rembed_batch
+    pub batch_output: BatchOutput,
+    /// Prepended to inputs embedded with the query role, e.g. "query: " for E5
+    pub query_prefix: Option<String>,
+    /// Prepended to inputs embedded with the document role, e.g. "passage: "
+    pub document_prefix: Option<String>,
+    /// Task instruction prepended to query inputs ahead of query_prefix
+    pub instruction: Option<String>,
+    /// Wrapper for query inputs with a `{text}` placeholder, replacing
+    /// instruction and query_prefix for models that need surrounding tokens
+    pub query_template: Option<String>,
+    /// Wrapper for document inputs with a `{text}` placeholder
+    pub document_template: Option<String>,
+    /// Model embedding inputs with the query role, sharing this client's
+    /// credentials and settings
+    pub query_model: Option<String>,
+    /// Model embedding inputs with the document role
+    pub document_model: Option<String>,
+    /// genai adapter forced for the model regardless of its prefix
+    pub adapter: Option<String>,
+    /// Endpoint base URL replacing the adapter's default, e.g. a self-hosted server
+    pub base_url: Option<String>,
+    /// Width every embedding is truncated or zero-padded to, so models with
+    /// different native dimensions can share one table
+    pub target_dim: Option<usize>,
+    /// Consecutive failures that open the circuit breaker; None disables it
+    pub breaker_threshold: Option<u32>,
+    /// Seconds an open breaker fails fast before letting a probe request through
+    pub breaker_cooldown_secs: Option<u64>,
+    /// Seconds a failed input answers with its error again instead of being
+    /// re-sent; None disables the negative cache
+    pub negative_cache_ttl_secs: Option<u64>,
+    /// Hard limit in milliseconds on how long one call blocks the SQLite
+    /// thread, covering every retry; None blocks until the call finishes
+    pub deadline_ms: Option<u64>,
+    /// Refuse endpoints reached over plaintext http://, other than localhost
+    pub require_tls: bool,
+    /// Provider price per 1000 tokens, used only by cost estimates
+    pub price_per_1k: Option<f64>,
+    /// Registered client that rembed retries with when this one fails
+    pub fallback: Option<String>,
+    /// Which embedding of a single-text response to return; None takes the first
+    pub embedding_index: Option<usize>,
+    /// Rewrites applied to every input before it is embedded or deduplicated
+    pub normalize_text: TextNormalization,
+    /// Request once more when a response holds an all-zero or non-finite vector
+    pub retry_degenerate: bool,
+    /// Fewest dimensions a returned vector may have before it is rejected
+    pub min_dims: Option<usize>,
+    /// Reject models whose names mark them as chat or vision models
+    pub validate_model: bool,
+    /// Send single texts as a one-element array, for servers that only
+    /// accept array inputs
+    pub input_as_array: bool,
+    /// Most estimated tokens per batch request, on top of batch_size
+    pub max_batch_tokens: Option<usize>,
+}
+
+/// Input rewrites selected by the `normalize_text` option, a comma-separated
+/// list of lowercase, collapse_whitespace and trim
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextNormalization {
+    pub lowercase: bool,
+    pub collapse_whitespace: bool,
+    pub trim: bool,
+}
+
+impl TextNormalization {
+    fn is_enabled(&self) -> bool {
+        self.lowercase || self.collapse_whitespace || self.trim
+    }
+
+    /// Text with the selected rewrites applied, borrowing it when none are
+    pub fn apply<'a>(&self, text: &'a str) -> std::borrow::Cow<'a, str> {
+        if !self.is_enabled() {
+            return std::borrow::Cow::Borrowed(text);
+        }
+        let mut text = if self.collapse_whitespace {
+            // Runs of whitespace become one space, keeping a single space at
+            // either end so trim stays independent
+            let mut collapsed = String::with_capacity(text.len());
+            let mut in_whitespace = false;
+            for c in text.chars() {
+                if c.is_whitespace() {
+                    if !in_whitespace {
+                        collapsed.push(' ');
+                    }
+                    in_whitespace = true;
+                } else {
+                    collapsed.push(c);
+                    in_whitespace = false;
+                }
+            }
+            collapsed
+        } else {
+            text.to_string()
+        };
+        if self.trim {
+            text = text.trim().to_string();
+        }
+        if self.lowercase {
+            text = text.to_lowercase();
+        }
+        std::borrow::Cow::Owned(text)
+    }
+}
+
+impl FromStr for TextNormalization {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut normalization = Self::default();
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "lowercase" => normalization.lowercase = true,
+                "collapse_whitespace" => normalization.collapse_whitespace = true,
+                "trim" => normalization.trim = true,
+                other => {
+                    return Err(Error::new_message(format!(
+                        "Invalid normalize_text '{}', expected a comma-separated list of \
+                         'lowercase', 'collapse_whitespace' and 'trim'",
+                        other
+                    )))
+                }
+            }
+        }
+        Ok(normalization)
+    }
+}
+
+impl std::fmt::Display for TextNormalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = [
+            (self.lowercase, "lowercase"),
+            (self.collapse_whitespace, "collapse_whitespace"),
+            (self.trim, "trim"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// Marks where the input goes in query_template and document_template
+const TEMPLATE_PLACEHOLDER: &str = "{text}";
+
+/// Adapters selectable with the `adapter` option
+const ADAPTERS: &[&str] = &["openai", "ollama", "gemini", "cohere", "anthropic"];
+
+/// How embed_batch sends a batch, selected by the `batch_strategy` option
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BatchStrategy {
+    /// The batch endpoint where the provider has one, otherwise concurrency
+    #[default]
+    Auto,
+    /// Sub-batches of batch_size texts to the batch endpoint
+    Native,
+    /// One request per text, max_concurrency at a time
+    Concurrent,
+}
+
+impl BatchStrategy {
+    /// Option value naming this strategy
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchStrategy::Auto => "auto",
+            BatchStrategy::Native => "native",
+            BatchStrategy::Concurrent => "concurrent",
+        }
+    }
+
+    /// Whether batches for this provider go to its batch endpoint
+    pub fn is_native(&self, provider: &str) -> bool {
+        match self {
+            BatchStrategy::Auto => has_batch_endpoint(provider),
+            BatchStrategy::Native => true,
+            BatchStrategy::Concurrent => false,
+        }
+    }
+}
+
+impl FromStr for BatchStrategy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "auto" => Ok(BatchStrategy::Auto),
+            "native" => Ok(BatchStrategy::Native),
+            "concurrent" => Ok(BatchStrategy::Concurrent),
+            other => Err(Error::new_message(format!(
+                "Invalid batch_strategy '{}', expected 'auto', 'native' or 'concurrent'",
+                other
+            ))),
+        }
+    }
+}
+
+/// JSON returned by rembed_batch, selected by the `batch_output` option
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BatchOutput {
+    /// Base64 embeddings (or null) in input order
+    #[default]
+    Array,
+    /// {"index", "embedding"} objects carrying each input's position
+    Objects,
+}
+
+impl BatchOutput {
+    /// Option value naming this output
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchOutput::Array => "array",
+            BatchOutput::Objects => "objects",
+        }
+    }
+}
+
+impl FromStr for BatchOutput {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "array" => Ok(BatchOutput::Array),
+            "objects" => Ok(BatchOutput::Objects),
+            other => Err(Error::new_message(format!(
+                "Invalid batch_output '{}', expected 'array' or 'objects'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Which side of a retrieval pair an input is, selecting its prefix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputRole {
+    Query,
+    Document,
+}
+
+impl FromStr for InputRole {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "query" => Ok(InputRole::Query),
+            "document" => Ok(InputRole::Document),
+            other => Err(Error::new_message(format!(
+                "Invalid role '{}', expected 'query' or 'document'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Parse an optional numeric option, naming the key in the error
+pub(crate) fn parse_option<T: FromStr>(
+    options: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>> {
+    options
+        .get(key)
+        .map(|value| {
+            value.trim().parse::<T>().map_err(|_| {
+                Error::new_message(format!("Invalid value '{}' for option '{}'", value, key))
+            })
+        })
+        .transpose()
+}
+
+impl ClientSettings {
+    /// Build settings from option key/value pairs, ignoring keys handled elsewhere
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self> {
+        let mut settings = Self::default();
+
+        if let Some(encoding_format) = options.get("encoding_format") {
+            match encoding_format.as_str() {
+                "float" | "base64" => settings.encoding_format = Some(encoding_format.clone()),
+                other => {
+                    return Err(Error::new_message(format!(
+                        "Invalid encoding_format '{}', expected 'float' or 'base64'",
+                        other
+                    )))
+                }
+            }
+        }
+
+        settings.timeout_secs = parse_option(options, "timeout_secs")?;
+        if settings.timeout_secs == Some(0) {
+            return Err(Error::new_message("'timeout_secs' must be greater than 0"));
+        }
+        settings.max_retries = parse_option(options, "max_retries")?.unwrap_or(0);
+        if let Some(output_format) = options.get("output_format") {
+            settings.output_format = output_format.parse()?;
+        }
+        if let Some(endian) = options.get("endian") {
+            settings.endian = endian.parse()?;
+        }
+        if let Some(f32_round) = options.get("f32_round") {
+            settings.f32_round = f32_round.parse()?;
+        }
+        if let Some(precision) = options.get("precision") {
+            settings.precision = precision.parse()?;
+        }
+
+        settings.chunk_size = parse_option(options, "chunk_size")?;
+        settings.chunk_overlap = parse_option(options, "chunk_overlap")?;
+        let (chunk_size, chunk_overlap) = settings.chunking();
+        if chunk_size == 0 || chunk_overlap >= chunk_size {
+            return Err(Error::new_message(format!(
+                "'chunk_overlap' ({}) must be smaller than a non-zero 'chunk_size' ({})",
+                chunk_overlap, chunk_size
+            )));
+        }
+
+        settings.batch_size = parse_option(options, "batch_size")?;
+        if settings.batch_size == Some(0) {
+            return Err(Error::new_message("'batch_size' must be greater than 0"));
+        }
+        settings.max_concurrency = parse_option(options, "max_concurrency")?;
+        if settings.max_concurrency == Some(0) {
+            return Err(Error::new_message("'max_concurrency' must be greater than 0"));
+        }
+        settings.dedup = parse_option(options, "dedup")?.unwrap_or(false);
+        if let Some(batch_strategy) = options.get("batch_strategy") {
+            settings.batch_strategy = batch_strategy.parse()?;
+        }
+        if let Some(batch_output) = options.get("batch_output") {
+            settings.batch_output = batch_output.parse()?;
+        }
+        settings.retry_degenerate = parse_option(options, "retry_degenerate")?.unwrap_or(false);
+        settings.min_dims = parse_option(options, "min_dims")?;
+        if settings.min_dims == Some(0) {
+            return Err(Error::new_message("'min_dims' must be greater than 0"));
+        }
+        settings.validate_model = parse_option(options, "validate_model")?.unwrap_or(false);
+        settings.input_as_array = parse_option(options, "input_as_array")?.unwrap_or(false);
+        settings.max_batch_tokens = parse_option(options, "max_batch_tokens")?;
+        if settings.max_batch_tokens == Some(0) {
+            return Err(Error::new_message("'max_batch_tokens' must be greater than 0"));
+        }
+
+        settings.query_prefix = options.get("query_prefix").cloned();
+        settings.document_prefix = options.get("document_prefix").cloned();
+        settings.instruction = options.get("instruction").cloned();
+        for (key, template, replaces) in [
+            ("query_template", &mut settings.query_template, ["query_prefix", "instruction"].as_slice()),
+            ("document_template", &mut settings.document_template, ["document_prefix"].as_slice()),
+        ] {
+            let Some(value) = options.get(key) else { continue };
+            if !value.contains(TEMPLATE_PLACEHOLDER) {
+                return Err(Error::new_message(format!(
+                    "'{}' must contain the {} placeholder",
+                    key, TEMPLATE_PLACEHOLDER
+                )));
+            }
+            if let Some(other) = replaces.iter().find(|other| options.contains_key(**other)) {
+                return Err(Error::new_message(format!("'{}' cannot be combined with '{}'", key, other)));
+            }
+            *template = Some(value.clone());
+        }
+        for (key, model) in [
+            ("query_model", &mut settings.query_model),
+            ("document_model", &mut settings.document_model),
+        ] {
+            let Some(value) = options.get(key) else { continue };
+            if value.trim().is_empty() {
+                return Err(Error::new_message(format!("'{}' must name a model", key)));
+            }
+            *model = Some(value.clone());
+        }
+
+        if let Some(adapter) = options.get("adapter") {
+            if !ADAPTERS.contains(&adapter.as_str()) {
+                return Err(Error::new_message(format!(
+                    "Invalid adapter '{}', expected one of: {}",
+                    adapter,
+                    ADAPTERS.join(", ")
+                )));
+            }
+            settings.adapter = Some(adapter.clone());
+        }
+        if let Some(base_url) = options.get("base_url").or_else(|| options.get("url")) {
+            if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+                return Err(Error::new_message(format!(
+                    "Invalid base_url '{}', expected an http:// or https:// URL",
+                    base_url
+                )));
+            }
+            // genai joins request paths onto the endpoint, which needs a trailing slash
+            settings.base_url = Some(format!("{}/", base_url.trim_end_matches('/')));
+        } else if let Some(host) = options.get("host") {
+            settings.base_url = Some(ollama_base_url(host));
+        }
+
+        settings.target_dim = parse_option(options, "target_dim")?;
+        if settings.target_dim == Some(0) {
+            return Err(Error::new_message("'target_dim' must be greater than 0"));
+        }
+
+        settings.breaker_threshold = parse_option(options, "breaker_threshold")?;
+        if settings.breaker_threshold == Some(0) {
+            return Err(Error::new_message("'breaker_threshold' must be greater than 0"));
+        }
+        settings.breaker_cooldown_secs = parse_option(options, "breaker_cooldown_secs")?;
+        if settings.breaker_cooldown_secs == Some(0) {
+            return Err(Error::new_message("'breaker_cooldown_secs' must be greater than 0"));
+        }
+        settings.negative_cache_ttl_secs = parse_option(options, "negative_cache_ttl_secs")?;
+        if settings.negative_cache_ttl_secs == Some(0) {
+            return Err(Error::new_message("'negative_cache_ttl_secs' must be greater than 0"));
+        }
+
+        settings.deadline_ms = parse_option(options, "deadline_ms")?;
+        if settings.deadline_ms == Some(0) {
+            return Err(Error::new_message("'deadline_ms' must be greater than 0"));
+        }
+
+        settings.price_per_1k = parse_option(options, "price_per_1k")?;
+        if settings.price_per_1k.is_some_and(|price: f64| !price.is_finite() || price < 0.0) {
+            return Err(Error::new_message("'price_per_1k' must be a non-negative number"));
+        }
+
+        settings.embedding_index = parse_option(options, "embedding_index")?;
+        if let Some(normalize_text) = options.get("normalize_text") {
+            settings.normalize_text = normalize_text.parse()?;
+        }
+
+        if let Some(fallback) = options.get("fallback") {
+            if fallback.trim().is_empty() {
+                return Err(Error::new_message("'fallback' must name a registered client"));
+            }
+            settings.fallback = Some(fallback.clone());
+        }
+
+        settings.require_tls = match parse_option(options, "require_tls")? {
+            Some(require_tls) => require_tls,
+            None => require_tls_from_env(),
+        };
+
+        Ok(settings)
+    }
+
+    /// Option key/value pairs that reproduce these settings via from_options,
+    /// listing only values that differ from the defaults
+    pub fn to_options(&self) -> HashMap<String, String> {
+        let mut options = HashMap::new();
+        if let Some(encoding_format) = &self.encoding_format {
+            options.insert("encoding_format".to_string(), encoding_format.clone());
+        }
+        if let Some(timeout_secs) = self.timeout_secs {
+            options.insert("timeout_secs".to_string(), timeout_secs.to_string());
+        }
+        if self.max_retries != 0 {
+            options.insert("max_retries".to_string(), self.max_retries.to_string());
+        }
+        if self.output_format != OutputFormat::default() {
+            options.insert("output_format".to_string(), self.output_format.as_str().to_string());
+        }
+        if self.endian != Endian::default() {
+            options.insert("endian".to_string(), self.endian.as_str().to_string());
+        }
+        if self.f32_round != F32Rounding::default() {
+            options.insert("f32_round".to_string(), self.f32_round.as_str().to_string());
+        }
+        if self.precision != Precision::default() {
+            options.insert("precision".to_string(), self.precision.as_str().to_string());
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            options.insert("chunk_size".to_string(), chunk_size.to_string());
+        }
+        if let Some(chunk_overlap) = self.chunk_overlap {
+            options.insert("chunk_overlap".to_string(), chunk_overlap.to_string());
+        }
+        if let Some(batch_size) = self.batch_size {
+            options.insert("batch_size".to_string(), batch_size.to_string());
+        }
+        if let Some(max_concurrency) = self.max_concurrency {
+            options.insert("max_concurrency".to_string(), max_concurrency.to_string());
+        }
+        if self.dedup {
+            options.insert("dedup".to_string(), "true".to_string());
+        }
+        if self.retry_degenerate {
+            options.insert("retry_degenerate".to_string(), "true".to_string());
+        }
+        if let Some(min_dims) = self.min_dims {
+            options.insert("min_dims".to_string(), min_dims.to_string());
+        }
+        if self.validate_model {
+            options.insert("validate_model".to_string(), "true".to_string());
+        }
+        if self.input_as_array {
+            options.insert("input_as_array".to_string(), "true".to_string());
+        }
+        if let Some(max_batch_tokens) = self.max_batch_tokens {
+            options.insert("max_batch_tokens".to_string(), max_batch_tokens.to_string());
+        }
+        if self.batch_strategy != BatchStrategy::default() {
+            options.insert("batch_strategy".to_string(), self.batch_strategy.as_str().to_string());
+        }
+        if self.batch_output != BatchOutput::default() {
+            options.insert("batch_output".to_string(), self.batch_output.as_str().to_string());
+        }
+        if let Some(target_dim) = self.target_dim {
+            options.insert("target_dim".to_string(), target_dim.to_string());
+        }
+        if let Some(breaker_threshold) = self.breaker_threshold {
+            options.insert("breaker_threshold".to_string(), breaker_threshold.to_string());
+        }
+        if let Some(breaker_cooldown_secs) = self.breaker_cooldown_secs {
+            options.insert("breaker_cooldown_secs".to_string(), breaker_cooldown_secs.to_string());
+        }
+        if let Some(negative_cache_ttl_secs) = self.negative_cache_ttl_secs {
+            options.insert("negative_cache_ttl_secs".to_string(), negative_cache_ttl_secs.to_string());
+        }
+        if let Some(deadline_ms) = self.deadline_ms {
+            options.insert("deadline_ms".to_string(), deadline_ms.to_string());
+        }
+        if self.require_tls {
+            options.insert("require_tls".to_string(), "true".to_string());
+        }
+        if let Some(embedding_index) = self.embedding_index {
+            options.insert("embedding_index".to_string(), embedding_index.to_string());
+        }
+        if self.normalize_text.is_enabled() {
+            options.insert("normalize_text".to_string(), self.normalize_text.to_string());
+        }
+        if let Some(price_per_1k) = self.price_per_1k {
+            options.insert("price_per_1k".to_string(), price_per_1k.to_string());
+        }
+        for (key, value) in [
+            ("query_prefix", &self.query_prefix),
+            ("document_prefix", &self.document_prefix),
+            ("instruction", &self.instruction),
+            ("query_template", &self.query_template),
+            ("document_template", &self.document_template),
+            ("query_model", &self.query_model),
+            ("document_model", &self.document_model),
+            ("adapter", &self.adapter),
+            ("base_url", &self.base_url),
+            ("fallback", &self.fallback),
+        ] {
+            if let Some(value) = value {
+                options.insert(key.to_string(), value.clone());
+            }
+        }
+        options
+    }
+
+    /// Effective (chunk_size, chunk_overlap) for document chunking
+    pub fn chunking(&self) -> (usize, usize) {
+        (
+            self.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE),
+            self.chunk_overlap.unwrap_or(DEFAULT_CHUNK_OVERLAP),
+        )
+    }
+
+    /// Input text with the template, or else the instruction and prefix,
+    /// configured for its role; inputs without a role are sent unchanged
+    pub fn prepare_input(&self, text: &str, role: Option<InputRole>) -> String {
+        let template = match role {
+            Some(InputRole::Query) => self.query_template.as_ref(),
+            Some(InputRole::Document) => self.document_template.as_ref(),
+            None => None,
+        };
+        if let Some(template) = template {
+            return template.replace(TEMPLATE_PLACEHOLDER, text);
+        }
+        let parts: [Option<&String>; 2] = match role {
+            Some(InputRole::Query) => [self.instruction.as_ref(), self.query_prefix.as_ref()],
+            Some(InputRole::Document) => [None, self.document_prefix.as_ref()],
+            None => [None, None],
+        };
+        let mut input: String = parts.into_iter().flatten().map(String::as_str).collect();
+        input.push_str(text);
+        input
+    }
+
+    /// Model configured for a role, or None when the client's own model
+    /// serves it. Once either role model is set, a role without one is an
+    /// error rather than silently embedding with a different model.
+    pub fn role_model(&self, role: Option<InputRole>) -> Result<Option<&str>> {
+        let (model, key) = match role {
+            Some(InputRole::Query) => (&self.query_model, "query_model"),
+            Some(InputRole::Document) => (&self.document_model, "document_model"),
+            None => return Ok(None),
+        };
+        match model {
+            Some(model) => Ok(Some(model)),
+            None if self.query_model.is_some() || self.document_model.is_some() => Err(Error::new_message(
+                format!("Client has no '{}' configured for the {} role", key, key.trim_end_matches("_model")),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Embedding fitted to target_dim, or unchanged when no target is set
+    pub fn fit_dimensions<T: vector::Element>(&self, embedding: Vec<T>) -> Vec<T> {
+        match self.target_dim {
+            Some(target_dim) => vector::fit_dimensions(embedding, target_dim),
+            None => embedding,
+        }
+    }
+
+    /// Await a whole call, giving up with a retryable busy error once
+    /// deadline_ms has passed so the caller can yield instead of blocking
+    async fn within_deadline<T>(&self, call: impl Future<Output = T>) -> Result<T> {
+        match self.deadline_ms {
+            Some(ms) => tokio::time::timeout(Duration::from_millis(ms), call).await.map_err(|_| {
+                Error::new_message(format!(
+                    "Embedding busy: no response within the {}ms deadline; retry later",
+                    ms
+                ))
+            }),
+            None => Ok(call.await),
+        }
+    }
+
+    /// Run a request future, applying the timeout and retry settings
+    async fn run<T, F, Fut>(&self, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let outcome = match self.timeout_secs {
+                Some(secs) => tokio::time::timeout(Duration::from_secs(secs), request())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(Error::new_message(format!(
+                            "Embedding request timed out after {}s",
+                            secs
+                        )))
+                    }),
+                None => request().await,
+            };
+
+            match outcome {
+                Err(_) if attempt < self.max_retries => attempt += 1,
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Run an embedding request like `run`, failing on a degenerate vector in
+    /// the response (with retry_degenerate it is requested once more first)
+    /// or on one shorter than min_dims
+    async fn run_checked<F, Fut>(&self, mut request: F) -> Result<EmbedOutput>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<EmbedOutput>>,
+    {
+        let mut retried = false;
+        loop {
+            let output = self.run(&mut request).await?;
+            let checked = output.vectors.iter().enumerate().try_for_each(|(index, vector)| {
+                vector::check_degenerate(vector)
+                    .map_err(|e| Error::new_message(format!("{} (vector {} of the response)", e, index)))
+            });
+            match checked {
+                Err(_) if self.retry_degenerate && !retried => retried = true,
+                checked => return checked.and_then(|()| self.check_min_dims(&output)).map(|()| output),
+            }
+        }
+    }
+
+    /// Fail if any vector of a response has fewer than min_dims dimensions
+    fn check_min_dims(&self, output: &EmbedOutput) -> Result<()> {
+        let Some(min_dims) = self.min_dims else {
+            return Ok(());
+        };
+        match output.vectors.iter().position(|vector| vector.len() < min_dims) {
+            Some(index) => Err(Error::new_message(format!(
+                "Embedding has {} dimensions, fewer than min_dims {} (vector {} of the response); \
+                 check the client's model",
+                output.vectors[index].len(),
+                min_dims,
+                index
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// genai request options derived from these settings, if any apply
+    fn embed_options(&self) -> Option<EmbedOptions> {
+        let encoding_format = self.encoding_format.as_ref()?;
+        Some(EmbedOptions::default().with_encoding_format(encoding_format.clone()))
+    }
+}
+
+/// Env var that makes require_tls the default for every client
+const REQUIRE_TLS_ENV: &str = "REMBED_REQUIRE_TLS";
+
+fn require_tls_from_env() -> bool {
+    std::env::var(REQUIRE_TLS_ENV)
+        .map(|value| matches!(value.trim(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// Fail if url is not https:// unless it points at the local machine
+fn check_tls(url: &str) -> Result<()> {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    if scheme.eq_ignore_ascii_case("https") || is_loopback_authority(rest) {
+        return Ok(());
+    }
+    Err(Error::new_message(format!(
+        "require_tls is set, but '{}' is not an https:// URL",
+        url
+    )))
+}
+
+/// Whether the host of a URL's authority, after any userinfo, is localhost
+/// or a loopback IP address
+fn is_loopback_authority(rest: &str) -> bool {
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host_port)| host_port);
+    let host = match host_port.strip_prefix('[') {
+        Some(ipv6) => match ipv6.split_once(']') {
+            Some((host, _)) => host,
+            None => return false,
+        },
+        None => host_port.split(':').next().unwrap_or_default(),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Fail for providers with no embedding endpoint, naming what to use instead
+fn check_embedding_provider(model: &str) -> Result<()> {
+    if provider_from_model(model) != "anthropic" {
+        return Ok(());
+    }
+    Err(Error::new_message(format!(
+        "'{}': Anthropic has no embedding endpoint; it recommends Voyage AI. Register \
+         '{{\"model\": \"openai::voyage-3\", \"base_url\": \"https://api.voyageai.com/v1/\", \"key\": \"<VOYAGE_API_KEY>\"}}' instead",
+        model
+    )))
+}
+
+/// Model name fragments of chat and vision models, which have no embeddings
+const NON_EMBEDDING_MODELS: &[&str] = &["llava", "vision", "claude"];
+
+/// Fail if the model's name marks it as a chat or vision model
+fn check_embedding_model(model: &str) -> Result<()> {
+    let name = model.rsplit("::").next().unwrap_or(model).to_lowercase();
+    match NON_EMBEDDING_MODELS.iter().find(|fragment| name.contains(*fragment)) {
+        Some(fragment) => Err(Error::new_message(format!(
+            "'{}' looks like a chat or vision model ('{}'), not an embedding model. To embed \
+             images with it, register a multimodal client by also setting embedding_model",
+            model, fragment
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Env var naming the Ollama server, as read by the Ollama CLI
+const OLLAMA_HOST_ENV: &str = "OLLAMA_HOST";
+/// Port Ollama listens on when a host is given without a scheme or port
+const OLLAMA_DEFAULT_PORT: u16 = 11434;
+
+/// Endpoint base URL for an OLLAMA_HOST-style value ("host", "host:port" or a
+/// full URL). Like Ollama, a bare host gets http and port 11434, while an
+/// explicit scheme keeps its own default port.
+pub fn ollama_base_url(host: &str) -> String {
+    let host = host.trim().trim_end_matches('/');
+    let url = if host.contains("://") {
+        host.to_string()
+    } else if host.contains(':') {
+        format!("http://{}", host)
+    } else {
+        format!("http://{}:{}", host, OLLAMA_DEFAULT_PORT)
+    };
+    format!("{}/", url)
+}
+
+/// Endpoint for a request: an explicit base_url wins, and OLLAMA_HOST applies
+/// only to requests going to the Ollama adapter
+fn resolve_endpoint(base_url: Option<&str>, ollama_host: Option<&str>, is_ollama: bool) -> Option<String> {
+    match (base_url, ollama_host) {
+        (Some(base_url), _) => Some(base_url.to_string()),
+        (None, Some(ollama_host)) if is_ollama => Some(ollama_host.to_string()),
+        _ => None,
+    }
+}
+
+/// Server root of a text-embeddings-inference instance when no url is given
+const TEI_DEFAULT_URL: &str = "http://localhost:8080";
+
+/// OpenAI-compatible base URL of a HuggingFace text-embeddings-inference
+/// server. TEI serves /v1/embeddings in OpenAI format next to its native
+/// /embed route, so it is reached through the openai adapter and accepts
+/// whole batches. The server root, its /v1 base or its /embed route may be
+/// given.
+pub fn tei_base_url(url: Option<&str>) -> String {
+    let url = url.map(str::trim).filter(|url| !url.is_empty()).unwrap_or(TEI_DEFAULT_URL);
+    let root = url.trim_end_matches('/');
+    let root = root
+        .strip_suffix("/embed")
+        .or_else(|| root.strip_suffix("/v1/embeddings"))
+        .or_else(|| root.strip_suffix("/v1"))
+        .unwrap_or(root);
+    format!("{}/v1/", root)
+}
+
+/// Ollama base URL from $OLLAMA_HOST, if set
+fn ollama_host_from_env() -> Option<String> {
+    ollama_host(std::env::var(OLLAMA_HOST_ENV).ok().as_deref())
+}
+
+/// Ollama base URL for an OLLAMA_HOST value, ignoring unset or blank values
+fn ollama_host(value: Option<&str>) -> Option<String> {
+    value.filter(|host| !host.trim().is_empty()).map(ollama_base_url)
+}
+
+/// genai client sending requests to base_url, or to $OLLAMA_HOST for Ollama
+/// models, instead of each adapter's default endpoint
+pub(crate) fn build_genai_client(base_url: Option<String>) -> GenAiClient {
+    let ollama_host = ollama_host_from_env();
+    if base_url.is_none() && ollama_host.is_none() {
+        return GenAiClient::default();
+    }
+
+    let resolver = ServiceTargetResolver::from_resolver_fn(
+        move |target: ServiceTarget| -> std::result::Result<ServiceTarget, genai::resolver::Error> {
+            let is_ollama = target.model.adapter_kind == AdapterKind::Ollama;
+            match resolve_endpoint(base_url.as_deref(), ollama_host.as_deref(), is_ollama) {
+                Some(endpoint) => Ok(ServiceTarget {
+                    endpoint: Endpoint::from_owned(endpoint),
+                    ..target
+                }),
+                None => Ok(target),
+            }
+        },
+    );
+    GenAiClient::builder().with_service_target_resolver(resolver).build()
+}
+
+/// Placeholder shown instead of credentials in debug requests
+const REDACTED: &str = "[REDACTED]";
+
+/// JSON description of the embedding request genai would build for one
+/// already-prepared text: method, path below the endpoint, headers with
+/// credentials redacted, and body. Built from the client's settings; nothing
+/// is sent.
+fn describe_request(model: &str, text: &str, settings: &ClientSettings) -> serde_json::Value {
+    let provider = provider_from_model(model);
+    let name = model.split_once("::").map_or(model, |(_, name)| name);
+    let input = if settings.input_as_array { serde_json::json!([text]) } else { serde_json::json!(text) };
+
+    let (path, auth_header, mut body) = match provider {
+        "ollama" => ("api/embed".to_string(), None, serde_json::json!({ "model": name, "input": input })),
+        "cohere" => (
+            "embed".to_string(),
+            Some("authorization"),
+            serde_json::json!({ "model": name, "texts": [text], "embedding_types": ["float"] }),
+        ),
+        "gemini" | "google" => {
+            let content = serde_json::json!({ "model": format!("models/{}", name), "content": { "parts": [{ "text": text }] } });
+            if settings.input_as_array {
+                (format!("models/{}:batchEmbedContents", name), Some("x-goog-api-key"), serde_json::json!({ "requests": [content] }))
+            } else {
+                (format!("models/{}:embedContent", name), Some("x-goog-api-key"), content)
+            }
+        }
+        _ => ("embeddings".to_string(), Some("authorization"), serde_json::json!({ "model": name, "input": input })),
+    };
+    if let (Some(encoding_format), Some(body)) = (&settings.encoding_format, body.as_object_mut()) {
+        body.insert("encoding_format".to_string(), serde_json::json!(encoding_format));
+    }
+
+    let mut headers = serde_json::Map::new();
+    headers.insert("content-type".to_string(), serde_json::json!("application/json"));
+    match auth_header {
+        Some("authorization") => headers.insert("authorization".to_string(), serde_json::json!(format!("Bearer {}", REDACTED))),
+        Some(header) => headers.insert(header.to_string(), serde_json::json!(REDACTED)),
+        None => None,
+    };
+
+    serde_json::json!({
+        "model": model,
+        "method": "POST",
+        "endpoint": settings.base_url,
+        "path": path,
+        "headers": headers,
+        "body": body,
+    })
+}
+
+/// Rough token count for cost estimates: about four characters per token,
+/// and never fewer tokens than words
+pub fn estimate_tokens(text: &str) -> usize {
+    let chars = text.chars().count();
+    let words = text.split_whitespace().count();
+    ((chars + 3) / 4).max(words)
+}
+
+/// Default per-request item limit for a provider's batch embedding endpoint
+pub fn default_batch_size(provider: &str) -> usize {
+    match provider {
+        "openai" => 2048,
+        "cohere" => 96,
+        "gemini" | "google" => 100,
+        "voyage" => 128,
+        "mistral" => 512,
+        _ => 100,
+    }
+}
+
+/// Whether a provider embeds a list of texts in one request; batches for
+/// the others are sent as concurrent single-text requests by default
+pub fn has_batch_endpoint(provider: &str) -> bool {
+    !matches!(provider, "ollama")
+}
+
+/// Sub-batch requests in flight at once when max_concurrency is not set
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+/// Embeddings of a batch whose sub-batches succeed or fail independently
+pub struct PartialBatch {
+    /// One entry per input text, None where its sub-batch failed after retries
+    pub embeddings: Vec<Option<Vec<f32>>>,
+    /// Error of the first failed sub-batch, if any failed
+    pub first_error: Option<Error>,
+}
+
+impl PartialBatch {
+    /// Input positions whose embeddings are missing
+    pub fn failed_indices(&self) -> Vec<usize> {
+        self.embeddings
+            .iter()
+            .enumerate()
+            .filter(|(_, embedding)| embedding.is_none())
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Every embedding, or the first error if any sub-batch failed
+    pub fn into_result(self) -> Result<Vec<Vec<f32>>> {
+        match self.first_error {
+            Some(e) => Err(e),
+            None => Ok(self.embeddings.into_iter().flatten().collect()),
+        }
+    }
+
+    /// A batch of len texts that all failed with error
+    fn failed(len: usize, error: Error) -> PartialBatch {
+        PartialBatch {
+            embeddings: vec![None; len],
+            first_error: Some(error),
+        }
+    }
+
+    /// Expand embeddings of deduplicated texts back to the original positions
+    fn fan_out(self, positions: &[usize]) -> PartialBatch {
+        PartialBatch {
+            embeddings: positions.iter().map(|&idx| self.embeddings[idx].clone()).collect(),
+            first_error: self.first_error,
+        }
+    }
+}
+
+/// Unique texts in first-seen order, and the index of each input among them
+fn dedup_texts(texts: Vec<String>) -> (Vec<String>, Vec<usize>) {
+    let mut unique = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let positions = texts
+        .into_iter()
+        .map(|text| {
+            *seen.entry(text).or_insert_with_key(|text| {
+                unique.push(text.clone());
+                unique.len() - 1
+            })
+        })
+        .collect();
+    (unique, positions)
+}
+
+/// Reject a batch response whose embeddings cannot be matched one-to-one
+/// with its inputs, so rows never receive another input's vector
+pub(crate) fn check_embedding_count(embeddings: Vec<Vec<f32>>, expected: usize) -> Result<Vec<Vec<f32>>> {
+    if embeddings.len() == expected {
+        Ok(embeddings)
+    } else {
+        Err(Error::new_message(format!(
+            "Batch embedding returned {} embeddings for {} inputs",
+            embeddings.len(),
+            expected
+        )))
+    }
+}
+
+/// Split texts into consecutive sub-batches of at most batch_size items and,
+/// with max_tokens, at most that many estimated tokens. A text over the
+/// token budget by itself is sent alone.
+fn split_batches(texts: &[String], batch_size: usize, max_tokens: Option<usize>) -> Vec<&[String]> {
+    let mut batches = Vec::new();
+    let (mut start, mut tokens) = (0, 0);
+    for (index, text) in texts.iter().enumerate() {
+        let text_tokens = estimate_tokens(text);
+        let over_budget = max_tokens.is_some_and(|max_tokens| tokens + text_tokens > max_tokens);
+        if index > start && (index - start >= batch_size.max(1) || over_budget) {
+            batches.push(&texts[start..index]);
+            (start, tokens) = (index, 0);
+        }
+        tokens += text_tokens;
+    }
+    if start < texts.len() {
+        batches.push(&texts[start..]);
+    }
+    batches
+}
+
+/// Send texts as sub-batches of at most batch_size items (and max_tokens
+/// estimated tokens), with up to concurrency requests in flight, keeping the
+/// embeddings in input order
+async fn embed_in_batches<F, Fut>(
+    texts: &[String],
+    batch_size: usize,
+    max_tokens: Option<usize>,
+    concurrency: usize,
+    mut request: F,
+) -> PartialBatch
+where
+    F: FnMut(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<Vec<Vec<f32>>>>,
+{
+    let results: Vec<(usize, Result<Vec<Vec<f32>>>)> = stream::iter(split_batches(texts, batch_size, max_tokens))
+        .map(|batch| {
+            let response = request(batch.to_vec());
+            async move { (batch.len(), response.await) }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut partial = PartialBatch {
+        embeddings: Vec::with_capacity(texts.len()),
+        first_error: None,
+    };
+    for (len, result) in results {
+        match result.and_then(|embeddings| check_embedding_count(embeddings, len)) {
+            Ok(embeddings) => partial.embeddings.extend(embeddings.into_iter().map(Some)),
+            Err(e) => {
+                partial.embeddings.extend(std::iter::repeat_with(|| None).take(len));
+                partial.first_error.get_or_insert(e);
+            }
+        }
+    }
+    partial
+}
+
+/// Ping every embedding and multimodal client concurrently, returning
+/// (name, latency, outcome) sorted by name
+pub fn health_check(
+    clients: Vec<(String, EmbeddingClient)>,
+    multimodal_clients: Vec<(String, MultimodalClient)>,
+    timeout: Duration,
+) -> Vec<(String, Duration, Result<()>)> {
+    let embedding = futures::future::join_all(clients.into_iter().map(|(name, client)| async move {
+        let (latency, result) = client.ping(timeout).await;
+        (name, latency, result)
+    }));
+    let multimodal = futures::future::join_all(multimodal_clients.into_iter().map(|(name, client)| async move {
+        let (latency, result) = client.ping(timeout).await;
+        (name, latency, result)
+    }));
+    let (mut rows, multimodal_rows) = RUNTIME.block_on(futures::future::join(embedding, multimodal));
+    rows.extend(multimodal_rows);
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+    rows
+}
+
+/// Most recent failure of a client, shared between clones and cleared on success
+#[derive(Clone, Default)]
+pub struct LastError(Arc<Mutex<Option<(String, u64)>>>);
+
+impl LastError {
+    /// Record the outcome of a call, passing the result through unchanged
+    pub fn track<T>(&self, result: Result<T>) -> Result<T> {
+        let mut last = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        *last = match &result {
+            Ok(_) => None,
+            Err(e) => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                Some((e.to_string(), timestamp))
+            }
+        };
+        result
+    }
+
+    /// The last error message and its unix timestamp, if the last call failed
+    pub fn get(&self) -> Option<(String, u64)> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Recent failures by (model, input), shared between clones; each answers
+/// with its error until it expires
+#[derive(Clone, Default)]
+struct NegativeCache(Arc<Mutex<HashMap<(String, String), (Instant, String)>>>);
+
+impl NegativeCache {
+    /// Error message of an unexpired failure for this input
+    fn get(&self, model: &str, text: &str) -> Option<String> {
+        let failures = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        failures
+            .get(&(model.to_string(), text.to_string()))
+            .filter(|(expires, _)| *expires > Instant::now())
+            .map(|(_, message)| message.clone())
+    }
+
+    /// Remember a failed input for ttl, or forget it once it succeeds,
+    /// passing the result through unchanged
+    fn record<T>(&self, model: &str, text: &str, ttl: Duration, result: Result<T>) -> Result<T> {
+        let mut failures = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        failures.retain(|_, (expires, _)| *expires > now);
+        let key = (model.to_string(), text.to_string());
+        match &result {
+            Ok(_) => {
+                failures.remove(&key);
+            }
+            Err(e) => {
+                failures.insert(key, (now + ttl, e.to_string()));
+            }
+        }
+        result
+    }
+}
+
+/// Env var setting how many recent calls each client keeps for rembed_trace
+const TRACE_CAPACITY_ENV: &str = "REMBED_TRACE_CAPACITY";
+/// Recent calls kept per client when REMBED_TRACE_CAPACITY is unset
+const DEFAULT_TRACE_CAPACITY: usize = 50;
+/// Orders trace records across every client
+static TRACE_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Calls kept per client for rembed_trace, and the rows it returns at most
+pub fn trace_capacity() -> usize {
+    std::env::var(TRACE_CAPACITY_ENV)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_TRACE_CAPACITY)
+}
+
+/// One embedding call as rembed_trace reports it. Inputs are kept only as
+/// their total length and a hash, never as text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub sequence: u64,
+    pub operation: &'static str,
+    pub input_chars: usize,
+    pub input_hash: String,
+    pub latency: Duration,
+    pub error: Option<String>,
+}
+
+/// Total length in characters and a short SHA-256 hex digest of a call's inputs
+fn describe_inputs<'a>(inputs: impl IntoIterator<Item = &'a str>) -> (usize, String) {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    let mut chars = 0;
+    for input in inputs {
+        chars += input.chars().count();
+        hasher.update((input.len() as u64).to_le_bytes());
+        hasher.update(input.as_bytes());
+    }
+    let digest = hasher.finalize();
+    (chars, digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Ring buffer of a client's most recent calls, shared between clones
+#[derive(Clone)]
+pub struct CallTrace {
+    records: Arc<Mutex<VecDeque<TraceRecord>>>,
+    capacity: usize,
+}
+
+impl Default for CallTrace {
+    fn default() -> Self {
+        Self { records: Arc::default(), capacity: trace_capacity() }
+    }
+}
+
+impl CallTrace {
+    /// Record a finished call, evicting the oldest record once full
+    fn record(&self, operation: &'static str, (input_chars, input_hash): (usize, String), started: Instant, error: Option<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(TraceRecord {
+            sequence: TRACE_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+            operation,
+            input_chars,
+            input_hash,
+            latency: started.elapsed(),
+            error,
+        });
+    }
+
+    /// Recorded calls, oldest first
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+}
+
+/// Cooldown of an open circuit breaker when breaker_cooldown_secs is not set
+pub const DEFAULT_BREAKER_COOLDOWN_SECS: u64 = 30;
+
+/// Fails calls fast once a client has failed threshold times in a row, so a
+/// provider outage doesn't cost a full timeout per row. After the cooldown
+/// one probe call is let through: success closes the circuit, failure
+/// reopens it for another cooldown. Shared between clones.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    threshold: Option<u32>,
+    cooldown: Duration,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Breaker opening after threshold consecutive failures; None never opens
+    pub fn new(threshold: Option<u32>, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Arc::new(Mutex::new(BreakerState::default())),
+        }
+    }
+
+    /// Fail fast while the circuit is open and its cooldown has not elapsed
+    pub fn check(&self) -> Result<()> {
+        if self.threshold.is_none() {
+            return Ok(());
+        }
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.opened_at.map(|opened_at| opened_at.elapsed()) {
+            Some(elapsed) if elapsed < self.cooldown => {
+                let remaining = self.cooldown.saturating_sub(elapsed);
+                Err(Error::new_message(format!(
+                    "Circuit breaker open after {} consecutive failures; failing fast for another {}s",
+                    state.consecutive_failures,
+                    remaining.as_secs_f64().ceil() as u64
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record whether a call reached the provider successfully
+    pub fn record(&self, success: bool) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if success {
+            *state = BreakerState::default();
+        } else {
+            state.consecutive_failures += 1;
+            if state.consecutive_failures >= threshold {
+                state.opened_at = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Run a call unless the circuit is open, recording its outcome
+    pub fn call<T>(&self, request: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.check()?;
+        let result = request();
+        self.record(result.is_ok());
+        result
+    }
+}
+
+/// Vectors of one embedding response, still as the provider's f64 values,
+/// with the prompt tokens it reported
+pub(crate) struct EmbedOutput {
+    vectors: Vec<Vec<f64>>,
+    prompt_tokens: Option<i64>,
+}
+
+impl EmbedOutput {
+    fn from_response(response: EmbedResponse) -> Self {
+        Self {
+            prompt_tokens: response.usage.prompt_tokens.map(i64::from),
+            vectors: response.embeddings.iter().map(|embedding| embedding.vector().to_vec()).collect(),
+        }
+    }
+
+    /// The vectors narrowed to f32 for sqlite-vec
+    fn narrowed(&self, rounding: F32Rounding) -> Vec<Vec<f32>> {
+        self.vectors.iter().map(|vector| vector::downcast(vector, rounding)).collect()
+    }
+}
+
+/// Where an EmbeddingClient sends its requests: genai, or in unit tests a
+/// stub answering with canned vectors without touching the network
+#[derive(Clone)]
+enum Backend {
+    GenAi(Arc<GenAiClient>),
+    #[cfg(test)]
+    Stub(Arc<tests::StubBackend>),
+}
+
+impl Backend {
+    async fn embed(
+        &self,
+        model: &str,
+        text: String,
+        options: Option<&EmbedOptions>,
+    ) -> Result<EmbedOutput> {
+        match self {
+            Backend::GenAi(client) => client
+                .embed(model, text, options)
+                .await
+                .map(EmbedOutput::from_response)
+                .map_err(|e| Error::new_message(e.to_string())),
+            #[cfg(test)]
+            Backend::Stub(stub) => stub.respond(model, vec![text]),
+        }
+    }
+
+    async fn embed_batch(
+        &self,
+        model: &str,
+        texts: Vec<String>,
+        options: Option<&EmbedOptions>,
+    ) -> Result<EmbedOutput> {
+        match self {
+            Backend::GenAi(client) => client
+                .embed_batch(model, texts, options)
+                .await
+                .map(EmbedOutput::from_response)
+                .map_err(|e| Error::new_message(e.to_string())),
+            #[cfg(test)]
+            Backend::Stub(stub) => stub.respond(model, texts),
+        }
+    }
+
+    /// Embed one text, through the batch request when it must be sent as an array
+    async fn embed_single(
+        &self,
+        model: &str,
+        text: String,
+        options: Option<&EmbedOptions>,
+        as_array: bool,
+    ) -> Result<EmbedOutput> {
+        if as_array {
+            self.embed_batch(model, vec![text], options).await
+        } else {
+            self.embed(model, text, options).await
+        }
+    }
+}
+
+/// Model with an explicit adapter replacing its provider prefix, which is
+/// how genai selects the adapter
+fn with_adapter(model: String, adapter: Option<&str>) -> String {
+    match adapter {
+        Some(adapter) => {
+            let model_name = model.split_once("::").map_or(model.as_str(), |(_, name)| name);
+            format!("{}::{}", adapter, model_name)
+        }
+        None => model,
+    }
+}
+
+/// Unified client using genai for all providers
+#[derive(Clone)]
+pub struct EmbeddingClient {
+    /// Sends embedding requests; genai outside of unit tests
+    backend: Backend,
+    /// Model identifier (can include provider prefix like "openai::text-embedding-3-small")
+    model: String,
+    /// Per-client request settings
+    settings: ClientSettings,
+    /// Most recent failure, reported by rembed_clients.last_error
+    last_error: LastError,
+    /// Fast-fails requests while the provider keeps failing
+    breaker: CircuitBreaker,
+    /// Client in this one's fallback chain that served the last embedding,
+    /// reported by rembed_clients.served_by
+    served_by: Arc<Mutex<Option<String>>>,
+    /// Inputs that failed within negative_cache_ttl_secs
+    failures: NegativeCache,
+    /// Recent calls, reported by rembed_trace
+    trace: CallTrace,
+}
+
+impl EmbeddingClient {
+    /// Create a new embedding client for the specified model
+    pub fn new(model: String, api_key: Option<String>) -> Result<Self> {
+        Self::with_settings(model, api_key, ClientSettings::default())
+    }
+
+    /// Create a new embedding client with custom per-client settings
+    pub fn with_settings(
+        model: String,
+        api_key: Option<String>,
+        settings: ClientSettings,
+    ) -> Result<Self> {
+        let model = with_adapter(model, settings.adapter.as_deref());
+        for role_model in [Some(&model), settings.query_model.as_ref(), settings.document_model.as_ref()].into_iter().flatten() {
+            check_embedding_provider(role_model)?;
+            if settings.validate_model {
+                check_embedding_model(role_model)?;
+            }
+        }
+
+        // If an API key is provided, set it as an environment variable
+        // This is a workaround since genai reads from env vars
+        if let Some(key) = api_key {
+            // Detect provider from model name and set appropriate env var
+            let provider = provider_from_model(&model);
+
+            match provider {
+                "openai" => std::env::set_var("OPENAI_API_KEY", &key),
+                "gemini" => std::env::set_var("GEMINI_API_KEY", &key),
+                "google" => std::env::set_var("GEMINI_API_KEY", &key), // Google uses GEMINI_API_KEY
+                "cohere" => std::env::set_var("CO_API_KEY", &key),
+                "anthropic" => std::env::set_var("ANTHROPIC_API_KEY", &key),
+                "groq" => std::env::set_var("GROQ_API_KEY", &key),
+                "deepseek" => std::env::set_var("DEEPSEEK_API_KEY", &key),
+                "xai" => std::env::set_var("XAI_API_KEY", &key),
+                "mistral" => std::env::set_var("MISTRAL_API_KEY", &key),
+                // For unknown providers, try setting a generic pattern
+                _ => std::env::set_var(&format!("{}_API_KEY", provider.to_uppercase()), &key),
+            }
+        }
+
+        // genai's default endpoints are https, apart from Ollama on localhost
+        if settings.require_tls {
+            let is_ollama = provider_from_model(&model) == "ollama";
+            let ollama_host = ollama_host_from_env();
+            if let Some(endpoint) = resolve_endpoint(settings.base_url.as_deref(), ollama_host.as_deref(), is_ollama) {
+                check_tls(&endpoint)?;
+            }
+        }
+
+        let breaker = CircuitBreaker::new(
+            settings.breaker_threshold,
+            Duration::from_secs(settings.breaker_cooldown_secs.unwrap_or(DEFAULT_BREAKER_COOLDOWN_SECS)),
+        );
+
+        Ok(Self {
+            backend: Backend::GenAi(Arc::new(build_genai_client(settings.base_url.clone()))),
+            model,
+            settings,
+            last_error: LastError::default(),
+            breaker,
+            served_by: Arc::default(),
+            failures: NegativeCache::default(),
+            trace: CallTrace::default(),
+        })
+    }
+
+    /// Model identifier this client embeds with
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Per-client request settings
+    pub fn settings(&self) -> &ClientSettings {
+        &self.settings
+    }
+
+    /// Per-attempt request timeout in seconds, if configured
+    pub fn timeout_secs(&self) -> Option<u64> {
+        self.settings.timeout_secs
+    }
+
+    /// Number of retries after a failed attempt
+    pub fn max_retries(&self) -> u32 {
+        self.settings.max_retries
+    }
+
+    /// Effective (chunk_size, chunk_overlap) for document chunking
+    pub fn chunking(&self) -> (usize, usize) {
+        self.settings.chunking()
+    }
+
+    /// Most recent failure of this client, if its last call failed
+    pub fn last_error(&self) -> Option<(String, u64)> {
+        self.last_error.get()
+    }
+
+    /// This client's most recent calls, oldest first
+    pub fn trace(&self) -> Vec<TraceRecord> {
+        self.trace.records()
+    }
+
+    /// Name of the client that produced this client's last embedding
+    pub fn served_by(&self) -> Option<String> {
+        self.served_by.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Record which client in the fallback chain produced an embedding
+    pub fn record_served_by(&self, name: &str) {
+        *self.served_by.lock().unwrap_or_else(|e| e.into_inner()) = Some(name.to_string());
+    }
+
+    /// Element type used when returning this client's embeddings
+    pub fn precision(&self) -> Precision {
+        self.settings.precision
+    }
+
+    /// Shape of the JSON returned for this client's batches
+    pub fn batch_output(&self) -> BatchOutput {
+        self.settings.batch_output
+    }
+
+    /// Layout used when returning this client's embeddings as blobs
+    pub fn output_format(&self) -> OutputFormat {
+        self.settings.output_format
+    }
+
+    /// Precision, layout and byte order of this client's embedding blobs
+    pub fn blob_format(&self) -> (Precision, OutputFormat, Endian) {
+        (self.settings.precision, self.settings.output_format, self.settings.endian)
+    }
+
+    /// Maximum texts sent per embed_batch request; 1 when batches are sent
+    /// as concurrent single-text requests
+    pub fn batch_size(&self) -> usize {
+        if !self.native_batches() {
+            return 1;
+        }
+        self.settings
+            .batch_size
+            .unwrap_or_else(|| default_batch_size(provider_from_model(&self.model)))
+    }
+
+    /// Whether batches go to the provider's batch endpoint, per batch_strategy
+    pub fn native_batches(&self) -> bool {
+        self.settings.batch_strategy.is_native(provider_from_model(&self.model))
+    }
+
+    /// Sub-batch requests sent concurrently by batch embedding
+    pub fn max_concurrency(&self) -> usize {
+        self.settings.max_concurrency.unwrap_or(DEFAULT_BATCH_CONCURRENCY)
+    }
+
+    /// Generate embeddings for a single text synchronously
+    pub fn embed_sync(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_with_usage_sync(text).map(|(embedding, _)| embedding)
+    }
+
+    /// Embed already-prepared input with the model configured for its role
+    /// (query_model or document_model), falling back to the client's model
+    pub fn embed_role_sync(&self, text: &str, role: Option<InputRole>) -> Result<Vec<f32>> {
+        self.embed_role_wide_sync(text, role)
+            .map(|embedding| vector::downcast(&embedding, self.settings.f32_round))
+    }
+
+    /// Like embed_role_sync, keeping the provider's f64 values for
+    /// precision=f64 clients
+    pub fn embed_role_wide_sync(&self, text: &str, role: Option<InputRole>) -> Result<Vec<f64>> {
+        let model = match self.role_model(role) {
+            Ok(model) => model,
+            Err(error) => return self.last_error.track(Err(error)),
+        };
+        self.embed_model_sync(model, text).map(|(embedding, _)| embedding)
+    }
+
+    /// Full model identifier serving a role: its query_model or
+    /// document_model, or the client's model
+    fn role_model(&self, role: Option<InputRole>) -> Result<String> {
+        Ok(match self.settings.role_model(role)? {
+            Some(model) => {
+                // A bare role model shares the client's provider prefix
+                let model = resolve_model_alias(model);
+                let model = match self.model.split_once("::") {
+                    Some((provider, _)) if !model.contains("::") => format!("{}::{}", provider, model),
+                    _ => model,
+                };
+                with_adapter(model, self.settings.adapter.as_deref())
+            }
+            None => self.model.clone(),
+        })
+    }
+
+    /// Like embed_sync, also returning the prompt tokens the provider
+    /// reported for the request, if it reported any
+    pub fn embed_with_usage_sync(&self, text: &str) -> Result<(Vec<f32>, Option<i64>)> {
+        self.embed_with_usage_wide_sync(text)
+            .map(|(embedding, tokens)| (vector::downcast(&embedding, self.settings.f32_round), tokens))
+    }
+
+    /// Like embed_with_usage_sync, keeping the provider's f64 values
+    pub fn embed_with_usage_wide_sync(&self, text: &str) -> Result<(Vec<f64>, Option<i64>)> {
+        self.embed_model_sync(self.model.clone(), text)
+    }
+
+    fn embed_model_sync(&self, model: String, text: &str) -> Result<(Vec<f64>, Option<i64>)> {
+        let text = self.settings.normalize_text.apply(text).into_owned();
+        let started = Instant::now();
+        let result = match self.settings.negative_cache_ttl_secs.map(Duration::from_secs) {
+            None => self.embed_uncached(model, text.clone()),
+            Some(ttl) => match self.failures.get(&model, &text) {
+                Some(message) => self.last_error.track(Err(Error::new_message(message))),
+                None => {
+                    let result = self.embed_uncached(model.clone(), text.clone());
+                    self.failures.record(&model, &text, ttl, result)
+                }
+            },
+        };
+        let error = result.as_ref().err().map(ToString::to_string);
+        self.trace.record("embed", describe_inputs([text.as_str()]), started, error);
+        result
+    }
+
+    fn embed_uncached(&self, model: String, text: String) -> Result<(Vec<f64>, Option<i64>)> {
+        let backend = self.backend.clone();
+        let embed_options = self.settings.embed_options();
+        let settings = self.settings.clone();
+        let embedding_index = self.settings.embedding_index.unwrap_or(0);
+        let as_array = self.settings.input_as_array;
+
+        // Run async operation in the runtime
+        let result = self.breaker.call(|| RUNTIME.block_on(async move {
+            let (backend, model, text, embed_options) = (&backend, &model, &text, embed_options.as_ref());
+            let request = settings.run_checked(move || async move {
+                telemetry::traced("embed", model, backend.embed_single(model, text.clone(), embed_options, as_array))
+                    .await
+                    .map_err(|e| Error::new_message(format!("Embedding failed: {}", e)))
+            });
+            settings.within_deadline(request).await.and_then(|result| result).and_then(|output| {
+                let count = output.vectors.len();
+                let tokens = output.prompt_tokens;
+                output
+                    .vectors
+                    .into_iter()
+                    .nth(embedding_index)
+                    .ok_or_else(|| match count {
+                        0 => Error::new_message("No embedding in response"),
+                        _ => Error::new_message(format!(
+                            "embedding_index {} is out of range for a response with {} embeddings",
+                            embedding_index, count
+                        )),
+                    })
+                    .map(|embedding| (embedding, tokens))
+            })
+        }));
+        self.last_error.track(result.map(|(embedding, tokens)| (self.settings.fit_dimensions(embedding), tokens)))
+    }
+
+    /// Send one minimal embedding request, without retries, the circuit
+    /// breaker or last_error, returning how long it took and its outcome
+    pub(crate) async fn ping(&self, timeout: Duration) -> (Duration, Result<()>) {
+        let embed_options = self.settings.embed_options();
+        let request = self.backend.embed_single(
+            &self.model,
+            "ping".to_string(),
+            embed_options.as_ref(),
+            self.settings.input_as_array,
+        );
+        let started = Instant::now();
+        let result = match tokio::time::timeout(timeout, telemetry::traced("health", &self.model, request)).await {
+            Ok(result) => result.map(|_| ()),
+            Err(_) => Err(Error::new_message(format!("No response within {}ms", timeout.as_millis()))),
+        };
+        (started.elapsed(), result)
+    }
+
+    /// The request rembed would send for text in a role, as JSON with the
+    /// method, path below the endpoint (null endpoint: the adapter's default),
+    /// headers and body. The input is prepared and the role's model chosen
+    /// as for embedding; credentials are redacted and nothing is sent.
+    pub fn debug_request(&self, text: &str, role: Option<InputRole>) -> Result<serde_json::Value> {
+        let model = self.role_model(role)?;
+        let input = self.settings.prepare_input(text, role);
+        let input = self.settings.normalize_text.apply(&input);
+        Ok(describe_request(&model, &input, &self.settings))
+    }
+
+    /// Estimated tokens, requests and (with price_per_1k) cost of batch
+    /// embedding texts, as JSON; nothing is sent
+    pub fn estimate_cost(&self, texts: Vec<String>) -> serde_json::Value {
+        let count = texts.len();
+        let texts = if self.settings.dedup { dedup_texts(texts).0 } else { texts };
+        let tokens: usize = texts.iter().map(|text| estimate_tokens(text)).sum();
+        let requests = split_batches(&texts, self.batch_size(), self.settings.max_batch_tokens).len();
+        let cost = self.settings.price_per_1k.map(|price| tokens as f64 / 1000.0 * price);
+
+        serde_json::json!({
+            "texts": count,
+            "tokens": tokens,
+            "requests": requests,
+            "estimated_cost": cost,
+        })
+    }
+
+    /// Generate embeddings for multiple texts synchronously (batch processing),
+    /// failing if any text could not be embedded
+    pub fn embed_batch_sync(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>> {
+        self.embed_batch_partial_sync(texts).into_result()
+    }
+
+    /// Generate embeddings for multiple texts, splitting the input into
+    /// concurrent requests of at most batch_size texts that are retried and
+    /// fail independently
+    pub fn embed_batch_partial_sync(&self, texts: Vec<&str>) -> PartialBatch {
+        self.embed_batch_with_options(texts, self.settings.embed_options())
+    }
+
+    /// Like embed_batch_partial_sync, tagging every text with one provider
+    /// input type such as Cohere's "search_query"
+    pub fn embed_batch_typed_sync(&self, texts: Vec<&str>, input_type: &str) -> Result<PartialBatch> {
+        validate_input_type(provider_from_model(&self.model), input_type)?;
+        let embed_options = self
+            .settings
+            .embed_options()
+            .unwrap_or_default()
+            .with_embedding_type(input_type.to_string());
+        Ok(self.embed_batch_with_options(texts, Some(embed_options)))
+    }
+
+    fn embed_batch_with_options(&self, texts: Vec<&str>, embed_options: Option<EmbedOptions>) -> PartialBatch {
+        let started = Instant::now();
+        let inputs = describe_inputs(texts.iter().copied());
+        if let Err(e) = self.breaker.check() {
+            let message = e.to_string();
+            let _ = self.last_error.track::<()>(Err(e));
+            self.trace.record("embed_batch", inputs, started, Some(message.clone()));
+            return PartialBatch::failed(texts.len(), Error::new_message(message));
+        }
+        let backend = self.backend.clone();
+        let model = self.model.clone();
+        let texts: Vec<String> = texts
+            .into_iter()
+            .map(|s| self.settings.normalize_text.apply(s).into_owned())
+            .collect();
+        let (texts, positions) = if self.settings.dedup {
+            let (unique, positions) = dedup_texts(texts);
+            (unique, Some(positions))
+        } else {
+            (texts, None)
+        };
+        let settings = self.settings.clone();
+        let batch_size = self.batch_size();
+        let max_batch_tokens = self.settings.max_batch_tokens;
+        let native = self.native_batches();
+        let as_array = self.settings.input_as_array;
+        let concurrency = self.max_concurrency();
+        let text_count = texts.len();
+
+        // Run async operation in the runtime
+        let partial = RUNTIME.block_on(async move {
+            let (backend, model, settings, embed_options) = (&backend, &model, &settings, embed_options.as_ref());
+            let batches = embed_in_batches(&texts, batch_size, max_batch_tokens, concurrency, move |batch| async move {
+                let batch = &batch;
+                settings.run_checked(move || async move {
+                    let output = if native {
+                        telemetry::traced("embed_batch", model, backend.embed_batch(model, batch.clone(), embed_options)).await
+                    } else {
+                        // Concurrent strategy: sub-batches hold a single text
+                        telemetry::traced("embed", model, backend.embed_single(model, batch[0].clone(), embed_options, as_array)).await
+                    };
+                    output.map_err(|e| Error::new_message(format!("Batch embedding failed: {}", e)))
+                })
+                .await
+                .map(|output| output.narrowed(settings.f32_round))
+            });
+            settings.within_deadline(batches).await
+        });
+        let partial = partial.unwrap_or_else(|e| PartialBatch::failed(text_count, e));
+        // Any embedded sub-batch shows the provider is reachable
+        if !partial.embeddings.is_empty() {
+            self.breaker.record(partial.embeddings.iter().any(Option::is_some));
+        }
+        let mut partial = match positions {
+            Some(positions) => partial.fan_out(&positions),
+            None => partial,
+        };
+        partial.embeddings = partial
+            .embeddings
+            .into_iter()
+            .map(|embedding| embedding.map(|embedding| self.settings.fit_dimensions(embedding)))
+            .collect();
+
+        let outcome = match &partial.first_error {
+            Some(e) => Err(Error::new_message(e.to_string())),
+            None => Ok(()),
+        };
+        self.trace.record("embed_batch", inputs, started, outcome.as_ref().err().map(ToString::to_string));
+        let _ = self.last_error.track(outcome);
+        partial
+    }
+}
+
+/// Input types each provider accepts for a whole batch request
+fn provider_input_types(provider: &str) -> &'static [&'static str] {
+    match provider {
+        "cohere" => &["search_document", "search_query", "classification", "clustering"],
+        "voyage" => &["query", "document"],
+        _ => &[],
+    }
+}
+
+/// Fail unless the provider accepts input_type
+pub fn validate_input_type(provider: &str, input_type: &str) -> Result<()> {
+    let accepted = provider_input_types(provider);
+    if accepted.is_empty() {
+        return Err(Error::new_message(format!(
+            "Provider '{}' does not accept an input_type",
+            provider
+        )));
+    }
+    if !accepted.contains(&input_type) {
+        return Err(Error::new_message(format!(
+            "Invalid input_type '{}' for {}, expected one of: {}",
+            input_type,
+            provider,
+            accepted.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Provider prefix of a genai model string ("ollama::nomic-embed-text" -> "ollama"),
+/// defaulting to openai for backward compatibility
+pub fn provider_from_model(model: &str) -> &str {
+    match model.find("::") {
+        Some(idx) => &model[..idx],
+        None => "openai",
+    }
+}
+
+/// Friendly model names mapped to canonical "provider::model" strings, shared
+/// by every connection in the process and extended by rembed_register_alias
+static MODEL_ALIASES: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| {
+    let aliases = [
+        ("ada", "openai::text-embedding-ada-002"),
+        ("ada-002", "openai::text-embedding-ada-002"),
+        ("text-embedding-ada-002", "openai::text-embedding-ada-002"),
+        ("3-small", "openai::text-embedding-3-small"),
+        ("3-large", "openai::text-embedding-3-large"),
+        ("nomic", "ollama::nomic-embed-text"),
+        ("mxbai", "ollama::mxbai-embed-large"),
+        ("cohere-english", "cohere::embed-english-v3.0"),
+        ("cohere-multilingual", "cohere::embed-multilingual-v3.0"),
+    ];
+    Mutex::new(
+        aliases
+            .into_iter()
+            .map(|(alias, canonical)| (alias.to_string(), canonical.to_string()))
+            .collect(),
+    )
 });
 
-/// Unified client using genai for all providers
-#[derive(Clone)]
-pub struct EmbeddingClient {
-    /// The genai client instance
-    client: Arc<GenAiClient>,
-    /// Model identifier (can include provider prefix like "openai::text-embedding-3-small")
-    model: String,
-}
+/// Add or replace a model alias; the canonical name must be "provider::model"
+pub fn register_model_alias(alias: &str, canonical: &str) -> Result<()> {
+    if alias.trim().is_empty() {
+        return Err(Error::new_message("Model alias cannot be empty"));
+    }
+    match canonical.split_once("::") {
+        Some((provider, model)) if !provider.is_empty() && !model.is_empty() => {}
+        _ => {
+            return Err(Error::new_message(format!(
+                "Canonical model '{}' must have the form 'provider::model'",
+                canonical
+            )))
+        }
+    }
+    MODEL_ALIASES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(alias.to_string(), canonical.to_string());
+    Ok(())
+}
+
+/// Canonical model for a registered alias; unknown names pass through unchanged
+pub fn resolve_model_alias(name: &str) -> String {
+    MODEL_ALIASES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Parsed client configuration from SQL
+#[derive(Debug, PartialEq)]
+pub struct ClientConfig {
+    pub model: String,
+    pub api_key: Option<String>,
+    pub settings: ClientSettings,
+}
+
+/// Flatten a JSON object into option key/value pairs; non-string values keep
+/// their JSON text, and non-objects produce no options
+pub fn json_to_options(json: &serde_json::Value) -> HashMap<String, String> {
+    json.as_object()
+        .map(|object| {
+            object
+                .iter()
+                .map(|(k, v)| {
+                    let value = match v {
+                        serde_json::Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    (k.clone(), value)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Helper to parse client options and extract model + api key
+pub fn parse_client_options(name: &str, options: &str) -> Result<ClientConfig> {
+    // Check if options contains JSON-like structure with key
+    if options.contains('{') && options.contains('}') {
+        // Try to parse as JSON
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(options) {
+            let model = json.get("model")
+                .or_else(|| json.get("provider"))
+                .and_then(|v| v.as_str())
+                .map(resolve_model_alias)
+                .unwrap_or_else(|| name.to_string());
+
+            let api_key = json.get("key")
+                .or_else(|| json.get("api_key"))
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string());
+            let model = with_key_provider(model, api_key.as_deref());
+
+            // Remaining fields are per-client settings
+            let settings = ClientSettings::from_options(&json_to_options(&json))?;
+
+            return Ok(ClientConfig { model, api_key, settings });
+        }
+    }
+
+    // Check if it's a simple "provider:key" format
+    if options.contains(':') && !options.contains("::") {
+        let parts: Vec<&str> = options.splitn(2, ':').collect();
+        if parts.len() == 2 {
+            let provider = parts[0];
+            let key = parts[1];
+            let model = prefix_model(provider, name)?;
+            return Ok(ClientConfig {
+                model,
+                api_key: Some(key.to_string()),
+                settings: ClientSettings::default(),
+            });
+        }
+    }
+
+    // A bare API key whose format identifies its provider
+    if provider_from_api_key(options).is_some() {
+        return Ok(ClientConfig {
+            model: with_key_provider(resolve_model_alias(name), Some(options)),
+            api_key: Some(options.to_string()),
+            settings: ClientSettings::default(),
+        });
+    }
+
+    // Legacy format: just provider name
+    let model = match options {
+        "openai" | "gemini" | "cohere" | "anthropic" | "ollama" | "groq" => prefix_model(options, name)?,
+        // If it already contains "::" assume it's a full model identifier
+        s if s.contains("::") => s.to_string(),
+        // Otherwise, assume it's a model name (or alias) that should work with default provider
+        _ => resolve_model_alias(options),
+    };
+
+    Ok(ClientConfig { model, api_key: None, settings: ClientSettings::default() })
+}
+
+/// Provider whose API keys start with a recognizable prefix
+pub fn provider_from_api_key(key: &str) -> Option<&'static str> {
+    // Longer prefixes first: Anthropic keys also start with "sk-"
+    const KEY_PREFIXES: &[(&str, &str)] = &[
+        ("sk-ant-", "anthropic"),
+        ("sk-", "openai"),
+        ("gsk_", "groq"),
+        ("xai-", "xai"),
+        ("AIza", "gemini"),
+    ];
+    KEY_PREFIXES
+        .iter()
+        .find(|(prefix, _)| key.starts_with(prefix))
+        .map(|(_, provider)| *provider)
+}
+
+/// Model prefixed with the provider its API key identifies, unless the model
+/// already names a provider or the key is not recognized
+fn with_key_provider(model: String, api_key: Option<&str>) -> String {
+    match api_key.and_then(provider_from_api_key) {
+        Some(provider) if !model.contains("::") => format!("{}::{}", provider, model),
+        _ => model,
+    }
+}
+
+/// "provider::model" for a model given with or without a provider prefix.
+/// An aliased name becomes its canonical model under this provider. An
+/// existing prefix must name the same provider rather than being doubled
+/// into "openai::openai::...".
+fn prefix_model(provider: &str, model: &str) -> Result<String> {
+    if let Some((prefix, model_name)) = model.split_once("::") {
+        if prefix != provider {
+            return Err(Error::new_message(format!(
+                "Model '{}' already has provider prefix '{}', which conflicts with '{}'; \
+                 remove the prefix or the provider",
+                model, prefix, provider
+            )));
+        }
+        return Ok(format!("{}::{}", provider, model_name));
+    }
+
+    let canonical = resolve_model_alias(model);
+    let model_name = canonical
+        .split_once("::")
+        .map_or(canonical.as_str(), |(_, model_name)| model_name);
+    Ok(format!("{}::{}", provider, model_name))
+}
+
+/// Legacy compatibility: Map old provider names to genai format. An aliased
+/// model name is replaced by its canonical model, keeping the given provider.
+pub fn legacy_provider_to_model(provider: &str, model_name: &str) -> Result<String> {
+    let genai_provider = match provider {
+        "openai" => "openai",
+        "nomic" => "openai", // Nomic uses OpenAI-compatible API
+        "cohere" => "cohere",
+        "jina" => "openai", // Jina uses OpenAI-compatible API
+        "mixedbread" => "openai", // MixedBread uses OpenAI-compatible API
+        "ollama" => "ollama",
+        "llamafile" => "ollama", // Llamafile is Ollama-compatible
+        "tei" => "openai", // text-embeddings-inference serves an OpenAI-compatible /v1/embeddings
+        _ => return Ok(resolve_model_alias(model_name)),
+    };
+    prefix_model(genai_provider, model_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backend answering each text with [its length in chars, 1.0] and
+    /// recording the model and texts of every request
+    #[derive(Default)]
+    pub(super) struct StubBackend {
+        requests: Mutex<Vec<Vec<String>>>,
+        models: Mutex<Vec<String>>,
+        /// Vectors answered in place of the defaults, one per text, in order
+        canned: Mutex<std::collections::VecDeque<Vec<f64>>>,
+    }
+
+    impl StubBackend {
+        pub(super) fn respond(&self, model: &str, texts: Vec<String>) -> Result<EmbedOutput> {
+            self.models.lock().unwrap().push(model.to_string());
+            let mut canned = self.canned.lock().unwrap();
+            let vectors = texts
+                .iter()
+                .map(|text| canned.pop_front().unwrap_or_else(|| vec![text.chars().count() as f64, 1.0]))
+                .collect();
+            let prompt_tokens = Some(texts.iter().map(|text| text.split_whitespace().count() as i64).sum());
+            self.requests.lock().unwrap().push(texts);
+            Ok(EmbedOutput { vectors, prompt_tokens })
+        }
+    }
+
+    /// Client whose requests are answered by a StubBackend instead of genai
+    fn stub_client(options: &[(&str, &str)]) -> (EmbeddingClient, Arc<StubBackend>) {
+        let options: HashMap<String, String> =
+            options.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let mut client = EmbeddingClient::with_settings("openai::stub".to_string(), None, settings).unwrap();
+        let stub = Arc::new(StubBackend::default());
+        client.backend = Backend::Stub(stub.clone());
+        (client, stub)
+    }
+
+    #[test]
+    fn test_health_check() {
+        let (healthy, stub) = stub_client(&[]);
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+        let down = EmbeddingClient::with_settings(
+            "openai::down".to_string(),
+            Some("test-key".to_string()),
+            ClientSettings::from_options(&options).unwrap(),
+        )
+        .unwrap();
+
+        let rows = health_check(
+            vec![("healthy".to_string(), healthy), ("down".to_string(), down.clone())],
+            Vec::new(),
+            Duration::from_secs(5),
+        );
+        assert_eq!(rows[0].0, "down");
+        assert!(rows[0].2.is_err());
+        assert_eq!(rows[1].0, "healthy");
+        assert!(rows[1].2.is_ok());
+        assert_eq!(*stub.requests.lock().unwrap(), vec![vec!["ping".to_string()]]);
+        // Health checks leave the client's own status alone
+        assert_eq!(down.last_error(), None);
+    }
+
+    #[test]
+    fn test_stub_backend() {
+        let (client, stub) = stub_client(&[]);
+        assert_eq!(client.embed_sync("hello").unwrap(), vec![5.0, 1.0]);
+        assert_eq!(client.embed_with_usage_sync("two words").unwrap(), (vec![9.0, 1.0], Some(2)));
+
+        let (client, stub_batched) = stub_client(&[("batch_size", "2"), ("dedup", "true")]);
+        let embeddings = client.embed_batch_sync(vec!["a", "bbb", "a", "cc"]).unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 1.0], vec![3.0, 1.0], vec![1.0, 1.0], vec![2.0, 1.0]]);
+        assert_eq!(
+            *stub_batched.requests.lock().unwrap(),
+            vec![vec!["a".to_string(), "bbb".to_string()], vec!["cc".to_string()]]
+        );
+        assert_eq!(stub.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_client_options() {
+        let config = parse_client_options("text-embedding-3-small", "openai").unwrap();
+        assert_eq!(config.model, "openai::text-embedding-3-small");
+        assert_eq!(config.api_key, None);
+
+        let config = parse_client_options("embedding-001", "gemini").unwrap();
+        assert_eq!(config.model, "gemini::embedding-001");
+        assert_eq!(config.api_key, None);
+
+        // Test passthrough for full model identifiers
+        let config = parse_client_options("ignored", "openai::ada-002").unwrap();
+        assert_eq!(config.model, "openai::ada-002");
+        assert_eq!(config.api_key, None);
+    }
+
+    #[test]
+    fn test_anthropic_embeddings_suggest_voyage() {
+        let message = EmbeddingClient::new("anthropic::voyage-3".to_string(), None).err().unwrap().to_string();
+        assert!(message.contains("Anthropic has no embedding endpoint"), "{}", message);
+        assert!(message.contains("https://api.voyageai.com/v1/"), "{}", message);
+
+        // An Anthropic key infers the provider, so it fails the same way
+        let config = parse_client_options("claude", "sk-ant-api03-abc").unwrap();
+        assert!(EmbeddingClient::with_settings(config.model, config.api_key, config.settings).is_err());
+
+        let settings = ClientSettings { query_model: Some("anthropic::voyage-3".to_string()), ..Default::default() };
+        assert!(EmbeddingClient::with_settings("openai::text-embedding-3-small".to_string(), None, settings).is_err());
+        assert!(EmbeddingClient::new("openai::voyage-3".to_string(), None).is_ok());
+    }
+
+    #[test]
+    fn test_validate_model() {
+        let validating = || ClientSettings { validate_model: true, ..Default::default() };
+        for model in ["ollama::llava:7b", "openai::gpt-4-vision-preview", "ollama::llama3.2-vision"] {
+            let err = EmbeddingClient::with_settings(model.to_string(), None, validating()).unwrap_err();
+            assert!(err.to_string().contains("embedding_model"), "{}", err);
+        }
+        for model in ["ollama::nomic-embed-text", "openai::text-embedding-3-small"] {
+            assert!(EmbeddingClient::with_settings(model.to_string(), None, validating()).is_ok());
+        }
+        // Off by default
+        assert!(EmbeddingClient::new("ollama::llava:7b".to_string(), None).is_ok());
+    }
+
+    #[test]
+    fn test_provider_from_api_key() {
+        for (key, provider) in [
+            ("sk-ant-api03-abc", Some("anthropic")),
+            ("sk-proj-abc", Some("openai")),
+            ("sk-abc", Some("openai")),
+            ("gsk_abc", Some("groq")),
+            ("xai-abc", Some("xai")),
+            ("AIzaSyabc", Some("gemini")),
+            ("abc123", None),
+        ] {
+            assert_eq!(provider_from_api_key(key), provider, "{}", key);
+        }
+
+        let config = parse_client_options("text-embedding-3-small", "sk-proj-abc").unwrap();
+        assert_eq!(config.model, "openai::text-embedding-3-small");
+        assert_eq!(config.api_key.as_deref(), Some("sk-proj-abc"));
+        let config = parse_client_options("ignored", r#"{"model": "embedding-001", "key": "AIzaSyabc"}"#).unwrap();
+        assert_eq!(config.model, "gemini::embedding-001");
+
+        // An explicit provider wins over the key's format
+        let config = parse_client_options("nomic-embed-text", "ollama:sk-abc").unwrap();
+        assert_eq!(config.model, "ollama::nomic-embed-text");
+        let config = parse_client_options("cohere::embed-english-v3.0", "sk-abc").unwrap();
+        assert_eq!(config.model, "cohere::embed-english-v3.0");
+        let config =
+            parse_client_options("ignored", r#"{"model": "mistral::mistral-embed", "key": "sk-abc"}"#).unwrap();
+        assert_eq!(config.model, "mistral::mistral-embed");
+    }
+
+    #[test]
+    fn test_parse_json_client_settings() {
+        let config = parse_client_options(
+            "ignored",
+            r#"{"model": "openai::text-embedding-3-small", "encoding_format": "base64"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.model, "openai::text-embedding-3-small");
+        assert_eq!(config.settings.encoding_format.as_deref(), Some("base64"));
+
+        assert!(parse_client_options(
+            "ignored",
+            r#"{"model": "openai::text-embedding-3-small", "encoding_format": "int8"}"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_timeout_and_retry_settings() {
+        let config = parse_client_options(
+            "ignored",
+            r#"{"model": "ollama::nomic-embed-text", "timeout_secs": 5, "max_retries": "2"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.settings.timeout_secs, Some(5));
+        assert_eq!(config.settings.max_retries, 2);
+
+        let mut options = HashMap::new();
+        options.insert("timeout_secs".to_string(), "0".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+        options.insert("timeout_secs".to_string(), "soon".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_run_retries_failed_attempts() {
+        let settings = ClientSettings { max_retries: 2, ..Default::default() };
+        let attempts = std::cell::Cell::new(0);
+        let result: Result<u32> = RUNTIME.block_on(settings.run(|| {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 3 {
+                    Err(Error::new_message("transient"))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        }));
+        assert_eq!(result.unwrap(), 3);
+
+        attempts.set(0);
+        let settings = ClientSettings { max_retries: 1, ..Default::default() };
+        let result: Result<u32> = RUNTIME.block_on(settings.run(|| {
+            attempts.set(attempts.get() + 1);
+            async { Err(Error::new_message("down")) }
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn test_chunking_settings() {
+        let mut options = HashMap::new();
+        assert_eq!(
+            ClientSettings::from_options(&options).unwrap().chunking(),
+            (DEFAULT_CHUNK_SIZE, DEFAULT_CHUNK_OVERLAP)
+        );
+
+        options.insert("chunk_size".to_string(), "100".to_string());
+        options.insert("chunk_overlap".to_string(), "10".to_string());
+        assert_eq!(ClientSettings::from_options(&options).unwrap().chunking(), (100, 10));
+
+        options.insert("chunk_overlap".to_string(), "100".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_batch_size_settings() {
+        let mut options = HashMap::new();
+        options.insert("batch_size".to_string(), "32".to_string());
+        assert_eq!(ClientSettings::from_options(&options).unwrap().batch_size, Some(32));
+        options.insert("batch_size".to_string(), "0".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+
+        let client = EmbeddingClient::new("cohere::embed-english-v3.0".to_string(), None).unwrap();
+        assert_eq!(client.batch_size(), 96);
+        let settings = ClientSettings { batch_size: Some(8), ..Default::default() };
+        let client = EmbeddingClient::with_settings("cohere::embed-english-v3.0".to_string(), None, settings).unwrap();
+        assert_eq!(client.batch_size(), 8);
+    }
+
+    #[test]
+    fn test_embed_in_batches_preserves_order() {
+        let texts: Vec<String> = (0..7).map(|i| i.to_string()).collect();
+        let requests = std::cell::RefCell::new(Vec::new());
+        let embeddings = RUNTIME
+            .block_on(embed_in_batches(&texts, 3, None, 2, |batch| {
+                requests.borrow_mut().push(batch.len());
+                async move {
+                    Ok(batch.iter().map(|t| vec![t.parse::<f32>().unwrap()]).collect())
+                }
+            }))
+            .into_result()
+            .unwrap();
+
+        assert_eq!(*requests.borrow(), vec![3, 3, 1]);
+        let expected: Vec<Vec<f32>> = (0..7).map(|i| vec![i as f32]).collect();
+        assert_eq!(embeddings, expected);
+
+        let short = RUNTIME.block_on(embed_in_batches(&texts, 4, None, 1, |_| async { Ok(vec![vec![0.0]]) }));
+        assert!(short.into_result().is_err());
+    }
+
+    #[test]
+    fn test_max_batch_tokens() {
+        // 2, 2, 5, 1 and 2 estimated tokens
+        let texts: Vec<String> = ["abcdefgh", "abcdefgh", "a b c d e", "a", "abcdefgh"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let lengths = |batches: Vec<&[String]>| batches.iter().map(|batch| batch.len()).collect::<Vec<_>>();
+        assert_eq!(lengths(split_batches(&texts, 10, None)), vec![5]);
+        assert_eq!(lengths(split_batches(&texts, 10, Some(4))), vec![2, 1, 2]);
+        assert_eq!(lengths(split_batches(&texts, 2, Some(4))), vec![2, 1, 2]);
+        assert_eq!(lengths(split_batches(&texts, 10, Some(1))), vec![1, 1, 1, 1, 1]);
+        assert!(split_batches(&[], 10, Some(4)).is_empty());
 
-impl EmbeddingClient {
-    /// Create a new embedding client for the specified model
-    pub fn new(model: String, api_key: Option<String>) -> Result<Self> {
-        // If an API key is provided, set it as an environment variable
-        // This is a workaround since genai reads from env vars
-        if let Some(key) = api_key {
-            // Detect provider from model name and set appropriate env var
-            let provider = if let Some(idx) = model.find("::") {
-                &model[..idx]
-            } else {
-                // Default to openai for backward compatibility
-                "openai"
-            };
+        let (client, stub) = stub_client(&[("max_batch_tokens", "4")]);
+        let embeddings = client.embed_batch_sync(texts.iter().map(String::as_str).collect()).unwrap();
+        assert_eq!(embeddings.len(), 5);
+        let mut requests = stub.requests.lock().unwrap().clone();
+        requests.sort();
+        assert_eq!(requests, vec![
+            vec!["a".to_string(), "abcdefgh".to_string()],
+            vec!["a b c d e".to_string()],
+            vec!["abcdefgh".to_string(), "abcdefgh".to_string()],
+        ]);
+        assert_eq!(client.estimate_cost(texts)["requests"], 3);
+    }
 
-            match provider {
-                "openai" => std::env::set_var("OPENAI_API_KEY", &key),
-                "gemini" => std::env::set_var("GEMINI_API_KEY", &key),
-                "google" => std::env::set_var("GEMINI_API_KEY", &key), // Google uses GEMINI_API_KEY
-                "cohere" => std::env::set_var("CO_API_KEY", &key),
-                "anthropic" => std::env::set_var("ANTHROPIC_API_KEY", &key),
-                "groq" => std::env::set_var("GROQ_API_KEY", &key),
-                "deepseek" => std::env::set_var("DEEPSEEK_API_KEY", &key),
-                "xai" => std::env::set_var("XAI_API_KEY", &key),
-                "mistral" => std::env::set_var("MISTRAL_API_KEY", &key),
-                // For unknown providers, try setting a generic pattern
-                _ => std::env::set_var(&format!("{}_API_KEY", provider.to_uppercase()), &key),
+    #[test]
+    fn test_batches_retry_and_fail_independently() {
+        let texts: Vec<String> = (0..7).map(|i| i.to_string()).collect();
+        let embed = |batch: &[String]| -> Vec<Vec<f32>> {
+            batch.iter().map(|t| vec![t.parse::<f32>().unwrap()]).collect()
+        };
+
+        // The sub-batch holding "2" fails once, and its retry succeeds
+        let settings = ClientSettings { max_retries: 1, ..Default::default() };
+        let failures = std::cell::Cell::new(0);
+        let settings_ref = &settings;
+        let (failures_ref, embed_ref) = (&failures, &embed);
+        let partial = RUNTIME.block_on(embed_in_batches(&texts, 2, None, 3, move |batch| async move {
+            let batch = &batch;
+            settings_ref
+                .run(move || async move {
+                    if batch.contains(&"2".to_string()) && failures_ref.get() == 0 {
+                        failures_ref.set(1);
+                        return Err(Error::new_message("transient"));
+                    }
+                    Ok(embed_ref(batch))
+                })
+                .await
+        }));
+        assert!(partial.failed_indices().is_empty());
+        assert_eq!(partial.into_result().unwrap(), embed(&texts));
+        assert_eq!(failures.get(), 1);
+
+        // A sub-batch that keeps failing only loses its own texts
+        let partial = RUNTIME.block_on(embed_in_batches(&texts, 2, None, 3, |batch| async move {
+            if batch.contains(&"4".to_string()) {
+                Err(Error::new_message("down"))
+            } else {
+                Ok(embed(&batch))
             }
+        }));
+        assert_eq!(partial.failed_indices(), vec![4, 5]);
+        assert_eq!(partial.embeddings[6], Some(vec![6.0]));
+        assert!(partial.into_result().is_err());
+    }
+
+    #[test]
+    fn test_role_prefixes() {
+        let mut options = HashMap::new();
+        options.insert("query_prefix".to_string(), "query: ".to_string());
+        options.insert("document_prefix".to_string(), "passage: ".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        assert_eq!(settings.prepare_input("cats", Some(InputRole::Query)), "query: cats");
+        assert_eq!(settings.prepare_input("cats", Some(InputRole::Document)), "passage: cats");
+        assert_eq!(settings.prepare_input("cats", None), "cats");
+
+        options.insert("instruction".to_string(), "Find pets. ".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        assert_eq!(settings.prepare_input("cats", Some(InputRole::Query)), "Find pets. query: cats");
+        assert_eq!(settings.prepare_input("cats", Some(InputRole::Document)), "passage: cats");
+
+        // Unconfigured clients send every role unchanged
+        let settings = ClientSettings::default();
+        assert_eq!(settings.prepare_input("cats", Some(InputRole::Query)), "cats");
+        assert_eq!(settings.prepare_input("cats", Some(InputRole::Document)), "cats");
+
+        assert_eq!("query".parse::<InputRole>().unwrap(), InputRole::Query);
+        assert!("passage".parse::<InputRole>().is_err());
+    }
+
+    #[test]
+    fn test_role_templates() {
+        let (client, stub) = stub_client(&[
+            ("query_template", "Instruct: Retrieve relevant docs\nQuery: {text}"),
+            ("document_template", "<doc>{text}</doc>"),
+        ]);
+        let settings = client.settings();
+        let options = settings.to_options();
+        assert_eq!(ClientSettings::from_options(&options).unwrap(), *settings);
+        for (role, expected) in [
+            (Some(InputRole::Query), "Instruct: Retrieve relevant docs\nQuery: cats"),
+            (Some(InputRole::Document), "<doc>cats</doc>"),
+            (None, "cats"),
+        ] {
+            client.embed_sync(&settings.prepare_input("cats", role)).unwrap();
+            let sent = stub.requests.lock().unwrap().pop().unwrap();
+            assert_eq!(sent, vec![expected.to_string()]);
         }
 
-        let client = GenAiClient::default();
+        let mut options = HashMap::new();
+        options.insert("query_template".to_string(), "Query: ".to_string());
+        let err = ClientSettings::from_options(&options).unwrap_err();
+        assert!(err.to_string().contains("{text}"), "{}", err);
+        options.insert("query_template".to_string(), "Query: {text}".to_string());
+        options.insert("instruction".to_string(), "Find pets. ".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+    }
 
-        Ok(Self {
-            client: Arc::new(client),
-            model,
-        })
+    #[test]
+    fn test_degenerate_embeddings() {
+        let (client, stub) = stub_client(&[]);
+        stub.canned.lock().unwrap().push_back(vec![0.0, 0.0]);
+        let err = client.embed_sync("cats").unwrap_err();
+        assert!(err.to_string().contains("all zeros"), "{}", err);
+        stub.canned.lock().unwrap().push_back(vec![f64::NAN, 1.0]);
+        let err = client.embed_sync("cats").unwrap_err();
+        assert!(err.to_string().contains("NaN"), "{}", err);
+        stub.canned.lock().unwrap().extend([vec![1.0, 0.0], vec![0.0, 0.0]]);
+        let err = client.embed_batch_sync(vec!["cats", "dogs"]).unwrap_err();
+        assert!(err.to_string().contains("vector 1 of the response"), "{}", err);
+        assert_eq!(stub.requests.lock().unwrap().len(), 3);
+
+        // retry_degenerate requests once more, then gives up
+        let (client, stub) = stub_client(&[("retry_degenerate", "true")]);
+        stub.canned.lock().unwrap().push_back(vec![0.0, 0.0]);
+        assert_eq!(client.embed_sync("cats").unwrap(), vec![4.0, 1.0]);
+        assert_eq!(stub.requests.lock().unwrap().len(), 2);
+        stub.canned.lock().unwrap().extend([vec![0.0, 0.0], vec![f64::INFINITY, 0.0]]);
+        assert!(client.embed_sync("cats").is_err());
+        assert_eq!(stub.requests.lock().unwrap().len(), 4);
     }
 
-    /// Generate embeddings for a single text synchronously
-    pub fn embed_sync(&self, text: &str) -> Result<Vec<f32>> {
-        let client = self.client.clone();
-        let model = self.model.clone();
-        let text = text.to_string();
+    #[test]
+    fn test_min_dims() {
+        let (client, stub) = stub_client(&[("min_dims", "3")]);
+        stub.canned.lock().unwrap().push_back(vec![1.0, 1.0, 1.0]);
+        assert_eq!(client.embed_sync("cats").unwrap(), vec![1.0, 1.0, 1.0]);
+        let err = client.embed_sync("cats").unwrap_err();
+        assert!(err.to_string().contains("fewer than min_dims 3"), "{}", err);
 
-        // Run async operation in the runtime
-        RUNTIME.block_on(async move {
-            client
-                .embed(&model, text, None)
-                .await
-                .map_err(|e| Error::new_message(format!("Embedding failed: {}", e)))
-                .and_then(|response| {
-                    response
-                        .first_embedding()
-                        .ok_or_else(|| Error::new_message("No embedding in response"))
-                        .map(|embedding| {
-                            // Convert f64 to f32 for compatibility with sqlite-vec
-                            embedding.vector().iter().map(|&v| v as f32).collect()
-                        })
-                })
-        })
+        stub.canned.lock().unwrap().extend([vec![1.0, 1.0, 1.0], vec![1.0]]);
+        let err = client.embed_batch_sync(vec!["cats", "dogs"]).unwrap_err();
+        assert!(err.to_string().contains("vector 1 of the response"), "{}", err);
+
+        let mut options = HashMap::new();
+        options.insert("min_dims".to_string(), "0".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
     }
 
-    /// Generate embeddings for multiple texts synchronously (batch processing)
-    pub fn embed_batch_sync(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>> {
-        let client = self.client.clone();
-        let model = self.model.clone();
-        let texts: Vec<String> = texts.into_iter().map(|s| s.to_string()).collect();
+    #[test]
+    fn test_batch_strategy() {
+        let texts = vec!["a", "bb", "ccc"];
+        for (options, expected_requests) in [
+            (vec![], vec![vec!["a", "bb", "ccc"]]),
+            (vec![("adapter", "ollama")], vec![vec!["a"], vec!["bb"], vec!["ccc"]]),
+            (vec![("adapter", "ollama"), ("batch_strategy", "native")], vec![vec!["a", "bb", "ccc"]]),
+            (vec![("batch_strategy", "concurrent")], vec![vec!["a"], vec!["bb"], vec!["ccc"]]),
+        ] {
+            let (client, stub) = stub_client(&options);
+            let embeddings = client.embed_batch_sync(texts.clone()).unwrap();
+            assert_eq!(embeddings, vec![vec![1.0, 1.0], vec![2.0, 1.0], vec![3.0, 1.0]]);
+            // Concurrent requests may arrive in any order
+            let mut requests = stub.requests.lock().unwrap().clone();
+            requests.sort();
+            assert_eq!(requests, expected_requests, "{:?}", options);
+        }
+        assert!("sequential".parse::<BatchStrategy>().is_err());
+    }
 
-        // Run async operation in the runtime
-        RUNTIME.block_on(async move {
-            client
-                .embed_batch(&model, texts, None)
-                .await
-                .map_err(|e| Error::new_message(format!("Batch embedding failed: {}", e)))
-                .map(|response| {
-                    response
-                        .embeddings
-                        .into_iter()
-                        .map(|embedding| {
-                            embedding.vector().iter().map(|&v| v as f32).collect()
-                        })
-                        .collect()
-                })
-        })
+    #[test]
+    fn test_call_trace() {
+        let (client, stub) = stub_client(&[]);
+        client.embed_sync("cats").unwrap();
+        stub.canned.lock().unwrap().push_back(vec![0.0, 0.0]);
+        client.embed_sync("secret text").unwrap_err();
+        client.embed_batch_sync(vec!["a", "bc"]).unwrap();
+
+        let trace = client.trace();
+        let summary: Vec<(&str, usize, bool)> =
+            trace.iter().map(|record| (record.operation, record.input_chars, record.error.is_none())).collect();
+        assert_eq!(summary, vec![("embed", 4, true), ("embed", 11, false), ("embed_batch", 3, true)]);
+        assert!(trace.windows(2).all(|pair| pair[0].sequence < pair[1].sequence));
+        assert_eq!(trace[1].input_hash, describe_inputs(["secret text"]).1);
+        assert!(!format!("{:?}", trace).contains("secret text"));
+
+        // Only the most recent calls are kept
+        let trace = CallTrace { records: Arc::default(), capacity: 2 };
+        for _ in 0..3 {
+            trace.record("embed", describe_inputs(["x"]), Instant::now(), None);
+        }
+        assert_eq!(trace.records().len(), 2);
     }
-}
 
-/// Parsed client configuration from SQL
-#[derive(Debug, PartialEq)]
-pub struct ClientConfig {
-    pub model: String,
-    pub api_key: Option<String>,
-}
+    #[test]
+    fn test_negative_cache() {
+        let (client, stub) = stub_client(&[("negative_cache_ttl_secs", "60")]);
+        stub.canned.lock().unwrap().push_back(vec![0.0, 0.0]);
+        let first = client.embed_sync("cats").unwrap_err();
+        let second = client.embed_sync("cats").unwrap_err();
+        assert_eq!(first.to_string(), second.to_string());
+        assert_eq!(stub.requests.lock().unwrap().len(), 1);
+        assert!(client.last_error().is_some());
 
-/// Helper to parse client options and extract model + api key
-pub fn parse_client_options(name: &str, options: &str) -> Result<ClientConfig> {
-    // Check if options contains JSON-like structure with key
-    if options.contains('{') && options.contains('}') {
-        // Try to parse as JSON
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(options) {
-            let model = json.get("model")
-                .or_else(|| json.get("provider"))
-                .and_then(|v| v.as_str())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| name.to_string());
+        // Other inputs are still sent
+        client.embed_sync("dogs").unwrap();
+        assert_eq!(stub.requests.lock().unwrap().len(), 2);
 
-            let api_key = json.get("key")
-                .or_else(|| json.get("api_key"))
-                .and_then(|v| v.as_str())
-                .map(|s| s.to_string());
+        // Without a TTL every failure is retried
+        let (client, stub) = stub_client(&[]);
+        stub.canned.lock().unwrap().push_back(vec![0.0, 0.0]);
+        assert!(client.embed_sync("cats").is_err());
+        assert!(client.embed_sync("cats").is_ok());
+        assert_eq!(stub.requests.lock().unwrap().len(), 2);
+    }
 
-            return Ok(ClientConfig { model, api_key });
+    #[test]
+    fn test_role_models() {
+        let (client, stub) = stub_client(&[("query_model", "stub-query"), ("document_model", "stub-doc")]);
+        for (role, expected) in [
+            (Some(InputRole::Query), "openai::stub-query"),
+            (Some(InputRole::Document), "openai::stub-doc"),
+            (None, "openai::stub"),
+        ] {
+            client.embed_role_sync("cats", role).unwrap();
+            assert_eq!(stub.models.lock().unwrap().pop().unwrap(), expected);
         }
+
+        // Roles keep the client's model until a role model is configured
+        let (client, stub) = stub_client(&[("query_prefix", "query: ")]);
+        client.embed_role_sync("cats", Some(InputRole::Query)).unwrap();
+        assert_eq!(stub.models.lock().unwrap().pop().unwrap(), "openai::stub");
+
+        let (client, stub) = stub_client(&[("query_model", "stub-query")]);
+        let err = client.embed_role_sync("cats", Some(InputRole::Document)).unwrap_err();
+        assert!(err.to_string().contains("document_model"), "{}", err);
+        assert!(stub.models.lock().unwrap().is_empty());
+        assert!(client.last_error().is_some());
     }
 
-    // Check if it's a simple "provider:key" format
-    if options.contains(':') && !options.contains("::") {
-        let parts: Vec<&str> = options.splitn(2, ':').collect();
-        if parts.len() == 2 {
-            let provider = parts[0];
-            let key = parts[1];
-            let model = format!("{}::{}", provider, name);
-            return Ok(ClientConfig {
-                model,
-                api_key: Some(key.to_string())
-            });
+    /// Serve one request on a local port with a canned JSON response,
+    /// returning the port and a handle yielding the request head and body
+    fn capture_request(response: &'static str) -> (u16, std::thread::JoinHandle<(String, String)>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                head.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn test_adapter_and_base_url() {
+        let mut options = HashMap::new();
+        options.insert("adapter".to_string(), "bedrock".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+        options.insert("adapter".to_string(), "openai".to_string());
+        options.insert("base_url".to_string(), "localhost:8080".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+
+        let response = r#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.25,0.5]}],"model":"my-model","usage":{"prompt_tokens":1,"total_tokens":1}}"#;
+        let (port, server) = capture_request(response);
+
+        options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+        options.insert("timeout_secs".to_string(), "10".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings("my-model".to_string(), Some("test-key".to_string()), settings).unwrap();
+        assert_eq!(client.model(), "openai::my-model");
+
+        assert_eq!(client.embed_sync("hello").unwrap(), vec![0.25, 0.5]);
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /v1/embeddings "), "{}", head);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(body["model"].as_str().unwrap().ends_with("my-model"));
+        assert!(body["input"] == "hello" || body["input"] == serde_json::json!(["hello"]));
+    }
+
+    #[test]
+    fn test_tei_client() {
+        assert_eq!(tei_base_url(None), "http://localhost:8080/v1/");
+        assert_eq!(tei_base_url(Some("http://tei:8080")), "http://tei:8080/v1/");
+        assert_eq!(tei_base_url(Some("http://tei:8080/embed")), "http://tei:8080/v1/");
+        assert_eq!(tei_base_url(Some("http://tei:8080/v1/")), "http://tei:8080/v1/");
+        assert_eq!(legacy_provider_to_model("tei", "BAAI/bge-small-en-v1.5").unwrap(), "openai::BAAI/bge-small-en-v1.5");
+
+        // TEI answers /v1/embeddings in OpenAI format, one entry per input
+        let response = r#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.1,0.2]},{"object":"embedding","index":1,"embedding":[0.3,0.4]}],"model":"BAAI/bge-small-en-v1.5","usage":{"prompt_tokens":2,"total_tokens":2}}"#;
+        let (port, server) = capture_request(response);
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), tei_base_url(Some(&format!("http://127.0.0.1:{}", port))));
+        let client = EmbeddingClient::with_settings(
+            legacy_provider_to_model("tei", "BAAI/bge-small-en-v1.5").unwrap(),
+            Some("test-key".to_string()),
+            ClientSettings::from_options(&options).unwrap(),
+        )
+        .unwrap();
+
+        let embeddings = client.embed_batch_sync(vec!["first", "second"]).unwrap();
+        assert_eq!(embeddings, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /v1/embeddings "), "{}", head);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["input"], serde_json::json!(["first", "second"]));
+    }
+
+    #[test]
+    fn test_dedup_batch() {
+        let texts: Vec<String> = ["a", "b", "a", "c", "b", "a"].iter().map(|t| t.to_string()).collect();
+        let (unique, positions) = dedup_texts(texts.clone());
+        assert_eq!(unique, vec!["a", "b", "c"]);
+        assert_eq!(positions, vec![0, 1, 0, 2, 1, 0]);
+
+        let sent = std::cell::RefCell::new(Vec::new());
+        let embed = |text: &str| vec![text.as_bytes()[0] as f32];
+        let partial = RUNTIME.block_on(embed_in_batches(&unique, 2, None, 1, |batch| {
+            sent.borrow_mut().extend(batch.clone());
+            async move { Ok(batch.iter().map(|t| embed(t)).collect()) }
+        }));
+        assert_eq!(*sent.borrow(), vec!["a", "b", "c"]);
+
+        let embeddings = partial.fan_out(&positions).into_result().unwrap();
+        let expected: Vec<Vec<f32>> = texts.iter().map(|t| embed(t)).collect();
+        assert_eq!(embeddings, expected);
+
+        let mut options = HashMap::new();
+        assert!(!ClientSettings::from_options(&options).unwrap().dedup);
+        options.insert("dedup".to_string(), "true".to_string());
+        assert!(ClientSettings::from_options(&options).unwrap().dedup);
+        options.insert("dedup".to_string(), "yes".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_ollama_host() {
+        assert_eq!(ollama_base_url("gpu-box"), "http://gpu-box:11434/");
+        assert_eq!(ollama_base_url("gpu-box:9999"), "http://gpu-box:9999/");
+        assert_eq!(ollama_base_url("https://ollama.example.com/"), "https://ollama.example.com/");
+
+        assert_eq!(ollama_host(None), None);
+        assert_eq!(ollama_host(Some("  ")), None);
+        let ollama_host = ollama_host(Some("gpu-box:9999"));
+        assert_eq!(
+            resolve_endpoint(None, ollama_host.as_deref(), true).as_deref(),
+            Some("http://gpu-box:9999/")
+        );
+        // Other adapters keep their default endpoint
+        assert_eq!(resolve_endpoint(None, ollama_host.as_deref(), false), None);
+        // An explicit base_url takes precedence
+        assert_eq!(
+            resolve_endpoint(Some("http://other:1/"), ollama_host.as_deref(), true).as_deref(),
+            Some("http://other:1/")
+        );
+
+        let mut options = HashMap::new();
+        options.insert("host".to_string(), "gpu-box".to_string());
+        assert_eq!(
+            ClientSettings::from_options(&options).unwrap().base_url.as_deref(),
+            Some("http://gpu-box:11434/")
+        );
+        options.insert("url".to_string(), "http://gpu-box:8000".to_string());
+        assert_eq!(
+            ClientSettings::from_options(&options).unwrap().base_url.as_deref(),
+            Some("http://gpu-box:8000/")
+        );
+    }
+
+    #[test]
+    fn test_batch_input_type() {
+        assert!(validate_input_type("cohere", "search_query").is_ok());
+        assert!(validate_input_type("cohere", "query").is_err());
+        assert!(validate_input_type("voyage", "document").is_ok());
+        assert!(validate_input_type("openai", "search_query").is_err());
+
+        let (port, server) = capture_request(r#"{"id":"1","embeddings":[[0.25],[0.5]],"texts":["a","b"]}"#);
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+        options.insert("timeout_secs".to_string(), "10".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings(
+            "cohere::embed-english-v3.0".to_string(),
+            Some("test-key".to_string()),
+            settings,
+        )
+        .unwrap();
+
+        assert!(client.embed_batch_typed_sync(vec!["a", "b"], "query").is_err());
+        // Only the request body matters here, not how the response parses
+        let _ = client.embed_batch_typed_sync(vec!["a", "b"], "search_query").unwrap();
+        let (_, body) = server.join().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["input_type"], "search_query");
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(Some(2), Duration::from_millis(100));
+        let down = || Err::<(), _>(Error::new_message("connection refused"));
+
+        assert!(!breaker.call(down).unwrap_err().to_string().contains("Circuit breaker"));
+        assert!(!breaker.call(down).unwrap_err().to_string().contains("Circuit breaker"));
+        // Threshold crossed: the request is not attempted
+        let mut attempted = false;
+        let err = breaker.call(|| {
+            attempted = true;
+            Ok(())
+        });
+        assert!(err.unwrap_err().to_string().contains("Circuit breaker open"));
+        assert!(!attempted);
+
+        // Half-open after the cooldown: a failed probe reopens the circuit
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(breaker.call(down).is_err());
+        assert!(breaker.check().is_err());
+
+        // A successful probe closes it
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(breaker.call(|| Ok(())).is_ok());
+        assert!(breaker.call(down).is_err());
+        assert!(breaker.check().is_ok());
+
+        let disabled = CircuitBreaker::new(None, Duration::from_millis(100));
+        for _ in 0..5 {
+            assert!(disabled.call(down).is_err());
         }
+        assert!(disabled.check().is_ok());
     }
 
-    // Legacy format: just provider name
-    let model = match options {
-        "openai" => format!("openai::{}", name),
-        "gemini" => format!("gemini::{}", name),
-        "cohere" => format!("cohere::{}", name),
-        "anthropic" => format!("anthropic::{}", name),
-        "ollama" => format!("ollama::{}", name),
-        "groq" => format!("groq::{}", name),
-        // If it already contains "::" assume it's a full model identifier
-        s if s.contains("::") => s.to_string(),
-        // Otherwise, assume it's a model name that should work with default provider
-        _ => options.to_string(),
-    };
+    #[test]
+    fn test_breaker_fast_fails_down_provider() {
+        // Bind and release a port so nothing is listening on it
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+        options.insert("breaker_threshold".to_string(), "2".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings(
+            "openai::text-embedding-3-small".to_string(),
+            Some("test-key".to_string()),
+            settings,
+        )
+        .unwrap();
 
-    Ok(ClientConfig { model, api_key: None })
-}
+        assert!(client.embed_sync("a").is_err());
+        assert!(client.embed_batch_sync(vec!["a", "b"]).is_err());
+        let err = client.embed_sync("a").unwrap_err();
+        assert!(err.to_string().contains("Circuit breaker open"));
+        let partial = client.embed_batch_partial_sync(vec!["a", "b"]);
+        assert_eq!(partial.failed_indices(), vec![0, 1]);
+        assert!(client.last_error().unwrap().0.contains("Circuit breaker open"));
+    }
 
-/// Legacy compatibility: Map old provider names to genai format
-pub fn legacy_provider_to_model(provider: &str, model_name: &str) -> String {
-    match provider {
-        "openai" => format!("openai::{}", model_name),
-        "nomic" => format!("openai::{}", model_name), // Nomic uses OpenAI-compatible API
-        "cohere" => format!("cohere::{}", model_name),
-        "jina" => format!("openai::{}", model_name), // Jina uses OpenAI-compatible API
-        "mixedbread" => format!("openai::{}", model_name), // MixedBread uses OpenAI-compatible API
-        "ollama" => format!("ollama::{}", model_name),
-        "llamafile" => format!("ollama::{}", model_name), // Llamafile is Ollama-compatible
-        _ => model_name.to_string(),
+    #[test]
+    fn test_deadline() {
+        // A provider that accepts the connection but never answers in time
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let held: Vec<_> = listener.incoming().take(2).collect();
+            std::thread::sleep(Duration::from_secs(5));
+            drop(held);
+        });
+
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+        options.insert("deadline_ms".to_string(), "200".to_string());
+        options.insert("max_retries".to_string(), "3".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings(
+            "openai::text-embedding-3-small".to_string(),
+            Some("test-key".to_string()),
+            settings,
+        )
+        .unwrap();
+
+        let started = Instant::now();
+        let err = client.embed_sync("slow").unwrap_err();
+        assert!(err.to_string().contains("busy"), "{}", err);
+        let partial = client.embed_batch_partial_sync(vec!["a", "b"]);
+        assert_eq!(partial.failed_indices(), vec![0, 1]);
+        assert!(partial.first_error.unwrap().to_string().contains("busy"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        options.insert("deadline_ms".to_string(), "0".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_debug_request() {
+        for (model, path, input_field) in [
+            ("openai::text-embedding-3-small", "embeddings", "input"),
+            ("ollama::nomic-embed-text", "api/embed", "input"),
+            ("cohere::embed-english-v3.0", "embed", "texts"),
+            ("gemini::text-embedding-004", "models/text-embedding-004:embedContent", "content"),
+        ] {
+            let client = EmbeddingClient::new(model.to_string(), Some("debug-secret".to_string())).unwrap();
+            let debug = client.debug_request("hello debug", None).unwrap();
+
+            assert_eq!(debug["method"], "POST", "{}", model);
+            assert_eq!(debug["path"], path, "{}", model);
+            let body = debug["body"].to_string();
+            assert!(body.contains(model.split_once("::").unwrap().1), "{}: {}", model, body);
+            assert!(debug["body"].get(input_field).is_some(), "{}: {}", model, body);
+            assert!(body.contains("hello debug"), "{}: {}", model, body);
+            assert!(!debug.to_string().contains("debug-secret"), "{}: {}", model, debug);
+        }
+        let gemini = EmbeddingClient::new("gemini::m".to_string(), None).unwrap();
+        assert_eq!(gemini.debug_request("hi", None).unwrap()["headers"]["x-goog-api-key"], "[REDACTED]");
+
+        // input_as_array sends a single text the way batches are sent
+        for (input_as_array, input) in [(false, serde_json::json!("hello")), (true, serde_json::json!(["hello"]))] {
+            let settings = ClientSettings { input_as_array, ..Default::default() };
+            let client = EmbeddingClient::with_settings(
+                "openai::text-embedding-3-small".to_string(),
+                Some("debug-secret".to_string()),
+                settings,
+            )
+            .unwrap();
+            assert_eq!(client.debug_request("hello", None).unwrap()["body"]["input"], input);
+        }
+
+        // The input is prepared and the role's model chosen as rembed does
+        let mut options = HashMap::new();
+        options.insert("query_prefix".to_string(), "query: ".to_string());
+        options.insert("query_model".to_string(), "text-embedding-3-large".to_string());
+        options.insert("document_model".to_string(), "text-embedding-3-small".to_string());
+        options.insert("encoding_format".to_string(), "float".to_string());
+        let client = EmbeddingClient::with_settings(
+            "openai::text-embedding-3-small".to_string(),
+            None,
+            ClientSettings::from_options(&options).unwrap(),
+        )
+        .unwrap();
+        let debug = client.debug_request("hello", Some(InputRole::Query)).unwrap();
+        assert_eq!(debug["model"], "openai::text-embedding-3-large");
+        assert_eq!(debug["body"]["input"], "query: hello");
+        assert_eq!(debug["body"]["encoding_format"], "float");
+        assert_eq!(client.debug_request("hello", None).unwrap()["body"]["input"], "hello");
+    }
 
     #[test]
-    fn test_parse_client_options() {
-        let config = parse_client_options("text-embedding-3-small", "openai").unwrap();
-        assert_eq!(config.model, "openai::text-embedding-3-small");
-        assert_eq!(config.api_key, None);
+    fn test_require_tls() {
+        let client_with = |base_url: &str| {
+            let mut options = HashMap::new();
+            options.insert("require_tls".to_string(), "true".to_string());
+            options.insert("base_url".to_string(), base_url.to_string());
+            let settings = ClientSettings::from_options(&options).unwrap();
+            EmbeddingClient::with_settings("openai::text-embedding-3-small".to_string(), None, settings)
+        };
 
-        let config = parse_client_options("embedding-001", "gemini").unwrap();
-        assert_eq!(config.model, "gemini::embedding-001");
-        assert_eq!(config.api_key, None);
+        let err = client_with("http://embeddings.example.com/v1").err().unwrap();
+        assert!(err.to_string().contains("require_tls"));
+        assert!(client_with("http://10.0.0.5:8080/v1").is_err());
+        assert!(client_with("https://embeddings.example.com/v1").is_ok());
+        assert!(client_with("http://localhost:8080/v1").is_ok());
+        assert!(client_with("http://127.0.0.1:8080/v1").is_ok());
+        assert!(client_with("http://[::1]:8080/v1").is_ok());
+        assert!(client_with("HTTPS://embeddings.example.com/v1").is_ok());
 
-        // Test passthrough for full model identifiers
-        let config = parse_client_options("ignored", "openai::ada-002").unwrap();
-        assert_eq!(config.model, "openai::ada-002");
-        assert_eq!(config.api_key, None);
+        // Hosts that only look local are still remote
+        assert!(client_with("http://127.evil.com/v1").is_err());
+        assert!(client_with("http://127.0.0.1@evil.com/v1").is_err());
+        assert!(client_with("http://127.0.0.1:x@evil.com/v1").is_err());
+        assert!(client_with("http://localhost.evil.com/v1").is_err());
+        assert!(client_with("HTTP://evil.com/v1").is_err());
+        assert!(client_with("Http://embeddings.example.com/v1").is_err());
+
+        // Without the option plaintext remote endpoints are still accepted
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), "http://embeddings.example.com/v1".to_string());
+        options.insert("require_tls".to_string(), "false".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        assert!(EmbeddingClient::with_settings("openai::m".to_string(), None, settings).is_ok());
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("a b c d e"), 5);
+
+        let texts: Vec<String> = ["abcdefgh", "abcdefgh", "a b c d e"].iter().map(|t| t.to_string()).collect();
+        let mut options = HashMap::new();
+        options.insert("batch_size".to_string(), "2".to_string());
+        options.insert("price_per_1k".to_string(), "0.5".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings("openai::m".to_string(), None, settings).unwrap();
+        let estimate = client.estimate_cost(texts.clone());
+        assert_eq!(estimate["texts"], 3);
+        assert_eq!(estimate["tokens"], 9);
+        assert_eq!(estimate["requests"], 2);
+        assert_eq!(estimate["estimated_cost"], 0.0045);
+
+        // Deduplicated texts are only sent, and counted, once
+        options.insert("dedup".to_string(), "true".to_string());
+        options.remove("price_per_1k");
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings("openai::m".to_string(), None, settings).unwrap();
+        let estimate = client.estimate_cost(texts);
+        assert_eq!(estimate["tokens"], 7);
+        assert_eq!(estimate["requests"], 1);
+        assert!(estimate["estimated_cost"].is_null());
+
+        options.insert("price_per_1k".to_string(), "-1".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_batch_count_mismatch() {
+        assert!(check_embedding_count(vec![vec![0.1], vec![0.2]], 2).is_ok());
+        assert!(check_embedding_count(Vec::new(), 0).is_ok());
+        let err = check_embedding_count(Vec::new(), 3).unwrap_err();
+        assert!(err.to_string().contains("returned 0 embeddings for 3 inputs"), "{}", err);
+
+        let response = r#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.1,0.2]}],"model":"short","usage":{"prompt_tokens":1,"total_tokens":1}}"#;
+        let (port, server) = capture_request(response);
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings("openai::short".to_string(), Some("test-key".to_string()), settings).unwrap();
+
+        let partial = client.embed_batch_partial_sync(vec!["first", "second"]);
+        server.join().unwrap();
+        assert_eq!(partial.embeddings, vec![None, None]);
+        let err = partial.first_error.unwrap();
+        assert!(err.to_string().contains("returned 1 embeddings for 2 inputs"), "{}", err);
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        let all: TextNormalization = "lowercase, collapse_whitespace,trim".parse().unwrap();
+        assert_eq!(all.apply("  Hello \t\n World  "), "hello world");
+        let collapse: TextNormalization = "collapse_whitespace".parse().unwrap();
+        assert_eq!(collapse.apply("  Hello \t World  "), " Hello World ");
+        assert_eq!(TextNormalization::default().apply(" As Is "), " As Is ");
+        assert!("stem".parse::<TextNormalization>().is_err());
+
+        let mut options = HashMap::new();
+        options.insert("normalize_text".to_string(), "lowercase,collapse_whitespace,trim".to_string());
+        options.insert("dedup".to_string(), "true".to_string());
+
+        let response = r#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.25,0.5]}],"model":"m","usage":{"prompt_tokens":1,"total_tokens":1}}"#;
+        let client_at = |port: u16| {
+            let mut options = options.clone();
+            options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+            let settings = ClientSettings::from_options(&options).unwrap();
+            EmbeddingClient::with_settings("openai::m".to_string(), Some("test-key".to_string()), settings).unwrap()
+        };
+
+        let (port, server) = capture_request(response);
+        client_at(port).embed_sync("  Hello\n\nWorld ").unwrap();
+        let (_, body) = server.join().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(body["input"] == "hello world" || body["input"] == serde_json::json!(["hello world"]), "{}", body);
+
+        // Inputs differing only in case and whitespace share one embedding
+        let (port, server) = capture_request(response);
+        let embeddings = client_at(port).embed_batch_sync(vec!["Hello  World", " hello world"]).unwrap();
+        let (_, body) = server.join().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["input"], serde_json::json!(["hello world"]));
+        assert_eq!(embeddings, vec![vec![0.25, 0.5], vec![0.25, 0.5]]);
+    }
+
+    #[test]
+    fn test_embed_with_usage() {
+        let response = r#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.25,0.5]}],"model":"m","usage":{"prompt_tokens":7,"total_tokens":7}}"#;
+        let (port, server) = capture_request(response);
+        let mut options = HashMap::new();
+        options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+        let settings = ClientSettings::from_options(&options).unwrap();
+        let client = EmbeddingClient::with_settings("openai::m".to_string(), Some("test-key".to_string()), settings).unwrap();
+
+        let (embedding, tokens) = client.embed_with_usage_sync("hello").unwrap();
+        server.join().unwrap();
+        assert_eq!(embedding, vec![0.25, 0.5]);
+        assert_eq!(tokens, Some(7));
+    }
+
+    #[test]
+    fn test_embedding_index() {
+        let response = r#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.1,0.2]},{"object":"embedding","index":1,"embedding":[0.3,0.4]}],"model":"multi","usage":{"prompt_tokens":1,"total_tokens":1}}"#;
+        let client_with = |port: u16, index: &str| {
+            let mut options = HashMap::new();
+            options.insert("base_url".to_string(), format!("http://127.0.0.1:{}/v1", port));
+            options.insert("embedding_index".to_string(), index.to_string());
+            let settings = ClientSettings::from_options(&options).unwrap();
+            EmbeddingClient::with_settings("openai::multi".to_string(), Some("test-key".to_string()), settings).unwrap()
+        };
+
+        let (port, server) = capture_request(response);
+        assert_eq!(client_with(port, "1").embed_sync("hello").unwrap(), vec![0.3, 0.4]);
+        server.join().unwrap();
+
+        let (port, server) = capture_request(response);
+        assert_eq!(client_with(port, "0").embed_sync("hello").unwrap(), vec![0.1, 0.2]);
+        server.join().unwrap();
+
+        let (port, server) = capture_request(response);
+        let err = client_with(port, "2").embed_sync("hello").unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{}", err);
+        server.join().unwrap();
+
+        let mut options = HashMap::new();
+        options.insert("embedding_index".to_string(), "-1".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_target_dim() {
+        let mut options = HashMap::new();
+        options.insert("target_dim".to_string(), "0".to_string());
+        assert!(ClientSettings::from_options(&options).is_err());
+
+        options.insert("target_dim".to_string(), "3".to_string());
+        let settings = ClientSettings::from_options(&options).unwrap();
+        assert_eq!(settings.fit_dimensions(vec![0.6, 0.8]).len(), 3);
+        assert_eq!(settings.fit_dimensions(vec![0.5, 0.5, 0.5, 0.5]).len(), 3);
+        assert_eq!(ClientSettings::default().fit_dimensions(vec![0.6, 0.8]), vec![0.6, 0.8]);
+    }
+
+    #[test]
+    fn test_settings_options_round_trip() {
+        let mut options = HashMap::new();
+        for (key, value) in [
+            ("encoding_format", "base64"),
+            ("timeout_secs", "5"),
+            ("max_retries", "2"),
+            ("precision", "f16"),
+            ("f32_round", "truncate"),
+            ("output_format", "prefixed"),
+            ("endian", "big"),
+            ("chunk_size", "100"),
+            ("chunk_overlap", "10"),
+            ("batch_size", "64"),
+            ("max_concurrency", "2"),
+            ("dedup", "true"),
+            ("query_prefix", "query: "),
+            ("document_prefix", "passage: "),
+            ("instruction", "Represent the question for retrieval: "),
+            ("adapter", "openai"),
+            ("base_url", "http://localhost:8080/v1/"),
+            ("target_dim", "256"),
+            ("breaker_threshold", "5"),
+            ("breaker_cooldown_secs", "60"),
+            ("negative_cache_ttl_secs", "30"),
+            ("deadline_ms", "1500"),
+            ("require_tls", "true"),
+            ("price_per_1k", "0.02"),
+            ("retry_degenerate", "true"),
+            ("min_dims", "256"),
+            ("validate_model", "true"),
+            ("input_as_array", "true"),
+            ("max_batch_tokens", "8000"),
+            ("batch_strategy", "concurrent"),
+            ("batch_output", "objects"),
+            ("query_model", "openai::text-embedding-3-small"),
+            ("document_model", "openai::text-embedding-3-large"),
+            ("fallback", "backup"),
+            ("embedding_index", "1"),
+            ("normalize_text", "lowercase,trim"),
+        ] {
+            options.insert(key.to_string(), value.to_string());
+        }
+        let settings = ClientSettings::from_options(&options).unwrap();
+        assert_eq!(settings.to_options(), options);
+        assert_eq!(ClientSettings::from_options(&settings.to_options()).unwrap(), settings);
+        assert!(ClientSettings::default().to_options().is_empty());
+    }
+
+    #[test]
+    fn test_last_error_tracking() {
+        let last_error = LastError::default();
+        let shared = last_error.clone();
+
+        assert!(last_error.track::<()>(Err(Error::new_message("provider down"))).is_err());
+        let (message, timestamp) = shared.get().expect("error recorded");
+        assert!(message.contains("provider down"));
+        assert!(timestamp > 0);
+
+        assert!(last_error.track(Ok(1)).is_ok());
+        assert_eq!(shared.get(), None);
     }
 
     #[test]
     fn test_legacy_provider_mapping() {
         assert_eq!(
-            legacy_provider_to_model("openai", "text-embedding-3-small"),
+            legacy_provider_to_model("openai", "text-embedding-3-small").unwrap(),
             "openai::text-embedding-3-small"
         );
         assert_eq!(
-            legacy_provider_to_model("ollama", "nomic-embed-text"),
+            legacy_provider_to_model("ollama", "nomic-embed-text").unwrap(),
             "ollama::nomic-embed-text"
         );
+        assert_eq!(legacy_provider_to_model("custom", "ollama::x").unwrap(), "ollama::x");
+    }
+
+    #[test]
+    fn test_no_double_provider_prefix() {
+        assert_eq!(
+            legacy_provider_to_model("openai", "openai::text-embedding-3-small").unwrap(),
+            "openai::text-embedding-3-small"
+        );
+        // Formats that speak another provider's API accept that provider's prefix
+        assert_eq!(
+            legacy_provider_to_model("jina", "openai::jina-embeddings-v2").unwrap(),
+            "openai::jina-embeddings-v2"
+        );
+        let err = legacy_provider_to_model("openai", "ollama::nomic-embed-text").unwrap_err();
+        assert!(err.to_string().contains("conflicts"));
+
+        assert_eq!(
+            parse_client_options("openai::text-embedding-3-small", "openai").unwrap().model,
+            "openai::text-embedding-3-small"
+        );
+        assert_eq!(
+            parse_client_options("openai::text-embedding-3-small", "openai:sk-key").unwrap().model,
+            "openai::text-embedding-3-small"
+        );
+        assert!(parse_client_options("cohere::embed-english-v3.0", "openai").is_err());
+    }
+
+    #[test]
+    fn test_model_aliases() {
+        assert_eq!(resolve_model_alias("ada"), "openai::text-embedding-ada-002");
+        assert_eq!(resolve_model_alias("unregistered-model"), "unregistered-model");
+        assert_eq!(legacy_provider_to_model("openai", "ada-002").unwrap(), "openai::text-embedding-ada-002");
+        // The format still picks the provider for aliased names
+        assert_eq!(legacy_provider_to_model("nomic", "nomic").unwrap(), "openai::nomic-embed-text");
+
+        register_model_alias("test-alias-small", "openai::text-embedding-3-small").unwrap();
+        assert_eq!(
+            parse_client_options("x", "test-alias-small").unwrap().model,
+            "openai::text-embedding-3-small"
+        );
+        assert_eq!(
+            parse_client_options("x", r#"{"model": "test-alias-small"}"#).unwrap().model,
+            "openai::text-embedding-3-small"
+        );
+        assert!(register_model_alias("test-alias-bad", "text-embedding-3-small").is_err());
+        assert!(register_model_alias(" ", "openai::text-embedding-3-small").is_err());
     }
 }
\ No newline at end of file
@@ -1,19 +1,33 @@
 // New lib.rs using genai - complete implementation
+mod chunking;
 mod genai_client;
 mod multimodal;
 mod mock_provider;
+mod runtime;
+mod telemetry;
+mod vector;
 
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU8, Ordering};
 
-use genai_client::{EmbeddingClient, parse_client_options, legacy_provider_to_model};
-use multimodal::MultimodalClient;
+use genai_client::{
+    health_check, BatchOutput, ClientSettings, EmbeddingClient, InputRole, json_to_options, parse_client_options, legacy_provider_to_model,
+    provider_from_model, register_model_alias, resolve_model_alias, tei_base_url, trace_capacity, PartialBatch,
+    TraceRecord,
+};
+use multimodal::{MultimodalClient, VisionConfig};
+use runtime::RUNTIME;
+use vector::{Endian, F32Rounding, OutputFormat, Precision};
 use sqlite_loadable::{
-    api, define_scalar_function, define_scalar_function_with_aux, define_virtual_table_writeablex,
+    api, define_scalar_function, define_scalar_function_with_aux, define_table_function,
+    define_virtual_table_writeablex, ext,
     prelude::*, Error, Result,
 };
-use sqlite_loadable::table::{UpdateOperation, IndexInfo, VTab, VTabArguments, VTabCursor, VTabWriteable};
+use sqlite_loadable::table::{
+    ConstraintOperator, UpdateOperation, IndexInfo, VTab, VTabArguments, VTabCursor, VTabWriteable,
+};
 use sqlite_loadable::api::ValueType;
 use sqlite_loadable::BestIndexError;
 use std::{marker::PhantomData, mem, os::raw::c_int};
@@ -22,9 +36,105 @@ use base64;
 use serde_json;
 
 const FLOAT32_VECTOR_SUBTYPE: u8 = 223;
+/// Subtype for half precision blobs, outside sqlite-vec's float32/bit/int8 range
+const FLOAT16_VECTOR_SUBTYPE: u8 = 226;
+/// Subtype for precision=f64 blobs, next to the half precision one
+const FLOAT64_VECTOR_SUBTYPE: u8 = 227;
+const VECTOR_SUBTYPE_ENV: &str = "REMBED_VECTOR_SUBTYPE";
+/// Env var naming the client used by the one-argument rembed(text) form
+const DEFAULT_CLIENT_ENV: &str = "REMBED_DEFAULT_CLIENT";
+/// Client name used by rembed(text) when REMBED_DEFAULT_CLIENT is unset
+const DEFAULT_CLIENT_NAME: &str = "default";
+/// Largest file rembed_embed_file will read (8 MiB)
+const MAX_EMBED_FILE_BYTES: u64 = 8 * 1024 * 1024;
+/// Env var that lets inserting into rembed_clients replace a client of the same name
+const REPLACE_CLIENTS_ENV: &str = "REMBED_REPLACE_CLIENTS";
 const CLIENT_OPTIONS_POINTER_NAME: &[u8] = b"sqlite-rembed-client-options\0";
 const MULTIMODAL_CLIENT_OPTIONS_POINTER_NAME: &[u8] = b"sqlite-rembed-multimodal-client-options\0";
 
+/// Subtype attached to embedding blobs, configured at init time (0 disables tagging)
+static VECTOR_SUBTYPE: AtomicU8 = AtomicU8::new(FLOAT32_VECTOR_SUBTYPE);
+
+/// Parse the REMBED_VECTOR_SUBTYPE value, defaulting to the sqlite-vec subtype
+fn parse_vector_subtype(value: Option<&str>) -> Result<u8> {
+    match value.map(str::trim) {
+        None | Some("") => Ok(FLOAT32_VECTOR_SUBTYPE),
+        Some(value) => match value.parse::<u8>() {
+            Ok(subtype @ (FLOAT16_VECTOR_SUBTYPE | FLOAT64_VECTOR_SUBTYPE)) => Err(Error::new_message(format!(
+                "{}={} clashes with the subtype of f16 or f64 blobs",
+                VECTOR_SUBTYPE_ENV, subtype
+            ))),
+            Ok(subtype) => Ok(subtype),
+            Err(_) => Err(Error::new_message(format!(
+                "{} must be an integer between 0 and 255, got '{}'",
+                VECTOR_SUBTYPE_ENV, value
+            ))),
+        },
+    }
+}
+
+/// Return an embedding as a blob in the given precision, tagged with its subtype
+fn result_embedding(context: *mut sqlite3_context, embedding: &[f32], precision: Precision) {
+    result_vector_blob(context, &vector::encode(embedding, precision), precision);
+}
+
+/// Return encoded vector bytes, tagged with the subtype of their precision.
+/// REMBED_VECTOR_SUBTYPE only replaces the float32 subtype; 0 disables tagging
+/// for every precision
+fn result_vector_blob(context: *mut sqlite3_context, blob: &[u8], precision: Precision) {
+    api::result_blob(context, blob);
+    let subtype = VECTOR_SUBTYPE.load(Ordering::Relaxed);
+    if subtype != 0 {
+        match precision {
+            Precision::F32 => api::result_subtype(context, subtype),
+            Precision::F16 => api::result_subtype(context, FLOAT16_VECTOR_SUBTYPE),
+            Precision::F64 => api::result_subtype(context, FLOAT64_VECTOR_SUBTYPE),
+        }
+    }
+}
+
+/// Whether a blob argument carries the precision=f64 subtype
+fn is_f64_vector(value: &*mut sqlite3_value) -> bool {
+    api::value_subtype(value) == FLOAT64_VECTOR_SUBTYPE.into()
+}
+
+/// Return a client's embedding as a blob in its precision, output format
+/// and byte order
+fn result_client_embedding(context: *mut sqlite3_context, embedding: &[f32], format: (Precision, OutputFormat, Endian)) {
+    result_client_blob(context, vector::encode(embedding, format.0), embedding.len(), format);
+}
+
+/// Encoded embedding bytes in a client's output format and byte order
+fn client_blob_bytes(
+    body: Vec<u8>,
+    dimensions: usize,
+    (precision, output_format, endian): (Precision, OutputFormat, Endian),
+) -> Vec<u8> {
+    let body = vector::reorder(body, precision, endian);
+    match output_format {
+        OutputFormat::Raw => body,
+        OutputFormat::Prefixed => vector::with_prefix(dimensions, &body),
+    }
+}
+
+/// Return encoded embedding bytes in a client's output format and byte
+/// order; dimension-prefixed and big-endian blobs are not sqlite-vec vectors
+/// and carry no subtype
+fn result_client_blob(
+    context: *mut sqlite3_context,
+    body: Vec<u8>,
+    dimensions: usize,
+    format: (Precision, OutputFormat, Endian),
+) {
+    let blob = client_blob_bytes(body, dimensions, format);
+    match format {
+        (precision, OutputFormat::Raw, endian) if endian != Endian::Big => {
+            result_vector_blob(context, &blob, precision)
+        }
+        _ => api::result_blob(context, &blob),
+    }
+}
+
 pub fn rembed_version(context: *mut sqlite3_context, _values: &[*mut sqlite3_value]) -> Result<()> {
     api::result_text(context, format!("v{}-genai", env!("CARGO_PKG_VERSION")))?;
     Ok(())
@@ -55,6 +165,10 @@ Backend: genai v0.4.0-alpha.4
 }
 
 
+/// Build a client from key/value pairs, returned as a pointer value for
+/// `INSERT INTO rembed_clients`. result_pointer boxes the client and registers
+/// a destructor, so SQLite frees it once the value is released whether or not
+/// an INSERT consumed it; the vtab clones the client rather than taking it.
 pub fn rembed_client_options(
     context: *mut sqlite3_context,
     values: &[*mut sqlite3_value],
@@ -84,24 +198,25 @@ pub fn rembed_client_options(
             // Legacy compatibility: convert old format to genai model
             let model_name = options.get("model")
                 .ok_or_else(|| Error::new_message("'model' option is required for vision model"))?;
-            legacy_provider_to_model(&format, model_name)
+            legacy_provider_to_model(&format, model_name)?
         } else if let Some(model) = options.get("model") {
-            model.clone()
+            resolve_model_alias(model)
         } else {
             return Err(Error::new_message("'model' or 'format' key is required for vision model"));
         };
 
-        let multimodal_client = MultimodalClient::new(vision_model, embedding_model.clone())?;
+        let multimodal_client = MultimodalClient::new(vision_model, embedding_model.clone())?
+            .with_vision_config(VisionConfig::from_options(&options)?);
         api::result_pointer(context, MULTIMODAL_CLIENT_OPTIONS_POINTER_NAME, multimodal_client);
     } else {
         // Create regular EmbeddingClient
-        let model = if let Some(format) = format {
+        let model = if let Some(format) = &format {
             // Legacy compatibility: convert old format to genai model
             let model_name = options.get("model")
                 .ok_or_else(|| Error::new_message("'model' option is required"))?;
-            legacy_provider_to_model(&format, model_name)
+            legacy_provider_to_model(&format, model_name)?
         } else if let Some(model) = options.get("model") {
-            model.clone()
+            resolve_model_alias(model)
         } else {
             return Err(Error::new_message("'model' or 'format' key is required"));
         };
@@ -109,20 +224,239 @@ pub fn rembed_client_options(
         let api_key = options.get("key").cloned()
             .or_else(|| options.get("api_key").cloned());
 
-        let client = EmbeddingClient::new(model, api_key)?;
+        // A TEI server is addressed by its root url rather than an adapter base_url
+        if format.as_deref() == Some("tei") {
+            let url = options.remove("url").or_else(|| options.remove("base_url"));
+            options.insert("base_url".to_string(), tei_base_url(url.as_deref()));
+        }
+
+        let client = EmbeddingClient::with_settings(model, api_key, ClientSettings::from_options(&options)?)?;
         api::result_pointer(context, CLIENT_OPTIONS_POINTER_NAME, client);
     }
 
     Ok(())
 }
 
+// Map a friendly model name to a canonical "provider::model" string
+pub fn rembed_register_alias(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let alias = api::value_text(&values[0])?;
+    let canonical = api::value_text(&values[1])?;
+    register_model_alias(alias, canonical)?;
+    api::result_null(context);
+    Ok(())
+}
+
+/// Name of the client used when rembed is called with only the input text
+fn default_client_name() -> String {
+    std::env::var(DEFAULT_CLIENT_ENV)
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_CLIENT_NAME.to_string())
+}
+
+/// An embedding of rembed-style arguments as the provider returned it, with
+/// the rounding of the client that served it and the primary client's format
+struct TextEmbedding {
+    values: Vec<f64>,
+    rounding: F32Rounding,
+    format: (Precision, OutputFormat, Endian),
+}
+
+impl TextEmbedding {
+    fn narrowed(&self) -> Vec<f32> {
+        vector::downcast(&self.values, self.rounding)
+    }
+
+    /// Bytes in the client's precision; precision=f64 keeps the provider's
+    /// values rather than widening f32
+    fn body(&self) -> Vec<u8> {
+        match self.format.0 {
+            Precision::F64 => vector::encode_wide(&self.values, Precision::F64, self.rounding),
+            precision => vector::encode(&self.narrowed(), precision),
+        }
+    }
+
+    /// Base64 of the blob rembed would return for this embedding
+    fn base64(&self) -> String {
+        use base64::Engine as _;
+        let blob = client_blob_bytes(self.body(), self.values.len(), self.format);
+        base64::engine::general_purpose::STANDARD.encode(blob)
+    }
+}
+
+/// Text of a TEXT value, or of a BLOB holding UTF-8 text
+fn value_input_text(value: &*mut sqlite3_value) -> Result<&str> {
+    match api::value_type(value) {
+        ValueType::Blob => std::str::from_utf8(api::value_blob(value))
+            .map_err(|e| Error::new_message(format!("Input BLOB is not valid UTF-8 text: {}", e))),
+        _ => api::value_text(value),
+    }
+}
+
+/// Role named by an optional, possibly NULL, role argument
+fn role_arg(value: Option<&*mut sqlite3_value>) -> Result<Option<InputRole>> {
+    match value {
+        Some(value) if !matches!(api::value_type(value), ValueType::Null) => {
+            Ok(Some(api::value_text(value)?.parse::<InputRole>()?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Embed the input of rembed-style arguments ([client,] text [, role]) with the
+/// named or default client
+fn embed_text_args(
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<TextEmbedding> {
+    let clients_map = clients.borrow();
+    let (client_name, client, input) = if values.len() == 1 {
+        let client_name = default_client_name();
+        let client = clients_map.get(&client_name).ok_or_else(|| {
+            Error::new_message(format!(
+                "No default client configured: register a client named '{}' with rembed_clients \
+                 or set {} to an existing client name.",
+                client_name, DEFAULT_CLIENT_ENV
+            ))
+        })?;
+        let input = value_input_text(&values[0])?;
+        (client_name, client, input)
+    } else {
+        let client_name = api::value_text(&values[0])?;
+        let client = clients_map.get(client_name).ok_or_else(|| {
+            Error::new_message(format!(
+                "Client with name {} was not registered with rembed_clients.",
+                client_name
+            ))
+        })?;
+        (client_name.to_owned(), client, value_input_text(&values[1])?)
+    };
+    // The optional third argument selects the query or document prefix
+    let role = role_arg(values.get(2))?;
+
+    let (values, rounding) = embed_with_fallback(&clients_map, &client_name, client, input, role)?;
+    Ok(TextEmbedding { values, rounding, format: client.blob_format() })
+}
+
+/// Embed input with a client, moving along its `fallback` chain while
+/// clients fail after their retries. Each client applies its own role
+/// prefixes and role models; the first client records which one served
+/// the result, returned with that client's f32 rounding.
+fn embed_with_fallback(
+    clients_map: &HashMap<String, EmbeddingClient>,
+    name: &str,
+    primary: &EmbeddingClient,
+    input: &str,
+    role: Option<InputRole>,
+) -> Result<(Vec<f64>, F32Rounding)> {
+    let mut chain = vec![name.to_owned()];
+    let mut client = primary;
+    loop {
+        // Generate embedding synchronously (blocks on async internally)
+        let error = match client.embed_role_wide_sync(&client.settings().prepare_input(input, role), role) {
+            Ok(embedding) => {
+                primary.record_served_by(chain.last().expect("chain starts with the primary"));
+                return Ok((embedding, client.settings().f32_round));
+            }
+            Err(error) => error,
+        };
+        // A cycle registered out of order ends the chain rather than looping
+        let next = match &client.settings().fallback {
+            Some(next) if !chain.contains(next) => next,
+            _ => return Err(error),
+        };
+        client = clients_map.get(next).ok_or_else(|| {
+            Error::new_message(format!(
+                "{} (fallback client {} was not registered with rembed_clients)",
+                error, next
+            ))
+        })?;
+        chain.push(next.clone());
+    }
+}
+
+/// Whether the env var lets inserts replace already registered clients
+fn replace_clients_from_env() -> bool {
+    std::env::var(REPLACE_CLIENTS_ENV)
+        .map(|value| matches!(value.trim(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// Fail if name is already registered as either kind of client, unless
+/// replacing clients is allowed
+fn check_client_name(
+    name: &str,
+    clients: &HashMap<String, EmbeddingClient>,
+    multimodal_clients: &HashMap<String, MultimodalClient>,
+    replace: bool,
+) -> Result<()> {
+    if !replace && (clients.contains_key(name) || multimodal_clients.contains_key(name)) {
+        return Err(Error::new_message(format!(
+            "Client {} is already registered; remove it with rembed_client_drop('{}') first, \
+             or set {}=1 to let inserts replace clients",
+            name, name, REPLACE_CLIENTS_ENV
+        )));
+    }
+    Ok(())
+}
+
+/// Reject registering `client` as `name` if its fallback chain leads back to `name`
+fn check_fallback_cycle(
+    clients: &HashMap<String, EmbeddingClient>,
+    name: &str,
+    client: &EmbeddingClient,
+) -> Result<()> {
+    let mut chain = vec![name.to_owned()];
+    let mut next = client.settings().fallback.clone();
+    while let Some(fallback) = next {
+        let seen = chain.contains(&fallback);
+        chain.push(fallback.clone());
+        if seen {
+            return Err(Error::new_message(format!(
+                "Fallback cycle: {}",
+                chain.join(" -> ")
+            )));
+        }
+        next = clients.get(&fallback).and_then(|c| c.settings().fallback.clone());
+    }
+    Ok(())
+}
+
 pub fn rembed(
     context: *mut sqlite3_context,
     values: &[*mut sqlite3_value],
     clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let embedding = embed_text_args(values, clients)?;
+    result_client_blob(context, embedding.body(), embedding.values.len(), embedding.format);
+    Ok(())
+}
+
+// rembed returning a JSON float array, as accepted by sqlite-vec's vec_f32()
+pub fn rembed_json(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let embedding = embed_text_args(values, clients)?;
+    let json = match embedding.format.0 {
+        Precision::F64 => serde_json::to_string(&embedding.values),
+        _ => serde_json::to_string(&embedding.narrowed()),
+    };
+    api::result_text(context, json
+        .map_err(|e| Error::new_message(format!("JSON serialization failed: {}", e)))?)?;
+    Ok(())
+}
+
+// rembed returning JSON with the base64 embedding and the prompt tokens the
+// provider reported (null when it reports none)
+pub fn rembed_with_usage(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
 ) -> Result<()> {
     let client_name = api::value_text(&values[0])?;
-    let input = api::value_text(&values[1])?;
+    let input = value_input_text(&values[1])?;
 
     let clients_map = clients.borrow();
     let client = clients_map.get(client_name).ok_or_else(|| {
@@ -132,31 +466,117 @@ pub fn rembed(
         ))
     })?;
 
-    // Generate embedding synchronously (blocks on async internally)
-    let embedding = client.embed_sync(input)?;
+    let (values, tokens) = client.embed_with_usage_wide_sync(&client.settings().prepare_input(input, None))?;
+    let embedding = TextEmbedding { values, rounding: client.settings().f32_round, format: client.blob_format() };
+    let result = serde_json::json!({
+        "embedding": embedding.base64(),
+        "tokens": tokens,
+    });
+    api::result_text(context, result.to_string())?;
+    Ok(())
+}
+
+// rembed returning JSON with the base64 embedding and the model, provider and
+// dimensions that produced it
+pub fn rembed_meta(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let input = value_input_text(&values[1])?;
+
+    let clients_map = clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Client with name {} was not registered with rembed_clients.",
+            client_name
+        ))
+    })?;
 
-    api::result_blob(context, embedding.as_bytes());
-    api::result_subtype(context, FLOAT32_VECTOR_SUBTYPE);
+    let (values, _) = client.embed_with_usage_wide_sync(&client.settings().prepare_input(input, None))?;
+    let embedding = TextEmbedding { values, rounding: client.settings().f32_round, format: client.blob_format() };
+    let result = serde_json::json!({
+        "model": client.model(),
+        "provider": provider_from_model(client.model()),
+        "dims": embedding.values.len(),
+        "embedding": embedding.base64(),
+    });
+    api::result_text(context, result.to_string())?;
     Ok(())
 }
 
-// Batch embedding function - accepts JSON array of texts
-pub fn rembed_batch(
+// The request a client would send for text, without contacting the provider
+pub fn rembed_debug_request(
     context: *mut sqlite3_context,
     values: &[*mut sqlite3_value],
     clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
 ) -> Result<()> {
     let client_name = api::value_text(&values[0])?;
-    let json_input = api::value_text(&values[1])?;
+    let text = value_input_text(&values[1])?;
+    let role = role_arg(values.get(2))?;
 
-    // Parse JSON array of texts
-    let texts: Vec<String> = serde_json::from_str(json_input)
-        .map_err(|e| Error::new_message(format!("Invalid JSON array: {}", e)))?;
+    let clients_map = clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Client with name {} was not registered with rembed_clients.",
+            client_name
+        ))
+    })?;
 
-    if texts.is_empty() {
-        return Err(Error::new_message("Input array cannot be empty"));
+    api::result_text(context, client.debug_request(text, role)?.to_string())?;
+    Ok(())
+}
+
+// Estimated tokens, requests and cost of embedding a JSON array of texts
+pub fn rembed_estimate_cost(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let texts = batch_texts(api::value_text(&values[1])?)?;
+
+    let clients_map = clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Client with name {} was not registered with rembed_clients.",
+            client_name
+        ))
+    })?;
+
+    api::result_text(context, client.estimate_cost(texts).to_string())?;
+    Ok(())
+}
+
+/// Read a UTF-8 text file, refusing files larger than `max_bytes`
+fn read_text_file(path: &str, max_bytes: u64) -> Result<String> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| Error::new_message(format!("Failed to read file {}: {}", path, e)))?;
+    if metadata.len() > max_bytes {
+        return Err(Error::new_message(format!(
+            "File {} is {} bytes, larger than the {} byte limit",
+            path,
+            metadata.len(),
+            max_bytes
+        )));
     }
 
+    let bytes = std::fs::read(path)
+        .map_err(|e| Error::new_message(format!("Failed to read file {}: {}", path, e)))?;
+    String::from_utf8(bytes)
+        .map_err(|_| Error::new_message(format!("File {} is not valid UTF-8 text", path)))
+}
+
+// Embed the contents of a text file without loading it into SQL first
+pub fn rembed_embed_file(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let path = api::value_text(&values[1])?;
+
     let clients_map = clients.borrow();
     let client = clients_map.get(client_name).ok_or_else(|| {
         Error::new_message(format!(
@@ -165,134 +585,557 @@ pub fn rembed_batch(
         ))
     })?;
 
-    // Generate embeddings in batch
-    let text_refs: Vec<&str> = texts.iter().map(|s| s.as_str()).collect();
-    let embeddings = client.embed_batch_sync(text_refs)?;
+    let role = role_arg(values.get(2))?;
 
-    // Return as JSON array of base64-encoded embeddings
-    let result: Vec<String> = embeddings.into_iter()
-        .map(|embedding| {
-            use base64::Engine as _;
-            base64::engine::general_purpose::STANDARD.encode(embedding.as_bytes())
-        })
-        .collect();
+    let contents = read_text_file(path, MAX_EMBED_FILE_BYTES)?;
+    // Prepared and retried along the fallback chain exactly like rembed
+    let (values, rounding) = embed_with_fallback(&clients_map, client_name, client, &contents, role)?;
+    let embedding = TextEmbedding { values, rounding, format: client.blob_format() };
 
-    api::result_text(context, serde_json::to_string(&result)
-        .map_err(|e| Error::new_message(format!("JSON serialization failed: {}", e)))?)?;
+    result_client_blob(context, embedding.body(), embedding.values.len(), embedding.format);
     Ok(())
 }
 
-// Virtual table implementation
-enum Columns {
-    Name,
-    Options,
+// Convert a precision=f64 blob to float32
+pub fn rembed_f64_to_f32(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let embedding = vector::f32_from_f64_bytes(api::value_blob(&values[0]))?;
+    result_embedding(context, &embedding, Precision::F32);
+    Ok(())
 }
 
-fn column(index: i32) -> Option<Columns> {
-    match index {
-        0 => Some(Columns::Name),
-        1 => Some(Columns::Options),
-        _ => None,
-    }
+// Convert a half precision blob to float32
+pub fn rembed_f16_to_f32(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let embedding = vector::f32_from_f16_bytes(api::value_blob(&values[0]))?;
+    result_embedding(context, &embedding, Precision::F32);
+    Ok(())
 }
 
-// Auxiliary data structure for the virtual table
-pub struct ClientsTableAux {
-    pub clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
-    pub multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+// Convert a float32 blob to half precision
+pub fn rembed_f32_to_f16(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let embedding = vector::f32_from_bytes(api::value_blob(&values[0]))?;
+    result_embedding(context, &embedding, Precision::F16);
+    Ok(())
 }
 
-#[repr(C)]
-pub struct ClientsTable {
-    base: sqlite3_vtab,
-    clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
-    multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+// Dimensions start..start+len of a float32 blob, as a new vector
+pub fn rembed_blob_slice(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let embedding = vector::f32_from_bytes(api::value_blob(&values[0]))?;
+    let (start, len) = (api::value_int64(&values[1]), api::value_int64(&values[2]));
+    result_embedding(context, vector::slice(&embedding, start, len)?, Precision::F32);
+    Ok(())
 }
 
-impl<'vtab> VTab<'vtab> for ClientsTable {
-    type Aux = ClientsTableAux;
-    type Cursor = ClientsCursor<'vtab>;
-
-    fn create(
-        db: *mut sqlite3,
-        aux: Option<&Self::Aux>,
-        args: VTabArguments,
-    ) -> Result<(String, Self)> {
-        Self::connect(db, aux, args)
-    }
+// The raw element blob of a dimension-prefixed embedding
+pub fn rembed_strip_prefix(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    api::result_blob(context, vector::strip_prefix(api::value_blob(&values[0]))?);
+    Ok(())
+}
 
-    fn connect(
-        _db: *mut sqlite3,
-        aux: Option<&Self::Aux>,
-        _args: VTabArguments,
-    ) -> Result<(String, ClientsTable)> {
-        let base: sqlite3_vtab = unsafe { mem::zeroed() };
-        let aux = aux.expect("Required aux");
-        let clients = aux.clients.clone();
-        let multimodal_clients = aux.multimodal_clients.clone();
+// Number of dimensions in a float32 blob
+pub fn rembed_blob_dimensions(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let embedding = vector::f32_from_bytes(api::value_blob(&values[0]))?;
+    api::result_int64(context, embedding.len() as i64);
+    Ok(())
+}
 
-        let vtab = ClientsTable {
-            base,
-            clients,
-            multimodal_clients,
-        };
-        let sql = "create table x(name text primary key, options)".to_owned();
+// Subtype attached to a value, 0 if none
+pub fn rembed_blob_subtype(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    api::result_int64(context, api::value_subtype(&values[0]).into());
+    Ok(())
+}
 
-        Ok((sql, vtab))
-    }
+// 1 if a float32 blob has unit length (within tolerance), otherwise 0
+pub fn rembed_blob_is_normalized(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let embedding = vector::f32_from_bytes(api::value_blob(&values[0]))?;
+    api::result_int64(context, vector::is_normalized(&embedding) as i64);
+    Ok(())
+}
 
-    fn destroy(&self) -> Result<()> {
-        Ok(())
-    }
+/// Split a document into non-blank chunks per the client's chunking settings
+fn document_chunks<'a>(text: &'a str, client: &EmbeddingClient) -> Result<Vec<&'a str>> {
+    let (chunk_size, chunk_overlap) = client.chunking();
+    let chunks: Vec<&str> = chunking::chunk_text(text, chunk_size, chunk_overlap)
+        .into_iter()
+        .filter(|chunk| !chunk.trim().is_empty())
+        .collect();
 
-    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
-        info.set_estimated_cost(10000.0);
-        info.set_estimated_rows(10000);
-        info.set_idxnum(1);
-        Ok(())
+    if chunks.is_empty() {
+        return Err(Error::new_message("Document contains no text to embed"));
     }
+    Ok(chunks)
+}
 
-    fn open(&'vtab mut self) -> Result<ClientsCursor<'vtab>> {
-        ClientsCursor::new(self)
-    }
+/// Chunks of a document prepared as document-role inputs, so each gets the
+/// client's document prefix or template
+fn document_inputs(text: &str, client: &EmbeddingClient) -> Result<Vec<String>> {
+    Ok(document_chunks(text, client)?
+        .into_iter()
+        .map(|chunk| client.settings().prepare_input(chunk, Some(InputRole::Document)))
+        .collect())
 }
 
-impl<'vtab> VTabWriteable<'vtab> for ClientsTable {
-    fn update(&'vtab mut self, operation: UpdateOperation<'_>, _p_rowid: *mut i64) -> Result<()> {
-        match operation {
-            UpdateOperation::Delete(_) => {
-                return Err(Error::new_message(
-                    "DELETE operations on rembed_clients is not supported yet",
-                ))
-            }
-            UpdateOperation::Update { _values } => {
-                return Err(Error::new_message(
-                    "UPDATE operations on rembed_clients is not supported yet",
-                ))
-            }
-            UpdateOperation::Insert { values, rowid: _ } => {
-                let name = api::value_text(&values[0])?;
+// Embed a long document as overlapping chunks, returning the normalized mean vector
+pub fn rembed_document(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let text = api::value_text(&values[1])?;
 
-                match api::value_type(&values[1]) {
-                    ValueType::Text => {
-                        let options = api::value_text(&values[1])?;
-                        // Parse the options to get model and api key
-                        let config = parse_client_options(name, options)?;
-                        // Create client with the model and api key
-                        let client = EmbeddingClient::new(config.model, config.api_key)?;
-                        self.clients.borrow_mut().insert(name.to_owned(), client);
-                    }
-                    ValueType::Null => unsafe {
+    let clients_map = clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Client with name {} was not registered with rembed_clients.",
+            client_name
+        ))
+    })?;
+
+    let inputs = document_inputs(text, client)?;
+    let embeddings = client.embed_batch_sync(inputs.iter().map(String::as_str).collect())?;
+    let pooled = vector::mean_pool_normalized(&embeddings)?;
+
+    result_client_embedding(context, &pooled, client.blob_format());
+    Ok(())
+}
+
+/// Cosine similarity between a query vector and base64-encoded candidates,
+/// each float32 or float64
+fn similarity_scores(query: &[f32], candidates_json: &str) -> Result<Vec<f32>> {
+    let candidates: Vec<String> = serde_json::from_str(candidates_json)
+        .map_err(|e| Error::new_message(format!("Invalid JSON array: {}", e)))?;
+
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| {
+            let candidate = vector::bytes_from_base64(candidate)?;
+            let candidate = vector::f32_from_bytes_of_width(&candidate, query.len())?;
+            vector::cosine_similarity(query, &candidate)
+                .map_err(|e| Error::new_message(format!("Candidate {}: {}", i, e)))
+        })
+        .collect()
+}
+
+// Score several candidate vectors against a query in one call, for reranking
+pub fn rembed_similarity_json(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+) -> Result<()> {
+    let query = if is_f64_vector(&values[0]) {
+        vector::f32_from_f64_bytes(api::value_blob(&values[0]))?
+    } else {
+        vector::f32_from_bytes(api::value_blob(&values[0]))?
+    };
+    let scores = similarity_scores(&query, api::value_text(&values[1])?)?;
+
+    api::result_text(context, serde_json::to_string(&scores)
+        .map_err(|e| Error::new_message(format!("JSON serialization failed: {}", e)))?)?;
+    Ok(())
+}
+
+// Decode a JSON array of base64 float32 vectors
+fn base64_vectors(json: &str) -> Result<Vec<Vec<f32>>> {
+    let encoded: Vec<String> = serde_json::from_str(json)
+        .map_err(|e| Error::new_message(format!("Invalid JSON array: {}", e)))?;
+    encoded
+        .iter()
+        .map(|vector| vector::f32_from_base64(vector))
+        .collect()
+}
+
+// Pairwise distances of a JSON array of base64 float32 vectors, as a JSON
+// array of rows
+pub fn rembed_distance_matrix(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let vectors = base64_vectors(api::value_text(&values[0])?)?;
+    let metric = api::value_text(&values[1])?.parse::<vector::DistanceMetric>()?;
+    let matrix = vector::distance_matrix(&vectors, metric)?;
+
+    api::result_text(context, serde_json::to_string(&matrix)
+        .map_err(|e| Error::new_message(format!("JSON serialization failed: {}", e)))?)?;
+    Ok(())
+}
+
+// Combine a JSON array of base64 float32 vectors into one blob
+pub fn rembed_pool(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let vectors = base64_vectors(api::value_text(&values[0])?)?;
+    let method = api::value_text(&values[1])?.parse::<vector::PoolMethod>()?;
+    let pooled = vector::pool(&vectors, method)?;
+
+    result_embedding(context, &pooled, Precision::F32);
+    Ok(())
+}
+
+/// Add one row's float32 blob to its group's centroid, kept behind a pointer
+/// in SQLite's aggregate context until centroid_final frees it
+fn centroid_add(context: *mut sqlite3_context, values: &[*mut sqlite3_value]) -> Result<()> {
+    let vector = vector::f32_from_bytes(api::value_blob(&values[0]))?;
+    let slot = unsafe { ext::sqlite3ext_aggregate_context(context, mem::size_of::<*mut vector::Centroid>() as c_int) }
+        as *mut *mut vector::Centroid;
+    if slot.is_null() {
+        return Err(Error::new_message("Out of memory allocating centroid state"));
+    }
+    unsafe {
+        if (*slot).is_null() {
+            *slot = Box::into_raw(Box::default());
+        }
+        (**slot).add(&vector)
+    }
+}
+
+/// Take the group's centroid out of its aggregate context; None for an
+/// empty group
+fn centroid_take(context: *mut sqlite3_context) -> Option<Box<vector::Centroid>> {
+    let slot = unsafe { ext::sqlite3ext_aggregate_context(context, 0) } as *mut *mut vector::Centroid;
+    if slot.is_null() {
+        return None;
+    }
+    unsafe {
+        let centroid = std::ptr::replace(slot, std::ptr::null_mut());
+        (!centroid.is_null()).then(|| Box::from_raw(centroid))
+    }
+}
+
+unsafe extern "C" fn centroid_step(context: *mut sqlite3_context, argc: c_int, argv: *mut *mut sqlite3_value) {
+    let values = std::slice::from_raw_parts(argv, argc as usize);
+    if let Err(err) = centroid_add(context, values) {
+        let _ = api::result_error(context, &err.to_string());
+    }
+}
+
+/// Return the group's mean vector, L2-normalized if asked, or NULL for no rows
+fn centroid_result(context: *mut sqlite3_context, normalized: bool) {
+    match centroid_take(context).and_then(|centroid| centroid.mean()) {
+        Some(mean) if normalized => result_embedding(context, &vector::normalize(mean), Precision::F32),
+        Some(mean) => result_embedding(context, &mean, Precision::F32),
+        None => api::result_null(context),
+    }
+}
+
+unsafe extern "C" fn centroid_final(context: *mut sqlite3_context) {
+    centroid_result(context, true);
+}
+
+unsafe extern "C" fn centroid_raw_final(context: *mut sqlite3_context) {
+    centroid_result(context, false);
+}
+
+/// Register rembed_centroid(blob) and rembed_centroid_raw(blob), aggregates
+/// over float32 vectors: sqlite-loadable only wraps scalar functions
+fn define_centroid_aggregates(db: *mut sqlite3, flags: FunctionFlags) -> Result<()> {
+    let finals: [(&str, unsafe extern "C" fn(*mut sqlite3_context)); 2] =
+        [("rembed_centroid", centroid_final), ("rembed_centroid_raw", centroid_raw_final)];
+    for (name, x_final) in finals {
+        let cname = std::ffi::CString::new(name).expect("function names have no NUL");
+        let rc = unsafe {
+            ext::sqlite3ext_create_function_v2(
+                db,
+                cname.as_ptr(),
+                1,
+                flags.bits(),
+                std::ptr::null_mut(),
+                None,
+                Some(centroid_step),
+                Some(x_final),
+                None,
+            )
+        };
+        if rc != 0 {
+            return Err(Error::new_message(format!("Failed to register {}: error code {}", name, rc)));
+        }
+    }
+    Ok(())
+}
+
+// Batch embedding function - accepts JSON array of texts
+pub fn rembed_batch(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let texts = batch_texts(api::value_text(&values[1])?)?;
+
+    let clients_map = clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Client with name {} was not registered with rembed_clients.",
+            client_name
+        ))
+    })?;
+
+    // Generate embeddings in concurrent, independently retried sub-batches
+    let text_refs: Vec<&str> = texts.iter().map(|s| s.as_str()).collect();
+    let partial = client.embed_batch_partial_sync(text_refs);
+    result_batch(context, partial, client.blob_format(), client.batch_output())
+}
+
+// Batch embedding with one provider input type (e.g. Cohere's search_query)
+// for every text; mixing query and document texts takes separate calls
+pub fn rembed_batch_typed(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    clients: &Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let texts = batch_texts(api::value_text(&values[1])?)?;
+    let input_type = api::value_text(&values[2])?;
+
+    let clients_map = clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Client with name {} was not registered with rembed_clients.",
+            client_name
+        ))
+    })?;
+
+    let text_refs: Vec<&str> = texts.iter().map(|s| s.as_str()).collect();
+    let partial = client.embed_batch_typed_sync(text_refs, input_type)?;
+    result_batch(context, partial, client.blob_format(), client.batch_output())
+}
+
+/// Parse the JSON array of texts passed to the batch functions
+fn batch_texts(json_input: &str) -> Result<Vec<String>> {
+    let texts: Vec<String> = serde_json::from_str(json_input)
+        .map_err(|e| Error::new_message(format!("Invalid JSON array: {}", e)))?;
+
+    if texts.is_empty() {
+        return Err(Error::new_message("Input array cannot be empty"));
+    }
+    Ok(texts)
+}
+
+/// Return batch embeddings as JSON, null where a sub-batch failed after its
+/// retries; fails only if every sub-batch failed
+fn result_batch(
+    context: *mut sqlite3_context,
+    partial: PartialBatch,
+    format: (Precision, OutputFormat, Endian),
+    output: BatchOutput,
+) -> Result<()> {
+    if partial.embeddings.iter().all(Option::is_none) {
+        if let Some(e) = partial.first_error {
+            return Err(e);
+        }
+    }
+
+    let result = batch_json(partial.embeddings, format, output);
+    api::result_text(context, serde_json::to_string(&result)
+        .map_err(|e| Error::new_message(format!("JSON serialization failed: {}", e)))?)?;
+    Ok(())
+}
+
+/// Base64 embeddings in input order and the client's blob format, as a bare
+/// array or as objects carrying each input's index
+fn batch_json(
+    embeddings: Vec<Option<Vec<f32>>>,
+    format: (Precision, OutputFormat, Endian),
+    output: BatchOutput,
+) -> serde_json::Value {
+    use base64::Engine as _;
+    let encoded = embeddings.into_iter().map(|embedding| {
+        embedding.map(|embedding| {
+            let blob = client_blob_bytes(vector::encode(&embedding, format.0), embedding.len(), format);
+            base64::engine::general_purpose::STANDARD.encode(blob)
+        })
+    });
+    match output {
+        BatchOutput::Array => encoded.collect(),
+        BatchOutput::Objects => encoded
+            .enumerate()
+            .map(|(index, embedding)| serde_json::json!({ "index": index, "embedding": embedding }))
+            .collect(),
+    }
+}
+
+// Virtual table implementation
+enum Columns {
+    Name,
+    Options,
+    TimeoutSecs,
+    MaxRetries,
+    LastError,
+    ServedBy,
+    Provider,
+    Seq,
+}
+
+/// idxnum for a full scan of rembed_clients
+const CLIENTS_IDX_SCAN: c_int = 1;
+/// idxnum for a `name = ?` lookup, with the name passed as argv[0] to filter
+const CLIENTS_IDX_NAME: c_int = 2;
+
+fn column(index: i32) -> Option<Columns> {
+    match index {
+        0 => Some(Columns::Name),
+        1 => Some(Columns::Options),
+        2 => Some(Columns::TimeoutSecs),
+        3 => Some(Columns::MaxRetries),
+        4 => Some(Columns::LastError),
+        5 => Some(Columns::ServedBy),
+        6 => Some(Columns::Provider),
+        7 => Some(Columns::Seq),
+        _ => None,
+    }
+}
+
+/// Order in which a connection's clients were registered, shown as
+/// rembed_clients.seq
+#[derive(Default)]
+pub struct Registrations {
+    last: i64,
+    seqs: HashMap<String, i64>,
+}
+
+impl Registrations {
+    /// Record that name was registered (or replaced) after every client so far
+    fn record(&mut self, name: &str) {
+        self.last += 1;
+        self.seqs.insert(name.to_owned(), self.last);
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.seqs.remove(name);
+    }
+
+    fn seq(&self, name: &str) -> Option<i64> {
+        self.seqs.get(name).copied()
+    }
+}
+
+// Auxiliary data structure for the virtual table
+pub struct ClientsTableAux {
+    pub clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+    pub multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+    pub registrations: Rc<RefCell<Registrations>>,
+}
+
+#[repr(C)]
+pub struct ClientsTable {
+    base: sqlite3_vtab,
+    clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+    multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+    registrations: Rc<RefCell<Registrations>>,
+}
+
+impl<'vtab> VTab<'vtab> for ClientsTable {
+    type Aux = ClientsTableAux;
+    type Cursor = ClientsCursor<'vtab>;
+
+    fn create(
+        db: *mut sqlite3,
+        aux: Option<&Self::Aux>,
+        args: VTabArguments,
+    ) -> Result<(String, Self)> {
+        Self::connect(db, aux, args)
+    }
+
+    fn connect(
+        _db: *mut sqlite3,
+        aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, ClientsTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let aux = aux.expect("Required aux");
+        let clients = aux.clients.clone();
+        let multimodal_clients = aux.multimodal_clients.clone();
+        let registrations = aux.registrations.clone();
+
+        let vtab = ClientsTable {
+            base,
+            clients,
+            multimodal_clients,
+            registrations,
+        };
+        let sql = "create table x(name text primary key, options, timeout_secs integer, max_retries integer, last_error, served_by text, provider text, seq integer)".to_owned();
+
+        Ok((sql, vtab))
+    }
+
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        // Push down `name = ?` so lookups of a single client skip the full scan
+        let mut has_name_eq = false;
+        for mut constraint in info.constraints() {
+            if constraint.usable()
+                && matches!(column(constraint.column_idx()), Some(Columns::Name))
+                && matches!(constraint.op(), Some(ConstraintOperator::EQ))
+            {
+                constraint.set_argv_index(1);
+                has_name_eq = true;
+                break;
+            }
+        }
+
+        if has_name_eq {
+            info.set_estimated_cost(1.0);
+            info.set_estimated_rows(1);
+            info.set_idxnum(CLIENTS_IDX_NAME);
+        } else {
+            info.set_estimated_cost(10000.0);
+            info.set_estimated_rows(10000);
+            info.set_idxnum(CLIENTS_IDX_SCAN);
+        }
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> Result<ClientsCursor<'vtab>> {
+        ClientsCursor::new(self)
+    }
+}
+
+impl<'vtab> VTabWriteable<'vtab> for ClientsTable {
+    fn update(&'vtab mut self, operation: UpdateOperation<'_>, _p_rowid: *mut i64) -> Result<()> {
+        match operation {
+            UpdateOperation::Delete(_) => {
+                return Err(Error::new_message(
+                    "DELETE operations on rembed_clients is not supported yet",
+                ))
+            }
+            UpdateOperation::Update { _values } => {
+                return Err(Error::new_message(
+                    "UPDATE operations on rembed_clients is not supported yet",
+                ))
+            }
+            UpdateOperation::Insert { values, rowid: _ } => {
+                let name = api::value_text(&values[0])?;
+                check_client_name(
+                    name,
+                    &self.clients.borrow(),
+                    &self.multimodal_clients.borrow(),
+                    replace_clients_from_env(),
+                )?;
+
+                match api::value_type(&values[1]) {
+                    ValueType::Text => {
+                        let options = api::value_text(&values[1])?;
+                        // Parse the options to get model and api key
+                        let config = parse_client_options(name, options)?;
+                        // Create client with the model and api key
+                        let client = EmbeddingClient::with_settings(config.model, config.api_key, config.settings)?;
+                        check_fallback_cycle(&self.clients.borrow(), name, &client)?;
+                        drop_client(name, &mut self.clients.borrow_mut(), &mut self.multimodal_clients.borrow_mut());
+                        self.clients.borrow_mut().insert(name.to_owned(), client);
+                    }
+                    ValueType::Null => unsafe {
+                        // The pointer stays owned by SQLite, which drops it with the
+                        // rembed_client_options result; register a clone
                         // Try multimodal client first
                         if let Some(multimodal_client) =
                             api::value_pointer::<MultimodalClient>(&values[1], MULTIMODAL_CLIENT_OPTIONS_POINTER_NAME)
                         {
+                            drop_client(name, &mut self.clients.borrow_mut(), &mut self.multimodal_clients.borrow_mut());
                             self.multimodal_clients.borrow_mut().insert(name.to_owned(), (*multimodal_client).clone());
                         }
                         // Fallback to regular embedding client
                         else if let Some(client) =
                             api::value_pointer::<EmbeddingClient>(&values[1], CLIENT_OPTIONS_POINTER_NAME)
                         {
+                            check_fallback_cycle(&self.clients.borrow(), name, &*client)?;
+                            drop_client(name, &mut self.clients.borrow_mut(), &mut self.multimodal_clients.borrow_mut());
                             self.clients.borrow_mut().insert(name.to_owned(), (*client).clone());
                         } else {
                             return Err(Error::new_message("client options required"));
@@ -300,6 +1143,7 @@ impl<'vtab> VTabWriteable<'vtab> for ClientsTable {
                     },
                     _ => return Err(Error::new_message("client options required")),
                 };
+                self.registrations.borrow_mut().record(name);
             }
         }
         Ok(())
@@ -313,6 +1157,7 @@ pub struct ClientsCursor<'vtab> {
     rowid: i64,
     clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
     multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+    registrations: Rc<RefCell<Registrations>>,
     phantom: PhantomData<&'vtab ClientsTable>,
 }
 
@@ -320,92 +1165,1015 @@ impl ClientsCursor<'_> {
     fn new(table: &mut ClientsTable) -> Result<ClientsCursor<'_>> {
         let base: sqlite3_vtab_cursor = unsafe { mem::zeroed() };
 
-        // Collect keys from both regular and multimodal clients
+        let cursor = ClientsCursor {
+            base,
+            keys: Vec::new(),
+            rowid: 0,
+            clients: table.clients.clone(),
+            multimodal_clients: table.multimodal_clients.clone(),
+            registrations: table.registrations.clone(),
+            phantom: PhantomData,
+        };
+        Ok(cursor)
+    }
+
+    /// Collect keys from both regular and multimodal clients, in registration order
+    fn collect_keys(&self) -> Vec<String> {
         let mut keys = Vec::new();
 
         // Add regular embedding client keys
-        let c = table.clients.borrow();
+        let c = self.clients.borrow();
         keys.extend(c.keys().map(|k| k.to_string()));
         drop(c);
 
         // Add multimodal client keys
-        let mc = table.multimodal_clients.borrow();
+        let mc = self.multimodal_clients.borrow();
         keys.extend(mc.keys().map(|k| k.to_string()));
         drop(mc);
 
-        let cursor = ClientsCursor {
-            base,
-            keys,
-            rowid: 0,
-            clients: table.clients.clone(),
-            multimodal_clients: table.multimodal_clients.clone(),
-            phantom: PhantomData,
-        };
-        Ok(cursor)
+        let registrations = self.registrations.borrow();
+        keys.sort_by_key(|key| registrations.seq(key));
+        keys
     }
 }
 
 impl VTabCursor for ClientsCursor<'_> {
     fn filter(
         &mut self,
-        _idx_num: c_int,
+        idx_num: c_int,
         _idx_str: Option<&str>,
-        _values: &[*mut sqlite3_value],
+        values: &[*mut sqlite3_value],
     ) -> Result<()> {
+        self.keys = self.collect_keys();
+        if idx_num == CLIENTS_IDX_NAME {
+            let name = api::value_text(&values[0])?;
+            self.keys.retain(|key| key == name);
+        }
+        self.rowid = 0;
         Ok(())
     }
 
-    fn next(&mut self) -> Result<()> {
-        self.rowid += 1;
-        Ok(())
-    }
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        (self.rowid as usize) >= self.keys.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let key = self
+            .keys
+            .get(self.rowid as usize)
+            .expect("Internal rembed_clients logic error");
+        match column(i) {
+            Some(Columns::Name) => api::result_text(context, key)?,
+            Some(Columns::Options) => {
+                // Check what type of client this is for debugging
+                let kind = client_kind(
+                    &self.clients.borrow(),
+                    &self.multimodal_clients.borrow(),
+                    key,
+                );
+                match kind {
+                    Some(kind) => api::result_text(context, kind)?,
+                    // The client was removed after the cursor collected its keys
+                    None => api::result_null(context),
+                }
+            },
+            Some(Columns::TimeoutSecs) => {
+                match self.clients.borrow().get(key).and_then(|c| c.timeout_secs()) {
+                    Some(secs) => api::result_int64(context, secs as i64),
+                    None => api::result_null(context),
+                }
+            },
+            Some(Columns::MaxRetries) => {
+                match self.clients.borrow().get(key) {
+                    Some(client) => api::result_int64(context, client.max_retries() as i64),
+                    None => api::result_null(context),
+                }
+            },
+            Some(Columns::LastError) => {
+                let last_error = match self.clients.borrow().get(key) {
+                    Some(client) => client.last_error(),
+                    None => self
+                        .multimodal_clients
+                        .borrow()
+                        .get(key)
+                        .and_then(|client| client.last_error()),
+                };
+                match last_error {
+                    Some((message, timestamp)) => api::result_text(
+                        context,
+                        serde_json::json!({ "message": message, "timestamp": timestamp }).to_string(),
+                    )?,
+                    None => api::result_null(context),
+                }
+            },
+            Some(Columns::ServedBy) => {
+                match self.clients.borrow().get(key).and_then(|c| c.served_by()) {
+                    Some(served_by) => api::result_text(context, served_by)?,
+                    None => api::result_null(context),
+                }
+            },
+            Some(Columns::Provider) => {
+                let model = match self.clients.borrow().get(key) {
+                    Some(client) => Some(client.model().to_owned()),
+                    None => self
+                        .multimodal_clients
+                        .borrow()
+                        .get(key)
+                        .map(|client| client.vision_model().to_owned()),
+                };
+                match model {
+                    Some(model) => api::result_text(context, provider_from_model(&model))?,
+                    None => api::result_null(context),
+                }
+            },
+            Some(Columns::Seq) => match self.registrations.borrow().seq(key) {
+                Some(seq) => api::result_int64(context, seq),
+                None => api::result_null(context),
+            },
+            None => (),
+        };
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+/// Describe which kind of client is registered under `name`, if any
+fn client_kind(
+    clients: &HashMap<String, EmbeddingClient>,
+    multimodal_clients: &HashMap<String, MultimodalClient>,
+    name: &str,
+) -> Option<&'static str> {
+    if clients.contains_key(name) {
+        Some("(embedding client)")
+    } else if multimodal_clients.contains_key(name) {
+        Some("(multimodal client)")
+    } else {
+        None
+    }
+}
+
+/// Column indices of the rembed_images_each table function
+const IMAGES_EACH_COLUMN_ID: c_int = 0;
+const IMAGES_EACH_COLUMN_EMBEDDING: c_int = 1;
+const IMAGES_EACH_COLUMN_ERROR: c_int = 2;
+const IMAGES_EACH_COLUMN_CLIENT: c_int = 3;
+const IMAGES_EACH_COLUMN_IMAGES: c_int = 4;
+
+/// One output row of rembed_images_each: the caller's id and either an
+/// embedding or the error that image failed with
+struct ImageRow {
+    id: serde_json::Value,
+    result: Result<Vec<f32>>,
+}
+
+/// Embed a JSON array of {id, base64_image} objects, one row per entry in
+/// input order. Entries that fail to decode or embed become error rows.
+fn embed_image_rows(client: &MultimodalClient, json: &str) -> Result<Vec<ImageRow>> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(json)
+        .map_err(|e| Error::new_message(format!("Invalid JSON array: {}", e)))?;
+
+    let decoded: Vec<(serde_json::Value, Result<Vec<u8>>)> = entries
+        .into_iter()
+        .map(|entry| {
+            let id = entry.get("id").cloned().unwrap_or(serde_json::Value::Null);
+            let image = entry
+                .get("base64_image")
+                .and_then(|image| image.as_str())
+                .ok_or_else(|| Error::new_message("Each entry needs a string 'base64_image'"))
+                .and_then(|image| {
+                    use base64::Engine as _;
+                    base64::engine::general_purpose::STANDARD
+                        .decode(image)
+                        .map_err(|e| Error::new_message(format!("Base64 decode failed: {}", e)))
+                });
+            (id, image)
+        })
+        .collect();
+
+    // Embed the decodable images together, then merge results back in order
+    let images: Vec<Vec<u8>> = decoded
+        .iter()
+        .filter_map(|(_, image)| image.as_ref().ok().cloned())
+        .collect();
+    let mut embeddings = client.embed_images_each_sync(images).into_iter();
+    Ok(decoded
+        .into_iter()
+        .map(|(id, image)| ImageRow {
+            id,
+            result: image.and_then(|_| {
+                embeddings
+                    .next()
+                    .unwrap_or_else(|| Err(Error::new_message("Missing image result")))
+            }),
+        })
+        .collect())
+}
+
+#[repr(C)]
+pub struct ImagesEachTable {
+    base: sqlite3_vtab,
+    multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+}
+
+impl<'vtab> VTab<'vtab> for ImagesEachTable {
+    type Aux = Rc<RefCell<HashMap<String, MultimodalClient>>>;
+    type Cursor = ImagesEachCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, ImagesEachTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = ImagesEachTable {
+            base,
+            multimodal_clients: aux.expect("Required aux").clone(),
+        };
+        let sql = "create table x(id, embedding blob, error text, client hidden, images hidden)".to_owned();
+        Ok((sql, vtab))
+    }
+
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        // Both arguments are required: client as argv[0], images as argv[1]
+        let (mut has_client, mut has_images) = (false, false);
+        for mut constraint in info.constraints() {
+            if !constraint.usable() || !matches!(constraint.op(), Some(ConstraintOperator::EQ)) {
+                continue;
+            }
+            match constraint.column_idx() {
+                IMAGES_EACH_COLUMN_CLIENT => {
+                    constraint.set_argv_index(1);
+                    has_client = true;
+                }
+                IMAGES_EACH_COLUMN_IMAGES => {
+                    constraint.set_argv_index(2);
+                    has_images = true;
+                }
+                _ => (),
+            }
+        }
+        if !has_client || !has_images {
+            return Err(BestIndexError::Constraint);
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(100);
+        info.set_idxnum(1);
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> Result<ImagesEachCursor<'vtab>> {
+        Ok(ImagesEachCursor {
+            base: unsafe { mem::zeroed() },
+            multimodal_clients: self.multimodal_clients.clone(),
+            rows: Vec::new(),
+            rowid: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[repr(C)]
+pub struct ImagesEachCursor<'vtab> {
+    base: sqlite3_vtab_cursor,
+    multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+    rows: Vec<ImageRow>,
+    rowid: i64,
+    phantom: PhantomData<&'vtab ImagesEachTable>,
+}
+
+impl VTabCursor for ImagesEachCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let client_name = api::value_text(&values[0])?;
+        let clients_map = self.multimodal_clients.borrow();
+        let client = clients_map.get(client_name).ok_or_else(|| {
+            Error::new_message(format!(
+                "Multimodal client with name {} was not registered.",
+                client_name
+            ))
+        })?;
+        self.rows = embed_image_rows(client, api::value_text(&values[1])?)?;
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        (self.rowid as usize) >= self.rows.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let row = self
+            .rows
+            .get(self.rowid as usize)
+            .expect("Internal rembed_images_each logic error");
+        match (i, &row.result) {
+            (IMAGES_EACH_COLUMN_ID, _) => match &row.id {
+                serde_json::Value::Null => api::result_null(context),
+                serde_json::Value::String(id) => api::result_text(context, id)?,
+                id => match id.as_i64() {
+                    Some(id) => api::result_int64(context, id),
+                    None => api::result_text(context, id.to_string())?,
+                },
+            },
+            (IMAGES_EACH_COLUMN_EMBEDDING, Ok(embedding)) => {
+                result_embedding(context, embedding, Precision::F32)
+            }
+            (IMAGES_EACH_COLUMN_ERROR, Err(e)) => api::result_text(context, e.to_string())?,
+            _ => api::result_null(context),
+        };
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+/// Column indices of the rembed_split table function
+const SPLIT_COLUMN_INDEX: c_int = 0;
+const SPLIT_COLUMN_TEXT: c_int = 1;
+const SPLIT_COLUMN_INPUT: c_int = 2;
+const SPLIT_COLUMN_CHUNK_SIZE: c_int = 3;
+const SPLIT_COLUMN_OVERLAP: c_int = 4;
+
+#[repr(C)]
+pub struct SplitTable {
+    base: sqlite3_vtab,
+}
+
+impl<'vtab> VTab<'vtab> for SplitTable {
+    type Aux = ();
+    type Cursor = SplitCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        _aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, SplitTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let sql = "create table x(chunk_index integer, chunk_text text, text hidden, chunk_size hidden, overlap hidden)"
+            .to_owned();
+        Ok((sql, SplitTable { base }))
+    }
+
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        // text and chunk_size are required as argv[0] and argv[1]; overlap
+        // is optional as argv[2]
+        let (mut has_text, mut has_chunk_size) = (false, false);
+        for mut constraint in info.constraints() {
+            if !constraint.usable() || !matches!(constraint.op(), Some(ConstraintOperator::EQ)) {
+                continue;
+            }
+            match constraint.column_idx() {
+                SPLIT_COLUMN_INPUT => {
+                    constraint.set_argv_index(1);
+                    has_text = true;
+                }
+                SPLIT_COLUMN_CHUNK_SIZE => {
+                    constraint.set_argv_index(2);
+                    has_chunk_size = true;
+                }
+                SPLIT_COLUMN_OVERLAP => constraint.set_argv_index(3),
+                _ => (),
+            }
+        }
+        if !has_text || !has_chunk_size {
+            return Err(BestIndexError::Constraint);
+        }
+        info.set_estimated_cost(100.0);
+        info.set_estimated_rows(100);
+        info.set_idxnum(1);
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> Result<SplitCursor<'vtab>> {
+        Ok(SplitCursor {
+            base: unsafe { mem::zeroed() },
+            chunks: Vec::new(),
+            rowid: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[repr(C)]
+pub struct SplitCursor<'vtab> {
+    base: sqlite3_vtab_cursor,
+    chunks: Vec<String>,
+    rowid: i64,
+    phantom: PhantomData<&'vtab SplitTable>,
+}
+
+impl VTabCursor for SplitCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let text = value_input_text(&values[0])?;
+        let chunk_size = api::value_int64(&values[1]);
+        let overlap = values.get(2).map_or(0, api::value_int64);
+        if chunk_size <= 0 {
+            return Err(Error::new_message("chunk_size must be greater than 0"));
+        }
+        if overlap < 0 || overlap >= chunk_size {
+            return Err(Error::new_message("overlap must be at least 0 and less than chunk_size"));
+        }
+        self.chunks = chunking::chunk_text(text, chunk_size as usize, overlap as usize)
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        (self.rowid as usize) >= self.chunks.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        match i {
+            SPLIT_COLUMN_INDEX => api::result_int64(context, self.rowid),
+            SPLIT_COLUMN_TEXT => api::result_text(context, &self.chunks[self.rowid as usize])?,
+            _ => api::result_null(context),
+        };
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+/// Column indices of the rembed_health table function
+const HEALTH_COLUMN_NAME: c_int = 0;
+const HEALTH_COLUMN_OK: c_int = 1;
+const HEALTH_COLUMN_LATENCY_MS: c_int = 2;
+const HEALTH_COLUMN_ERROR: c_int = 3;
+const HEALTH_COLUMN_TIMEOUT_MS: c_int = 4;
+/// How long rembed_health waits for each client unless given timeout_ms
+const DEFAULT_HEALTH_TIMEOUT_MS: u64 = 5000;
+
+#[repr(C)]
+pub struct HealthTable {
+    base: sqlite3_vtab,
+    clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+    multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+}
+
+impl<'vtab> VTab<'vtab> for HealthTable {
+    type Aux = ClientsTableAux;
+    type Cursor = HealthCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, HealthTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let aux = aux.expect("Required aux");
+        let vtab = HealthTable {
+            base,
+            clients: aux.clients.clone(),
+            multimodal_clients: aux.multimodal_clients.clone(),
+        };
+        let sql = "create table x(name text, ok integer, latency_ms integer, error text, timeout_ms hidden)".to_owned();
+        Ok((sql, vtab))
+    }
+
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        // timeout_ms is optional and passed as argv[0] when given
+        let mut has_timeout = false;
+        for mut constraint in info.constraints() {
+            if constraint.usable()
+                && matches!(constraint.op(), Some(ConstraintOperator::EQ))
+                && constraint.column_idx() == HEALTH_COLUMN_TIMEOUT_MS
+            {
+                constraint.set_argv_index(1);
+                has_timeout = true;
+            }
+        }
+        info.set_estimated_cost(100000.0);
+        info.set_estimated_rows(10);
+        info.set_idxnum(if has_timeout { 2 } else { 1 });
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> Result<HealthCursor<'vtab>> {
+        Ok(HealthCursor {
+            base: unsafe { mem::zeroed() },
+            clients: self.clients.clone(),
+            multimodal_clients: self.multimodal_clients.clone(),
+            rows: Vec::new(),
+            rowid: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[repr(C)]
+pub struct HealthCursor<'vtab> {
+    base: sqlite3_vtab_cursor,
+    clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+    multimodal_clients: Rc<RefCell<HashMap<String, MultimodalClient>>>,
+    rows: Vec<(String, std::time::Duration, Result<()>)>,
+    rowid: i64,
+    phantom: PhantomData<&'vtab HealthTable>,
+}
+
+impl VTabCursor for HealthCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        let timeout_ms = match values.first() {
+            Some(value) => match api::value_int64(value) {
+                timeout_ms if timeout_ms > 0 => timeout_ms as u64,
+                _ => return Err(Error::new_message("timeout_ms must be greater than 0")),
+            },
+            None => DEFAULT_HEALTH_TIMEOUT_MS,
+        };
+        // Ping clones so no borrow is held while requests are in flight
+        let clients: Vec<(String, EmbeddingClient)> = self
+            .clients
+            .borrow()
+            .iter()
+            .map(|(name, client)| (name.clone(), client.clone()))
+            .collect();
+        let multimodal_clients: Vec<(String, MultimodalClient)> = self
+            .multimodal_clients
+            .borrow()
+            .iter()
+            .map(|(name, client)| (name.clone(), client.clone()))
+            .collect();
+        self.rows = health_check(clients, multimodal_clients, std::time::Duration::from_millis(timeout_ms));
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        (self.rowid as usize) >= self.rows.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let (name, latency, result) = self
+            .rows
+            .get(self.rowid as usize)
+            .expect("Internal rembed_health logic error");
+        match (i, result) {
+            (HEALTH_COLUMN_NAME, _) => api::result_text(context, name)?,
+            (HEALTH_COLUMN_OK, _) => api::result_int64(context, result.is_ok() as i64),
+            (HEALTH_COLUMN_LATENCY_MS, _) => api::result_int64(context, latency.as_millis() as i64),
+            (HEALTH_COLUMN_ERROR, Err(e)) => api::result_text(context, e.to_string())?,
+            _ => api::result_null(context),
+        };
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+/// Column indices of the rembed_trace table function
+const TRACE_COLUMN_CLIENT: c_int = 0;
+const TRACE_COLUMN_SEQUENCE: c_int = 1;
+const TRACE_COLUMN_OPERATION: c_int = 2;
+const TRACE_COLUMN_INPUT_CHARS: c_int = 3;
+const TRACE_COLUMN_INPUT_HASH: c_int = 4;
+const TRACE_COLUMN_LATENCY_MS: c_int = 5;
+const TRACE_COLUMN_OK: c_int = 6;
+const TRACE_COLUMN_ERROR: c_int = 7;
+
+#[repr(C)]
+pub struct TraceTable {
+    base: sqlite3_vtab,
+    clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+}
+
+impl<'vtab> VTab<'vtab> for TraceTable {
+    type Aux = Rc<RefCell<HashMap<String, EmbeddingClient>>>;
+    type Cursor = TraceCursor<'vtab>;
+
+    fn connect(
+        _db: *mut sqlite3,
+        aux: Option<&Self::Aux>,
+        _args: VTabArguments,
+    ) -> Result<(String, TraceTable)> {
+        let base: sqlite3_vtab = unsafe { mem::zeroed() };
+        let vtab = TraceTable {
+            base,
+            clients: aux.expect("Required aux").clone(),
+        };
+        let sql = "create table x(client text, sequence integer, operation text, input_chars integer, \
+                   input_hash text, latency_ms integer, ok integer, error text)"
+            .to_owned();
+        Ok((sql, vtab))
+    }
+
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn best_index(&self, mut info: IndexInfo) -> core::result::Result<(), BestIndexError> {
+        info.set_estimated_cost(1000.0);
+        info.set_estimated_rows(trace_capacity() as i64);
+        info.set_idxnum(1);
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> Result<TraceCursor<'vtab>> {
+        Ok(TraceCursor {
+            base: unsafe { mem::zeroed() },
+            clients: self.clients.clone(),
+            multimodal_clients: self.multimodal_clients.clone(),
+            rows: Vec::new(),
+            rowid: 0,
+            phantom: PhantomData,
+        })
+    }
+}
+
+#[repr(C)]
+pub struct TraceCursor<'vtab> {
+    base: sqlite3_vtab_cursor,
+    clients: Rc<RefCell<HashMap<String, EmbeddingClient>>>,
+    rows: Vec<(String, TraceRecord)>,
+    rowid: i64,
+    phantom: PhantomData<&'vtab TraceTable>,
+}
+
+impl VTabCursor for TraceCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        _values: &[*mut sqlite3_value],
+    ) -> Result<()> {
+        // The most recent calls across every client, oldest first
+        let mut rows: Vec<(String, TraceRecord)> = self
+            .clients
+            .borrow()
+            .iter()
+            .flat_map(|(name, client)| client.trace().into_iter().map(move |record| (name.clone(), record)))
+            .collect();
+        rows.sort_by_key(|(_, record)| record.sequence);
+        let excess = rows.len().saturating_sub(trace_capacity());
+        rows.drain(..excess);
+        self.rows = rows;
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        (self.rowid as usize) >= self.rows.len()
+    }
+
+    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
+        let (client, record) = self
+            .rows
+            .get(self.rowid as usize)
+            .expect("Internal rembed_trace logic error");
+        match (i, &record.error) {
+            (TRACE_COLUMN_CLIENT, _) => api::result_text(context, client)?,
+            (TRACE_COLUMN_SEQUENCE, _) => api::result_int64(context, record.sequence as i64),
+            (TRACE_COLUMN_OPERATION, _) => api::result_text(context, record.operation)?,
+            (TRACE_COLUMN_INPUT_CHARS, _) => api::result_int64(context, record.input_chars as i64),
+            (TRACE_COLUMN_INPUT_HASH, _) => api::result_text(context, &record.input_hash)?,
+            (TRACE_COLUMN_LATENCY_MS, _) => api::result_int64(context, record.latency.as_millis() as i64),
+            (TRACE_COLUMN_OK, error) => api::result_int64(context, error.is_none() as i64),
+            (TRACE_COLUMN_ERROR, Some(error)) => api::result_text(context, error)?,
+            _ => api::result_null(context),
+        };
+        Ok(())
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+/// Serialize registered clients as a JSON array of {name, model, provider, options}.
+/// API keys are never exported; importing relies on the provider's env var instead.
+fn export_clients(
+    clients: &HashMap<String, EmbeddingClient>,
+    multimodal_clients: &HashMap<String, MultimodalClient>,
+) -> serde_json::Value {
+    let mut entries: Vec<serde_json::Value> = clients
+        .iter()
+        .map(|(name, client)| {
+            serde_json::json!({
+                "name": name,
+                "model": client.model(),
+                "provider": provider_from_model(client.model()),
+                "options": client.settings().to_options(),
+            })
+        })
+        .collect();
+
+    entries.extend(multimodal_clients.iter().map(|(name, client)| {
+        let mut options = client.vision_config().to_options();
+        options.insert("embedding_model".to_string(), client.embedding_model().to_string());
+        serde_json::json!({
+            "name": name,
+            "model": client.vision_model(),
+            "provider": provider_from_model(client.vision_model()),
+            "options": options,
+        })
+    }));
+
+    // HashMap order is arbitrary; sort so exports are stable
+    entries.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    serde_json::Value::Array(entries)
+}
+
+/// Register every client in an exported JSON array, replacing existing clients
+/// with the same name. Returns the number of clients imported.
+fn import_clients(
+    json: &str,
+    clients: &mut HashMap<String, EmbeddingClient>,
+    multimodal_clients: &mut HashMap<String, MultimodalClient>,
+    registrations: &mut Registrations,
+) -> Result<usize> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(json)
+        .map_err(|e| Error::new_message(format!("Invalid JSON array: {}", e)))?;
+
+    // Build every client before registering any, so a bad entry imports nothing
+    let mut imported_clients = Vec::new();
+    let mut imported_multimodal = Vec::new();
+    for entry in &entries {
+        let name = entry["name"]
+            .as_str()
+            .ok_or_else(|| Error::new_message("Each client needs a string 'name'"))?;
+        let model = entry["model"]
+            .as_str()
+            .ok_or_else(|| Error::new_message(format!("Client {} needs a string 'model'", name)))?;
+        let options = json_to_options(&entry["options"]);
+
+        if let Some(embedding_model) = options.get("embedding_model") {
+            let client = MultimodalClient::new(model.to_string(), embedding_model.clone())?
+                .with_vision_config(VisionConfig::from_options(&options)?);
+            imported_multimodal.push((name.to_string(), client));
+        } else {
+            let settings = ClientSettings::from_options(&options)?;
+            let client = EmbeddingClient::with_settings(model.to_string(), None, settings)?;
+            imported_clients.push((name.to_string(), client));
+        }
+    }
+
+    // Check fallbacks against the clients as they will be after the import
+    let mut merged = clients.clone();
+    for (name, _) in &imported_multimodal {
+        merged.remove(name);
+    }
+    for (name, client) in &imported_clients {
+        merged.insert(name.clone(), client.clone());
+    }
+    for (name, client) in &imported_clients {
+        check_fallback_cycle(&merged, name, client)?;
+    }
+
+    let count = imported_clients.len() + imported_multimodal.len();
+    for (name, client) in imported_clients {
+        multimodal_clients.remove(&name);
+        registrations.record(&name);
+        clients.insert(name, client);
+    }
+    for (name, client) in imported_multimodal {
+        clients.remove(&name);
+        registrations.record(&name);
+        multimodal_clients.insert(name, client);
+    }
+    Ok(count)
+}
+
+pub fn rembed_clients_export(
+    context: *mut sqlite3_context,
+    _values: &[*mut sqlite3_value],
+    aux: &ClientsTableAux,
+) -> Result<()> {
+    let exported = export_clients(&aux.clients.borrow(), &aux.multimodal_clients.borrow());
+    api::result_text(context, exported.to_string())?;
+    Ok(())
+}
+
+pub fn rembed_clients_import(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    aux: &ClientsTableAux,
+) -> Result<()> {
+    let json = api::value_text(&values[0])?;
+    let count = import_clients(
+        json,
+        &mut aux.clients.borrow_mut(),
+        &mut aux.multimodal_clients.borrow_mut(),
+        &mut aux.registrations.borrow_mut(),
+    )?;
+    api::result_int64(context, count as i64);
+    Ok(())
+}
+
+/// Remove a client from whichever map holds it, releasing its genai client.
+/// Returns whether a client was removed.
+fn drop_client(
+    name: &str,
+    clients: &mut HashMap<String, EmbeddingClient>,
+    multimodal_clients: &mut HashMap<String, MultimodalClient>,
+) -> bool {
+    let removed = clients.remove(name).is_some();
+    multimodal_clients.remove(name).is_some() || removed
+}
+
+pub fn rembed_client_drop(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    aux: &ClientsTableAux,
+) -> Result<()> {
+    // Never errors: a non-text name simply matches no client
+    let removed = match api::value_text(&values[0]) {
+        Ok(name) => {
+            aux.registrations.borrow_mut().remove(name);
+            drop_client(
+                name,
+                &mut aux.clients.borrow_mut(),
+                &mut aux.multimodal_clients.borrow_mut(),
+            )
+        }
+        Err(_) => false,
+    };
+    api::result_int64(context, removed as i64);
+    Ok(())
+}
+
+// Start the shared runtime ahead of a client's first call, so that call
+// doesn't pay for it mid-query. Nothing is sent to the provider.
+pub fn rembed_warm(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    aux: &ClientsTableAux,
+) -> Result<()> {
+    let name = api::value_text(&values[0])?;
+    if client_kind(&aux.clients.borrow(), &aux.multimodal_clients.borrow(), name).is_none() {
+        return Err(Error::new_message(format!(
+            "Client with name {} was not registered with rembed_clients.",
+            name
+        )));
+    }
+    RUNTIME.warm();
+    api::result_int64(context, 1);
+    Ok(())
+}
+
+// Release every client and stop the shared runtime's worker threads, e.g.
+// before the host unloads the extension. Returns the number of clients removed.
+pub fn rembed_shutdown(
+    context: *mut sqlite3_context,
+    _values: &[*mut sqlite3_value],
+    aux: &ClientsTableAux,
+) -> Result<()> {
+    let mut clients = aux.clients.borrow_mut();
+    let mut multimodal_clients = aux.multimodal_clients.borrow_mut();
+    let removed = clients.len() + multimodal_clients.len();
+    clients.clear();
+    multimodal_clients.clear();
+    *aux.registrations.borrow_mut() = Registrations::default();
+    RUNTIME.shutdown();
+    api::result_int64(context, removed as i64);
+    Ok(())
+}
+
+// For now, we'll focus on the scalar batch function approach
+// Table function implementation can be added later when sqlite-loadable has better support
+
+// Image embedding using hybrid approach (vision model → text → embedding)
+pub fn rembed_image(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    multimodal_clients: &Rc<RefCell<HashMap<String, MultimodalClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let image_blob = api::value_blob(&values[1]);
+
+    let clients_map = multimodal_clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Multimodal client with name {} was not registered.",
+            client_name
+        ))
+    })?;
+
+    // Generate embedding using hybrid approach
+    let embedding = client.embed_image_sync(image_blob)?;
+
+    result_embedding(context, &embedding, Precision::F32);
+    Ok(())
+}
+
+// Hits, misses and entries of a multimodal client's description cache, as JSON
+pub fn rembed_description_cache_stats(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    multimodal_clients: &Rc<RefCell<HashMap<String, MultimodalClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+
+    let clients_map = multimodal_clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Multimodal client with name {} was not registered.",
+            client_name
+        ))
+    })?;
+
+    let stats = client.description_cache_stats();
+    let result = serde_json::json!({
+        "hits": stats.hits,
+        "misses": stats.misses,
+        "entries": stats.entries,
+    });
+    api::result_text(context, result.to_string())?;
+    Ok(())
+}
 
-    fn eof(&self) -> bool {
-        (self.rowid as usize) >= self.keys.len()
-    }
+// Image embedding with an explicit MIME type for non-JPEG images
+pub fn rembed_image_typed(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    multimodal_clients: &Rc<RefCell<HashMap<String, MultimodalClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let image_blob = api::value_blob(&values[1]);
+    let mime_type = api::value_text(&values[2])?;
 
-    fn column(&self, context: *mut sqlite3_context, i: c_int) -> Result<()> {
-        let key = self
-            .keys
-            .get(self.rowid as usize)
-            .expect("Internal rembed_clients logic error");
-        match column(i) {
-            Some(Columns::Name) => api::result_text(context, key)?,
-            Some(Columns::Options) => {
-                // Check what type of client this is for debugging
-                let clients = self.clients.borrow();
-                if clients.contains_key(key) {
-                    api::result_text(context, "(embedding client)")?;
-                } else {
-                    drop(clients);
-                    let multimodal = self.multimodal_clients.borrow();
-                    if multimodal.contains_key(key) {
-                        api::result_text(context, "(multimodal client)")?;
-                    }
-                    // If neither, return NULL
-                }
-            },
-            None => (),
-        };
-        Ok(())
-    }
+    let clients_map = multimodal_clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Multimodal client with name {} was not registered.",
+            client_name
+        ))
+    })?;
 
-    fn rowid(&self) -> Result<i64> {
-        Ok(self.rowid)
-    }
-}
+    let embedding = client.embed_image_typed_sync(image_blob, mime_type)?;
 
-// For now, we'll focus on the scalar batch function approach
-// Table function implementation can be added later when sqlite-loadable has better support
+    result_embedding(context, &embedding, Precision::F32);
+    Ok(())
+}
 
-// Image embedding using hybrid approach (vision model → text → embedding)
-pub fn rembed_image(
+// Image embedding from a "data:image/<type>;base64,..." URI, using its declared MIME type
+pub fn rembed_image_datauri(
     context: *mut sqlite3_context,
     values: &[*mut sqlite3_value],
     multimodal_clients: &Rc<RefCell<HashMap<String, MultimodalClient>>>,
 ) -> Result<()> {
     let client_name = api::value_text(&values[0])?;
-    let image_blob = api::value_blob(&values[1]);
+    let (mime_type, image_data) = multimodal::parse_data_uri(api::value_text(&values[1])?)?;
 
     let clients_map = multimodal_clients.borrow();
     let client = clients_map.get(client_name).ok_or_else(|| {
@@ -415,11 +2183,9 @@ pub fn rembed_image(
         ))
     })?;
 
-    // Generate embedding using hybrid approach
-    let embedding = client.embed_image_sync(image_blob)?;
+    let embedding = client.embed_image_typed_sync(&image_data, &mime_type)?;
 
-    api::result_blob(context, embedding.as_bytes());
-    api::result_subtype(context, FLOAT32_VECTOR_SUBTYPE);
+    result_embedding(context, &embedding, Precision::F32);
     Ok(())
 }
 
@@ -444,8 +2210,44 @@ pub fn rembed_image_prompt(
     // Generate embedding with custom prompt
     let embedding = client.embed_image_with_prompt_sync(image_blob, prompt)?;
 
-    api::result_blob(context, embedding.as_bytes());
-    api::result_subtype(context, FLOAT32_VECTOR_SUBTYPE);
+    result_embedding(context, &embedding, Precision::F32);
+    Ok(())
+}
+
+// Several images described together and embedded as one vector
+pub fn rembed_images_combined(
+    context: *mut sqlite3_context,
+    values: &[*mut sqlite3_value],
+    multimodal_clients: &Rc<RefCell<HashMap<String, MultimodalClient>>>,
+) -> Result<()> {
+    let client_name = api::value_text(&values[0])?;
+    let json_input = api::value_text(&values[1])?;
+
+    // Parse JSON array of base64-encoded images
+    let images_base64: Vec<String> = serde_json::from_str(json_input)
+        .map_err(|e| Error::new_message(format!("Invalid JSON array: {}", e)))?;
+
+    let clients_map = multimodal_clients.borrow();
+    let client = clients_map.get(client_name).ok_or_else(|| {
+        Error::new_message(format!(
+            "Multimodal client with name {} was not registered.",
+            client_name
+        ))
+    })?;
+
+    // Decode base64 images
+    let mut images: Vec<Vec<u8>> = Vec::new();
+    for img_base64 in &images_base64 {
+        use base64::Engine as _;
+        let img_data = base64::engine::general_purpose::STANDARD.decode(img_base64)
+            .map_err(|e| Error::new_message(format!("Base64 decode failed: {}", e)))?;
+        images.push(img_data);
+    }
+
+    let image_refs: Vec<&[u8]> = images.iter().map(|v| v.as_slice()).collect();
+    let embedding = client.embed_images_combined_sync(image_refs)?;
+
+    result_embedding(context, &embedding, Precision::F32);
     Ok(())
 }
 
@@ -514,6 +2316,9 @@ pub fn rembed_images_concurrent(
 
 #[sqlite_entrypoint]
 pub fn sqlite3_rembed_init(db: *mut sqlite3) -> Result<()> {
+    let subtype = parse_vector_subtype(std::env::var(VECTOR_SUBTYPE_ENV).ok().as_deref())?;
+    VECTOR_SUBTYPE.store(subtype, Ordering::Relaxed);
+
     let flags = FunctionFlags::UTF8
         | FunctionFlags::DETERMINISTIC
         | unsafe { FunctionFlags::from_bits_unchecked(0x001000000) };
@@ -549,9 +2354,36 @@ pub fn sqlite3_rembed_init(db: *mut sqlite3) -> Result<()> {
         FunctionFlags::UTF8 | FunctionFlags::DETERMINISTIC,
     )?;
 
+    define_scalar_function_with_aux(db, "rembed", 1, rembed, flags, Rc::clone(&clients))?;
     define_scalar_function_with_aux(db, "rembed", 2, rembed, flags, Rc::clone(&clients))?;
     define_scalar_function_with_aux(db, "rembed", 3, rembed, flags, Rc::clone(&clients))?;
 
+    for n_args in 1..=3 {
+        define_scalar_function_with_aux(
+            db,
+            "rembed_json",
+            n_args,
+            rembed_json,
+            FunctionFlags::UTF8 | FunctionFlags::DETERMINISTIC,
+            Rc::clone(&clients),
+        )?;
+    }
+
+    // rembed without DETERMINISTIC, so SQLite calls the provider for every row
+    // instead of reusing results for identical arguments
+    let nondeterministic_flags = FunctionFlags::UTF8
+        | unsafe { FunctionFlags::from_bits_unchecked(0x001000000) };
+    for n_args in 1..=3 {
+        define_scalar_function_with_aux(
+            db,
+            "rembed_nd",
+            n_args,
+            rembed,
+            nondeterministic_flags,
+            Rc::clone(&clients),
+        )?;
+    }
+
     define_scalar_function(
         db,
         "rembed_client_options",
@@ -560,14 +2392,147 @@ pub fn sqlite3_rembed_init(db: *mut sqlite3) -> Result<()> {
         flags,
     )?;
 
+    define_scalar_function_with_aux(
+        db,
+        "rembed_with_usage",
+        2,
+        rembed_with_usage,
+        FunctionFlags::UTF8 | FunctionFlags::DETERMINISTIC,
+        Rc::clone(&clients),
+    )?;
+
+    define_scalar_function_with_aux(
+        db,
+        "rembed_meta",
+        2,
+        rembed_meta,
+        FunctionFlags::UTF8 | FunctionFlags::DETERMINISTIC,
+        Rc::clone(&clients),
+    )?;
+
+    for n_args in 2..=3 {
+        define_scalar_function_with_aux(
+            db,
+            "rembed_debug_request",
+            n_args,
+            rembed_debug_request,
+            FunctionFlags::UTF8,
+            Rc::clone(&clients),
+        )?;
+    }
+
+    define_scalar_function_with_aux(
+        db,
+        "rembed_estimate_cost",
+        2,
+        rembed_estimate_cost,
+        FunctionFlags::UTF8,
+        Rc::clone(&clients),
+    )?;
+
+    // Aliases are process-wide, consulted when clients are registered
+    define_scalar_function(
+        db,
+        "rembed_register_alias",
+        2,
+        rembed_register_alias,
+        FunctionFlags::UTF8,
+    )?;
+
     // Create auxiliary data for the virtual table
+    let registrations = Rc::new(RefCell::new(Registrations::default()));
     let clients_table_aux = ClientsTableAux {
         clients: Rc::clone(&clients),
         multimodal_clients: Rc::clone(&multimodal_clients),
+        registrations: Rc::clone(&registrations),
     };
 
     define_virtual_table_writeablex::<ClientsTable>(db, "rembed_clients", Some(clients_table_aux))?;
 
+    // One (id, embedding, error) row per image of a JSON array of {id, base64_image}
+    define_table_function::<ImagesEachTable>(db, "rembed_images_each", Some(Rc::clone(&multimodal_clients)))?;
+    define_table_function::<SplitTable>(db, "rembed_split", None)?;
+    // Pings every client, so it only runs when queried explicitly
+    define_table_function::<HealthTable>(
+        db,
+        "rembed_health",
+        Some(ClientsTableAux {
+            clients: Rc::clone(&clients),
+            multimodal_clients: Rc::clone(&multimodal_clients),
+            registrations: Rc::clone(&registrations),
+        }),
+    )?;
+    define_table_function::<TraceTable>(db, "rembed_trace", Some(Rc::clone(&clients)))?;
+
+    // Share client registrations across machines (secrets are not exported)
+    define_scalar_function_with_aux(
+        db,
+        "rembed_clients_export",
+        0,
+        rembed_clients_export,
+        FunctionFlags::UTF8,
+        ClientsTableAux {
+            clients: Rc::clone(&clients),
+            multimodal_clients: Rc::clone(&multimodal_clients),
+            registrations: Rc::clone(&registrations),
+        },
+    )?;
+
+    define_scalar_function_with_aux(
+        db,
+        "rembed_clients_import",
+        1,
+        rembed_clients_import,
+        FunctionFlags::UTF8,
+        ClientsTableAux {
+            clients: Rc::clone(&clients),
+            multimodal_clients: Rc::clone(&multimodal_clients),
+            registrations: Rc::clone(&registrations),
+        },
+    )?;
+
+    // Free a client's resources without closing the connection
+    define_scalar_function_with_aux(
+        db,
+        "rembed_client_drop",
+        1,
+        rembed_client_drop,
+        FunctionFlags::UTF8,
+        ClientsTableAux {
+            clients: Rc::clone(&clients),
+            multimodal_clients: Rc::clone(&multimodal_clients),
+            registrations: Rc::clone(&registrations),
+        },
+    )?;
+
+    define_scalar_function_with_aux(
+        db,
+        "rembed_warm",
+        1,
+        rembed_warm,
+        FunctionFlags::UTF8,
+        ClientsTableAux {
+            clients: Rc::clone(&clients),
+            multimodal_clients: Rc::clone(&multimodal_clients),
+            registrations: Rc::clone(&registrations),
+        },
+    )?;
+
+    // The runtime restarts on the next request; reload the extension to
+    // restore the default clients
+    define_scalar_function_with_aux(
+        db,
+        "rembed_shutdown",
+        0,
+        rembed_shutdown,
+        FunctionFlags::UTF8,
+        ClientsTableAux {
+            clients: Rc::clone(&clients),
+            multimodal_clients: Rc::clone(&multimodal_clients),
+            registrations: Rc::clone(&registrations),
+        },
+    )?;
+
     // Batch embedding function
     define_scalar_function_with_aux(
         db,
@@ -578,6 +2543,79 @@ pub fn sqlite3_rembed_init(db: *mut sqlite3) -> Result<()> {
         Rc::clone(&clients),
     )?;
 
+    define_scalar_function_with_aux(
+        db,
+        "rembed_batch_typed",
+        3,
+        rembed_batch_typed,
+        flags,
+        Rc::clone(&clients),
+    )?;
+
+    define_scalar_function_with_aux(
+        db,
+        "rembed_document",
+        2,
+        rembed_document,
+        flags,
+        Rc::clone(&clients),
+    )?;
+
+    // SQLITE_SUBTYPE, so the query's precision=f64 tag is visible
+    define_scalar_function(
+        db,
+        "rembed_similarity_json",
+        2,
+        rembed_similarity_json,
+        FunctionFlags::UTF8
+            | FunctionFlags::DETERMINISTIC
+            | unsafe { FunctionFlags::from_bits_unchecked(0x000100000) },
+    )?;
+
+    define_scalar_function(db, "rembed_pool", 2, rembed_pool, flags)?;
+    define_scalar_function(
+        db,
+        "rembed_distance_matrix",
+        2,
+        rembed_distance_matrix,
+        FunctionFlags::UTF8 | FunctionFlags::DETERMINISTIC,
+    )?;
+    define_centroid_aggregates(db, flags)?;
+    define_scalar_function(db, "rembed_f16_to_f32", 1, rembed_f16_to_f32, flags)?;
+    define_scalar_function(db, "rembed_f64_to_f32", 1, rembed_f64_to_f32, flags)?;
+    define_scalar_function(db, "rembed_f32_to_f16", 1, rembed_f32_to_f16, flags)?;
+    define_scalar_function(db, "rembed_blob_dimensions", 1, rembed_blob_dimensions, flags)?;
+    define_scalar_function(db, "rembed_strip_prefix", 1, rembed_strip_prefix, flags)?;
+    define_scalar_function(db, "rembed_blob_slice", 3, rembed_blob_slice, flags)?;
+    define_scalar_function(db, "rembed_blob_is_normalized", 1, rembed_blob_is_normalized, flags)?;
+    // SQLITE_SUBTYPE, so the argument's subtype is visible
+    define_scalar_function(
+        db,
+        "rembed_blob_subtype",
+        1,
+        rembed_blob_subtype,
+        FunctionFlags::UTF8
+            | FunctionFlags::DETERMINISTIC
+            | unsafe { FunctionFlags::from_bits_unchecked(0x000100000) },
+    )?;
+
+    // File contents can change between calls, and reading files should not be
+    // reachable from views or triggers
+    let file_flags = FunctionFlags::UTF8
+        | FunctionFlags::DIRECTONLY
+        | unsafe { FunctionFlags::from_bits_unchecked(0x001000000) };
+
+    for n_args in 2..=3 {
+        define_scalar_function_with_aux(
+            db,
+            "rembed_embed_file",
+            n_args,
+            rembed_embed_file,
+            file_flags,
+            Rc::clone(&clients),
+        )?;
+    }
+
     // Table function will be added in a future version when sqlite-loadable has better support
 
     // Image embedding functions (hybrid multimodal)
@@ -590,6 +2628,33 @@ pub fn sqlite3_rembed_init(db: *mut sqlite3) -> Result<()> {
         Rc::clone(&multimodal_clients),
     )?;
 
+    define_scalar_function_with_aux(
+        db,
+        "rembed_description_cache_stats",
+        1,
+        rembed_description_cache_stats,
+        FunctionFlags::UTF8,
+        Rc::clone(&multimodal_clients),
+    )?;
+
+    define_scalar_function_with_aux(
+        db,
+        "rembed_image_typed",
+        3,
+        rembed_image_typed,
+        flags,
+        Rc::clone(&multimodal_clients),
+    )?;
+
+    define_scalar_function_with_aux(
+        db,
+        "rembed_image_datauri",
+        2,
+        rembed_image_datauri,
+        flags,
+        Rc::clone(&multimodal_clients),
+    )?;
+
     define_scalar_function_with_aux(
         db,
         "rembed_image_prompt",
@@ -599,6 +2664,15 @@ pub fn sqlite3_rembed_init(db: *mut sqlite3) -> Result<()> {
         Rc::clone(&multimodal_clients),
     )?;
 
+    define_scalar_function_with_aux(
+        db,
+        "rembed_images_combined",
+        2,
+        rembed_images_combined,
+        flags,
+        Rc::clone(&multimodal_clients),
+    )?;
+
     // High-performance concurrent image batch processing
     define_scalar_function_with_aux(
         db,
@@ -617,6 +2691,314 @@ pub fn sqlite3_rembed_init(db: *mut sqlite3) -> Result<()> {
             "ollama::nomic-embed-text".to_string(),
         )?,
     );
+    registrations.borrow_mut().record("ollama-multimodal");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vector_subtype() {
+        assert_eq!(parse_vector_subtype(None).unwrap(), FLOAT32_VECTOR_SUBTYPE);
+        assert_eq!(parse_vector_subtype(Some("")).unwrap(), FLOAT32_VECTOR_SUBTYPE);
+        assert_eq!(parse_vector_subtype(Some("0")).unwrap(), 0);
+        assert_eq!(parse_vector_subtype(Some(" 74 ")).unwrap(), 74);
+        assert!(parse_vector_subtype(Some("256")).is_err());
+        assert!(parse_vector_subtype(Some("-1")).is_err());
+        assert!(parse_vector_subtype(Some("vec")).is_err());
+        assert!(parse_vector_subtype(Some("226")).is_err());
+        assert!(parse_vector_subtype(Some("227")).is_err());
+    }
+
+    #[test]
+    fn test_read_text_file() {
+        let dir = std::env::temp_dir();
+        let text_path = dir.join(format!("rembed-file-{}.txt", std::process::id()));
+        std::fs::write(&text_path, "hello from a file\n").unwrap();
+        let text_path = text_path.to_str().unwrap();
+
+        assert_eq!(read_text_file(text_path, 1024).unwrap(), "hello from a file\n");
+        let err = read_text_file(text_path, 4).unwrap_err();
+        assert!(format!("{:?}", err).contains("byte limit"));
+
+        let binary_path = dir.join(format!("rembed-file-{}.bin", std::process::id()));
+        std::fs::write(&binary_path, [0xff, 0xfe, 0x00]).unwrap();
+        let err = read_text_file(binary_path.to_str().unwrap(), 1024).unwrap_err();
+        assert!(format!("{:?}", err).contains("UTF-8"));
+
+        assert!(read_text_file("/nonexistent/rembed.txt", 1024).is_err());
+
+        std::fs::remove_file(text_path).unwrap();
+        std::fs::remove_file(binary_path).unwrap();
+    }
+
+    #[test]
+    fn test_document_chunks_and_pooling() {
+        let mut options = HashMap::new();
+        options.insert("chunk_size".to_string(), "40".to_string());
+        options.insert("chunk_overlap".to_string(), "10".to_string());
+        let client = EmbeddingClient::with_settings(
+            "ollama::nomic-embed-text".to_string(),
+            None,
+            ClientSettings::from_options(&options).unwrap(),
+        )
+        .unwrap();
+
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(5);
+        let chunks = document_chunks(&text, &client).unwrap();
+        // 225 characters in 40-character windows with a 30-character stride
+        assert_eq!(chunks.len(), 8);
+
+        let pool = |chunks: &[&str]| {
+            let embeddings: Vec<Vec<f32>> = chunks
+                .iter()
+                .map(|chunk| mock_provider::generate_mock_embedding(chunk, 8).unwrap())
+                .collect();
+            vector::mean_pool_normalized(&embeddings).unwrap()
+        };
+        let pooled = pool(&chunks);
+        assert_eq!(pooled, pool(&document_chunks(&text, &client).unwrap()));
+        let norm = pooled.iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+
+        let blank = " ".repeat(100);
+        assert!(document_chunks(&blank, &client).is_err());
+
+        options.insert("document_prefix".to_string(), "passage: ".to_string());
+        let prefixed = EmbeddingClient::with_settings(
+            "ollama::nomic-embed-text".to_string(),
+            None,
+            ClientSettings::from_options(&options).unwrap(),
+        )
+        .unwrap();
+        let inputs = document_inputs(&text, &prefixed).unwrap();
+        assert_eq!(inputs.len(), chunks.len());
+        for (input, chunk) in inputs.iter().zip(&chunks) {
+            assert_eq!(input, &format!("passage: {}", chunk));
+        }
+    }
+
+    #[test]
+    fn test_fallback_cycle() {
+        let with_fallback = |fallback: &str| {
+            let mut options = HashMap::new();
+            options.insert("fallback".to_string(), fallback.to_string());
+            EmbeddingClient::with_settings(
+                "ollama::nomic-embed-text".to_string(),
+                None,
+                ClientSettings::from_options(&options).unwrap(),
+            )
+            .unwrap()
+        };
+
+        let mut clients = HashMap::new();
+        assert!(check_fallback_cycle(&clients, "a", &with_fallback("a")).is_err());
+
+        // a -> b is fine while b is unregistered or ends the chain
+        assert!(check_fallback_cycle(&clients, "a", &with_fallback("b")).is_ok());
+        clients.insert("a".to_string(), with_fallback("b"));
+        clients.insert("c".to_string(), with_fallback("a"));
+
+        let err = check_fallback_cycle(&clients, "b", &with_fallback("c")).unwrap_err();
+        assert!(err.to_string().contains("b -> c -> a -> b"), "{}", err);
+        assert!(check_fallback_cycle(&clients, "b", &with_fallback("d")).is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_client_name() {
+        let mut clients = HashMap::new();
+        let mut multimodal_clients = HashMap::new();
+        assert!(check_client_name("a", &clients, &multimodal_clients, false).is_ok());
+
+        clients.insert("a".to_string(), EmbeddingClient::new("ollama::nomic-embed-text".to_string(), None).unwrap());
+        multimodal_clients.insert(
+            "m".to_string(),
+            MultimodalClient::new("ollama::llava".to_string(), "ollama::nomic-embed-text".to_string()).unwrap(),
+        );
+        for name in ["a", "m"] {
+            let err = check_client_name(name, &clients, &multimodal_clients, false).unwrap_err();
+            assert!(err.to_string().contains("already registered"), "{}", err);
+            assert!(check_client_name(name, &clients, &multimodal_clients, true).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_clients_export_import_round_trip() {
+        let mut clients = HashMap::new();
+        let mut options = HashMap::new();
+        options.insert("timeout_secs".to_string(), "5".to_string());
+        options.insert("precision".to_string(), "f16".to_string());
+        clients.insert(
+            "tuned".to_string(),
+            EmbeddingClient::with_settings(
+                "openai::text-embedding-3-small".to_string(),
+                None,
+                ClientSettings::from_options(&options).unwrap(),
+            )
+            .unwrap(),
+        );
+        clients.insert(
+            "local".to_string(),
+            EmbeddingClient::new("ollama::nomic-embed-text".to_string(), None).unwrap(),
+        );
+        let mut multimodal_clients = HashMap::new();
+
+        let exported = export_clients(&clients, &multimodal_clients);
+        assert_eq!(exported[0]["name"], "local");
+        assert_eq!(exported[0]["provider"], "ollama");
+        assert_eq!(exported[1]["options"]["precision"], "f16");
+
+        let (mut fresh, mut fresh_multimodal) = (HashMap::new(), HashMap::new());
+        let mut registrations = Registrations::default();
+        let json = exported.to_string();
+        assert_eq!(import_clients(&json, &mut fresh, &mut fresh_multimodal, &mut registrations).unwrap(), 2);
+        assert_eq!(export_clients(&fresh, &fresh_multimodal), exported);
+        assert_eq!((registrations.seq("local"), registrations.seq("tuned")), (Some(1), Some(2)));
+
+        // Re-importing updates the existing registrations instead of duplicating them
+        let mut registrations = Registrations::default();
+        assert_eq!(import_clients(&json, &mut clients, &mut multimodal_clients, &mut registrations).unwrap(), 2);
+        assert_eq!(clients.len(), 2);
+
+        assert!(import_clients(r#"[{"model": "openai::x"}]"#, &mut fresh, &mut fresh_multimodal, &mut registrations).is_err());
+    }
+
+    #[test]
+    fn test_import_fallback_cycle() {
+        let (mut clients, mut multimodal_clients) = (HashMap::new(), HashMap::new());
+        let mut registrations = Registrations::default();
+        let json = r#"[
+            {"name": "a", "model": "openai::x", "options": {"fallback": "b"}},
+            {"name": "b", "model": "openai::x", "options": {"fallback": "a"}}
+        ]"#;
+        let err = import_clients(json, &mut clients, &mut multimodal_clients, &mut registrations).unwrap_err();
+        assert!(err.to_string().contains("Fallback cycle"));
+        assert!(clients.is_empty() && registrations.seq("a").is_none());
+
+        // An import can also close a cycle through an already registered client
+        let mut options = HashMap::new();
+        options.insert("fallback".to_string(), "c".to_string());
+        clients.insert(
+            "b".to_string(),
+            EmbeddingClient::with_settings(
+                "openai::x".to_string(),
+                None,
+                ClientSettings::from_options(&options).unwrap(),
+            )
+            .unwrap(),
+        );
+        let json = r#"[{"name": "c", "model": "openai::x", "options": {"fallback": "b"}}]"#;
+        assert!(import_clients(json, &mut clients, &mut multimodal_clients, &mut registrations).is_err());
+        assert_eq!(clients.len(), 1);
+    }
+
+    #[test]
+    fn test_text_embedding_base64() {
+        use base64::Engine as _;
+        let decode = |encoded: String| base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        let value = 0.1f64;
+        let embedding = |precision| TextEmbedding {
+            values: vec![value],
+            rounding: F32Rounding::default(),
+            format: (precision, OutputFormat::Raw, Endian::Native),
+        };
+
+        // precision=f64 keeps the provider's value rather than widening f32
+        assert_eq!(decode(embedding(Precision::F64).base64()), value.to_ne_bytes());
+        assert_eq!(decode(embedding(Precision::F32).base64()), (value as f32).to_ne_bytes());
+
+        let big = TextEmbedding { format: (Precision::F32, OutputFormat::Raw, Endian::Big), ..embedding(Precision::F32) };
+        assert_eq!(decode(big.base64()), (value as f32).to_be_bytes());
+    }
+
+    #[test]
+    fn test_batch_json() {
+        let format = (Precision::F32, OutputFormat::Raw, Endian::Native);
+        let embeddings = vec![Some(vec![1.0]), None, Some(vec![2.0])];
+        let array = batch_json(embeddings.clone(), format, BatchOutput::Array);
+        assert_eq!(array.as_array().unwrap().len(), 3);
+        assert!(array[1].is_null());
+
+        let objects = batch_json(embeddings.clone(), format, BatchOutput::Objects);
+        let objects = objects.as_array().unwrap();
+        for (position, object) in objects.iter().enumerate() {
+            assert_eq!(object["index"], position);
+            assert_eq!(object["embedding"], array[position]);
+        }
+        assert!(objects[1]["embedding"].is_null());
+        assert_eq!(vector::f32_from_base64(objects[2]["embedding"].as_str().unwrap()).unwrap(), vec![2.0]);
+
+        // Batch outputs follow the client's byte order and output format
+        let big = batch_json(embeddings, (Precision::F32, OutputFormat::Prefixed, Endian::Big), BatchOutput::Array);
+        let bytes = vector::bytes_from_base64(big[2].as_str().unwrap()).unwrap();
+        assert_eq!(bytes, [&1u32.to_le_bytes()[..], &2f32.to_be_bytes()[..]].concat());
+    }
+
+    #[test]
+    fn test_similarity_scores() {
+        use base64::Engine as _;
+        let encode = |v: &[f32]| base64::engine::general_purpose::STANDARD.encode(v.as_bytes());
+        let candidates = serde_json::json!([
+            encode(&[1.0, 0.0]),
+            encode(&[0.0, 1.0]),
+            encode(&[-2.0, 0.0]),
+        ])
+        .to_string();
+
+        let scores = similarity_scores(&[1.0, 0.0], &candidates).unwrap();
+        assert_eq!(scores, vec![1.0, 0.0, -1.0]);
+
+        let mismatched = serde_json::json!([encode(&[1.0, 0.0]), encode(&[1.0, 0.0, 0.0])]).to_string();
+        let err = similarity_scores(&[1.0, 0.0], &mismatched).unwrap_err();
+        assert!(format!("{:?}", err).contains("Candidate 1"));
+    }
+
+    #[test]
+    fn test_client_kind() {
+        let mut clients = HashMap::new();
+        clients.insert(
+            "text".to_string(),
+            EmbeddingClient::new("ollama::nomic-embed-text".to_string(), None).unwrap(),
+        );
+        let mut multimodal_clients = HashMap::new();
+        multimodal_clients.insert(
+            "vision".to_string(),
+            MultimodalClient::new(
+                "ollama::llava:7b".to_string(),
+                "ollama::nomic-embed-text".to_string(),
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(client_kind(&clients, &multimodal_clients, "text"), Some("(embedding client)"));
+        assert_eq!(client_kind(&clients, &multimodal_clients, "vision"), Some("(multimodal client)"));
+        // A key that disappeared from both maps maps to NULL in the options column
+        assert_eq!(client_kind(&clients, &multimodal_clients, "gone"), None);
+    }
+
+    #[test]
+    fn test_drop_client() {
+        let mut clients = HashMap::new();
+        clients.insert(
+            "text".to_string(),
+            EmbeddingClient::new("ollama::nomic-embed-text".to_string(), None).unwrap(),
+        );
+        let mut multimodal_clients = HashMap::new();
+        multimodal_clients.insert(
+            "vision".to_string(),
+            MultimodalClient::new(
+                "ollama::llava:7b".to_string(),
+                "ollama::nomic-embed-text".to_string(),
+            )
+            .unwrap(),
+        );
+
+        assert!(drop_client("text", &mut clients, &mut multimodal_clients));
+        assert!(drop_client("vision", &mut clients, &mut multimodal_clients));
+        assert!(clients.is_empty() && multimodal_clients.is_empty());
+        assert!(!drop_client("text", &mut clients, &mut multimodal_clients));
+    }
+}
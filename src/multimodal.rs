@@ -1,20 +1,20 @@
 // Hybrid multimodal support using the LLaVA → text → embedding approach
 // Based on the examples from rsp2k/rust-genai fork
 
-use genai::{Client as GenAiClient, chat::{ChatMessage, ChatRequest, ContentPart}};
+use crate::genai_client::{build_genai_client, check_embedding_count, parse_option, LastError};
+use crate::runtime::RUNTIME;
+use crate::telemetry;
+use crate::vector::{self, F32Rounding};
+use genai::{Client as GenAiClient, chat::{ChatMessage, ChatOptions, ChatRequest, ContentPart}};
+use sha2::{Digest, Sha256};
 use sqlite_loadable::{Error, Result};
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::runtime::Runtime;
 use tokio::sync::Semaphore;
-use once_cell::sync::Lazy;
 use futures::stream::{self, StreamExt};
 
-/// Global tokio runtime for async operations
-static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
-    Runtime::new().expect("Failed to create tokio runtime")
-});
-
 /// Provider capabilities for intelligent routing
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -45,6 +45,172 @@ impl Default for PerformanceConfig {
     }
 }
 
+/// Prompt configuration for the vision (description) step
+#[derive(Debug, Clone, Default)]
+pub struct VisionConfig {
+    /// Fixed description schema (e.g. "Objects: ...; Scene: ...; Colors: ...")
+    /// enforced through the system prompt so descriptions stay consistent
+    pub description_template: Option<String>,
+    /// Send blobs whose format is not recognized instead of rejecting them
+    pub allow_unknown_image: bool,
+    /// Most image embeddings remembered by content hash; 0 sets no entry limit
+    pub cache_size: usize,
+    /// Most bytes of cached vectors; None sets no byte limit. The cache is
+    /// off unless cache_size or cache_max_bytes is set
+    pub cache_max_bytes: Option<usize>,
+    /// Longest side in pixels sent to the vision model; larger images are
+    /// downscaled first. None sends images at their original size
+    pub max_dimension: Option<u32>,
+    /// How the embedding model's f64 values are narrowed to f32
+    pub f32_round: F32Rounding,
+    /// Sampling temperature sent with vision requests; 0 for reproducible
+    /// descriptions. None leaves the provider's default
+    pub temperature: Option<f64>,
+    /// Sampling seed sent with vision requests, for providers that accept one
+    pub seed: Option<u64>,
+    /// Most vision descriptions remembered per image and prompt, so repeated
+    /// embeds of an image reuse the exact text; 0 turns the cache off
+    pub description_cache_size: usize,
+}
+
+impl VisionConfig {
+    /// Build vision settings from rembed_client_options key/value pairs
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self> {
+        let description_template = match options.get("description_template") {
+            Some(template) if template.trim().is_empty() => {
+                return Err(Error::new_message("'description_template' cannot be empty"));
+            }
+            Some(template) => Some(template.clone()),
+            None => None,
+        };
+
+        let allow_unknown_image = parse_option(options, "allow_unknown_image")?.unwrap_or(false);
+        let cache_size = parse_option(options, "cache_size")?.unwrap_or(0);
+        let cache_max_bytes = parse_option(options, "cache_max_bytes")?;
+        if cache_max_bytes == Some(0) {
+            return Err(Error::new_message("'cache_max_bytes' must be greater than 0"));
+        }
+        let max_dimension = parse_option(options, "max_dimension")?;
+        if max_dimension == Some(0) {
+            return Err(Error::new_message("'max_dimension' must be greater than 0"));
+        }
+
+        let f32_round = match options.get("f32_round") {
+            Some(f32_round) => f32_round.parse()?,
+            None => F32Rounding::default(),
+        };
+
+        let temperature = parse_option(options, "vision_temperature")?;
+        if temperature.is_some_and(|temperature: f64| !temperature.is_finite() || temperature < 0.0) {
+            return Err(Error::new_message("'vision_temperature' must be a non-negative number"));
+        }
+        let seed = parse_option(options, "vision_seed")?;
+        let description_cache_size = parse_option(options, "description_cache_size")?.unwrap_or(0);
+
+        Ok(Self {
+            description_template,
+            allow_unknown_image,
+            cache_size,
+            cache_max_bytes,
+            max_dimension,
+            f32_round,
+            temperature,
+            seed,
+            description_cache_size,
+        })
+    }
+
+    /// Option key/value pairs that reproduce this configuration via from_options
+    pub fn to_options(&self) -> HashMap<String, String> {
+        let mut options = HashMap::new();
+        if let Some(template) = &self.description_template {
+            options.insert("description_template".to_string(), template.clone());
+        }
+        if self.allow_unknown_image {
+            options.insert("allow_unknown_image".to_string(), "true".to_string());
+        }
+        if self.cache_size > 0 {
+            options.insert("cache_size".to_string(), self.cache_size.to_string());
+        }
+        if let Some(cache_max_bytes) = self.cache_max_bytes {
+            options.insert("cache_max_bytes".to_string(), cache_max_bytes.to_string());
+        }
+        if let Some(max_dimension) = self.max_dimension {
+            options.insert("max_dimension".to_string(), max_dimension.to_string());
+        }
+        if self.f32_round != F32Rounding::default() {
+            options.insert("f32_round".to_string(), self.f32_round.as_str().to_string());
+        }
+        if let Some(temperature) = self.temperature {
+            options.insert("vision_temperature".to_string(), temperature.to_string());
+        }
+        if let Some(seed) = self.seed {
+            options.insert("vision_seed".to_string(), seed.to_string());
+        }
+        if self.description_cache_size > 0 {
+            options.insert("description_cache_size".to_string(), self.description_cache_size.to_string());
+        }
+        options
+    }
+
+    /// Sampling options for vision requests, if any are configured
+    fn chat_options(&self) -> Option<ChatOptions> {
+        if self.temperature.is_none() && self.seed.is_none() {
+            return None;
+        }
+        let mut chat_options = ChatOptions::default();
+        if let Some(temperature) = self.temperature {
+            chat_options = chat_options.with_temperature(temperature);
+        }
+        if let Some(seed) = self.seed {
+            chat_options = chat_options.with_seed(seed);
+        }
+        Some(chat_options)
+    }
+
+    /// MIME type of an image blob from its magic bytes. Unrecognized blobs are
+    /// rejected before a vision request is spent on them, unless
+    /// allow_unknown_image is set, in which case they are sent as JPEG.
+    pub fn image_mime_type(&self, image_data: &[u8]) -> Result<&'static str> {
+        match sniff_image_mime_type(image_data) {
+            Some(mime_type) => Ok(mime_type),
+            None if self.allow_unknown_image => Ok(DEFAULT_IMAGE_MIME_TYPE),
+            None => Err(Error::new_message(
+                "Input is not a recognized image (expected PNG, JPEG, GIF, WebP, BMP or TIFF); \
+                 set the 'allow_unknown_image' option to send it anyway",
+            )),
+        }
+    }
+
+    /// Base64 payload and MIME type sent to the vision model for an image,
+    /// downscaled first when its longest side exceeds max_dimension
+    pub fn encode_image<'a>(&self, image_data: &[u8], mime_type: &'a str) -> Result<(String, &'a str)> {
+        use base64::Engine as _;
+        let downscaled = match self.max_dimension {
+            Some(max_dimension) => downscale_image(image_data, max_dimension)?,
+            None => None,
+        };
+        Ok(match downscaled {
+            Some((data, mime_type)) => (base64::engine::general_purpose::STANDARD.encode(data), mime_type),
+            None => (base64::engine::general_purpose::STANDARD.encode(image_data), mime_type),
+        })
+    }
+
+    /// Combine a base system prompt with the description template, if any
+    fn system_prompt(&self, base: Option<&str>) -> Option<String> {
+        match (base, &self.description_template) {
+            (base, Some(template)) => Some(format!(
+                "{}Always write the description using exactly this template, keeping the \
+                 section labels unchanged and replacing each placeholder:\n{}",
+                base.map(|b| format!("{}\n\n", b)).unwrap_or_default(),
+                template
+            )),
+            (Some(base), None) => Some(base.to_string()),
+            (None, None) => None,
+        }
+    }
+}
+
 /// Processing statistics for performance monitoring
 #[derive(Debug, Clone)]
 pub struct ProcessingStats {
@@ -55,6 +221,147 @@ pub struct ProcessingStats {
     pub avg_time_per_item: Duration,
 }
 
+/// Embeddings of images already seen, keyed by image_cache_key and
+/// evicting the oldest entries once either limit would be exceeded
+#[derive(Default)]
+struct ImageCache {
+    /// Most entries held; 0 means no entry limit
+    capacity: usize,
+    /// Most vector bytes held; None means no byte limit
+    max_bytes: Option<usize>,
+    bytes: usize,
+    entries: HashMap<[u8; 32], Vec<f32>>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl ImageCache {
+    fn new(capacity: usize, max_bytes: Option<usize>) -> Self {
+        Self { capacity, max_bytes, ..Self::default() }
+    }
+
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<f32>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: [u8; 32], embedding: Vec<f32>) {
+        let size = std::mem::size_of_val(embedding.as_slice());
+        let too_large = self.max_bytes.is_some_and(|max_bytes| size > max_bytes);
+        if (self.capacity == 0 && self.max_bytes.is_none()) || too_large || self.entries.contains_key(&key) {
+            return;
+        }
+        while (self.capacity > 0 && self.entries.len() >= self.capacity)
+            || self.max_bytes.is_some_and(|max_bytes| self.bytes + size > max_bytes)
+        {
+            let Some(oldest) = self.order.pop_front() else { break };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.bytes -= std::mem::size_of_val(evicted.as_slice());
+            }
+        }
+        self.bytes += size;
+        self.order.push_back(key);
+        self.entries.insert(key, embedding);
+    }
+}
+
+/// Lookups of a cache since its client was registered
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Vision descriptions already generated, keyed by description_cache_key and
+/// evicting the oldest once capacity is reached
+#[derive(Default)]
+struct DescriptionCache {
+    /// Most entries held; 0 turns the cache off
+    capacity: usize,
+    entries: HashMap<[u8; 32], String>,
+    order: VecDeque<[u8; 32]>,
+    hits: u64,
+    misses: u64,
+}
+
+impl DescriptionCache {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, ..Self::default() }
+    }
+
+    fn get(&mut self, key: &[u8; 32]) -> Option<String> {
+        let description = self.entries.get(key).cloned();
+        match description {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        description
+    }
+
+    fn insert(&mut self, key: [u8; 32], description: String) {
+        if self.capacity == 0 || self.entries.contains_key(&key) {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            self.entries.remove(&oldest);
+        }
+        self.order.push_back(key);
+        self.entries.insert(key, description);
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats { hits: self.hits, misses: self.misses, entries: self.entries.len() }
+    }
+}
+
+/// SHA-256 over the encoded image, the vision model and both prompts, so a
+/// cached description is only reused for the same vision request
+fn description_cache_key(image_base64: &str, vision_model: &str, system: Option<&str>, prompt: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in [image_base64, vision_model, system.unwrap_or_default(), prompt] {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// A description from the cache, or generated by describe and remembered
+async fn cached_description(
+    descriptions: &Mutex<DescriptionCache>,
+    key: impl FnOnce() -> [u8; 32],
+    describe: impl Future<Output = Result<String>>,
+) -> Result<String> {
+    if descriptions.lock().unwrap().capacity == 0 {
+        return describe.await;
+    }
+    let key = key();
+    let cached = descriptions.lock().unwrap().get(&key);
+    if let Some(description) = cached {
+        return Ok(description);
+    }
+    let description = describe.await?;
+    descriptions.lock().unwrap().insert(key, description.clone());
+    Ok(description)
+}
+
+/// SHA-256 over the image bytes, both models and the prompt (if any), so a
+/// cached vector is only reused for the same image through the same pipeline
+fn image_cache_key(image_data: &[u8], vision_model: &str, embedding_model: &str, prompt: Option<&str>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in [image_data, vision_model.as_bytes(), embedding_model.as_bytes()] {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    match prompt {
+        Some(prompt) => {
+            hasher.update([1]);
+            hasher.update(prompt.as_bytes());
+        }
+        None => hasher.update([0]),
+    }
+    hasher.finalize().into()
+}
+
 /// Hybrid multimodal client that combines vision and embedding models
 /// with future-ready support for native image embeddings
 #[derive(Clone)]
@@ -64,6 +371,10 @@ pub struct MultimodalClient {
     embedding_model: String,
     capabilities: ProviderCapabilities,
     performance_config: PerformanceConfig,
+    vision_config: VisionConfig,
+    last_error: LastError,
+    image_cache: Arc<Mutex<ImageCache>>,
+    descriptions: Arc<Mutex<DescriptionCache>>,
 }
 
 impl MultimodalClient {
@@ -82,14 +393,51 @@ impl MultimodalClient {
         let capabilities = Self::detect_capabilities(&embedding_model);
 
         Ok(Self {
-            client: Arc::new(GenAiClient::default()),
+            client: Arc::new(build_genai_client(None)),
             vision_model,
             embedding_model,
             capabilities,
             performance_config,
+            vision_config: VisionConfig::default(),
+            last_error: LastError::default(),
+            image_cache: Arc::default(),
+            descriptions: Arc::default(),
         })
     }
 
+    /// Set the prompt configuration used for the vision step
+    pub fn with_vision_config(mut self, vision_config: VisionConfig) -> Self {
+        self.image_cache = Arc::new(Mutex::new(ImageCache::new(vision_config.cache_size, vision_config.cache_max_bytes)));
+        self.descriptions = Arc::new(Mutex::new(DescriptionCache::new(vision_config.description_cache_size)));
+        self.vision_config = vision_config;
+        self
+    }
+
+    /// Most recent failure of this client, if its last call failed
+    pub fn last_error(&self) -> Option<(String, u64)> {
+        self.last_error.get()
+    }
+
+    /// Model used for the vision (description) step
+    pub fn vision_model(&self) -> &str {
+        &self.vision_model
+    }
+
+    /// Model used to embed generated descriptions
+    pub fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+
+    /// Prompt configuration for the vision step
+    pub fn vision_config(&self) -> &VisionConfig {
+        &self.vision_config
+    }
+
+    /// Hits, misses and entries of the description cache
+    pub fn description_cache_stats(&self) -> CacheStats {
+        self.descriptions.lock().unwrap().stats()
+    }
+
     /// Detect provider capabilities for intelligent routing
     fn detect_capabilities(model: &str) -> ProviderCapabilities {
         // Extract provider from model string (e.g., "openai::model" -> "openai")
@@ -133,6 +481,81 @@ impl MultimodalClient {
     /// - Uses native image embeddings if provider supports it (future)
     /// - Falls back to hybrid approach (vision → text → embedding) otherwise
     pub fn embed_image_sync(&self, image_data: &[u8]) -> Result<Vec<f32>> {
+        let mime_type = self.vision_config.image_mime_type(image_data)?;
+        self.embed_image_typed_sync(image_data, mime_type)
+    }
+
+    /// Look up an image embedding in the cache, computing and remembering it
+    /// on a miss
+    fn cached(
+        &self,
+        image_data: &[u8],
+        prompt: Option<&str>,
+        embed: impl FnOnce() -> Result<Vec<f32>>,
+    ) -> Result<Vec<f32>> {
+        let key = self.image_key(image_data, prompt);
+        if let Some(embedding) = self.cache_get(key.as_ref()) {
+            return Ok(embedding);
+        }
+        let embedding = embed()?;
+        self.cache_insert(key, &embedding);
+        Ok(embedding)
+    }
+
+    /// Like cached, for an embedding computed inside an async block
+    async fn cached_async(
+        &self,
+        image_data: &[u8],
+        prompt: Option<&str>,
+        embed: impl Future<Output = Result<Vec<f32>>>,
+    ) -> Result<Vec<f32>> {
+        let key = self.image_key(image_data, prompt);
+        if let Some(embedding) = self.cache_get(key.as_ref()) {
+            return Ok(embedding);
+        }
+        let embedding = embed.await?;
+        self.cache_insert(key, &embedding);
+        Ok(embedding)
+    }
+
+    /// Key of an image in the embedding cache, None when the cache is off
+    fn image_key(&self, image_data: &[u8], prompt: Option<&str>) -> Option<[u8; 32]> {
+        if self.vision_config.cache_size == 0 && self.vision_config.cache_max_bytes.is_none() {
+            return None;
+        }
+        Some(image_cache_key(image_data, &self.vision_model, &self.embedding_model, prompt))
+    }
+
+    fn cache_get(&self, key: Option<&[u8; 32]>) -> Option<Vec<f32>> {
+        key.and_then(|key| self.image_cache.lock().unwrap().get(key))
+    }
+
+    fn cache_insert(&self, key: Option<[u8; 32]>, embedding: &[f32]) {
+        if let Some(key) = key {
+            self.image_cache.lock().unwrap().insert(key, embedding.to_vec());
+        }
+    }
+
+    /// Send one minimal request to the embedding model, without retries or
+    /// last_error, returning how long it took and its outcome. The vision
+    /// model is not contacted.
+    pub(crate) async fn ping(&self, timeout: Duration) -> (Duration, Result<()>) {
+        let request = self.client.embed(&self.embedding_model, "ping".to_string(), None);
+        let started = Instant::now();
+        let result = match tokio::time::timeout(timeout, telemetry::traced("health", &self.embedding_model, request)).await {
+            Ok(result) => result.map(|_| ()).map_err(|e| Error::new_message(format!("Embedding failed: {}", e))),
+            Err(_) => Err(Error::new_message(format!("No response within {}ms", timeout.as_millis()))),
+        };
+        (started.elapsed(), result)
+    }
+
+    /// Process an image whose MIME type is known, e.g. "image/png"
+    pub fn embed_image_typed_sync(&self, image_data: &[u8], mime_type: &str) -> Result<Vec<f32>> {
+        validate_image_mime_type(mime_type)?;
+        self.cached(image_data, None, || self.embed_image_uncached(image_data, mime_type))
+    }
+
+    fn embed_image_uncached(&self, image_data: &[u8], mime_type: &str) -> Result<Vec<f32>> {
         // Check if provider supports native image embeddings
         if self.capabilities.supports_image_embeddings {
             // Future: Use native image embedding API when available
@@ -141,16 +564,18 @@ impl MultimodalClient {
         let client = self.client.clone();
         let vision_model = self.vision_model.clone();
         let embedding_model = self.embedding_model.clone();
-        use base64::Engine as _;
-        let image_base64 = base64::engine::general_purpose::STANDARD.encode(image_data);
+        let vision_config = self.vision_config.clone();
+        let descriptions = self.descriptions.clone();
+        let (image_base64, mime_type) = vision_config.encode_image(image_data, mime_type)?;
+        let mime_type = mime_type.to_string();
 
-        RUNTIME.block_on(async move {
+        self.last_error.track(RUNTIME.block_on(async move {
             // Step 1: Describe the image using vision model
-            let description = describe_image(&client, &vision_model, &image_base64).await?;
+            let description =
+                describe_image(&client, &vision_model, &vision_config, &descriptions, &image_base64, &mime_type).await?;
 
             // Step 2: Embed the description
-            client
-                .embed(&embedding_model, description, None)
+            telemetry::traced("embed", &embedding_model, client.embed(&embedding_model, description, None))
                 .await
                 .map_err(|e| Error::new_message(format!("Embedding failed: {}", e)))
                 .and_then(|response| {
@@ -158,31 +583,48 @@ impl MultimodalClient {
                         .first_embedding()
                         .ok_or_else(|| Error::new_message("No embedding in response"))
                         .map(|embedding| {
-                            embedding.vector().iter().map(|&v| v as f32).collect()
+                            vector::downcast(embedding.vector(), vision_config.f32_round)
                         })
                 })
-        })
+        }))
     }
 
-    /// Process multiple images in batch with original sequential method
+    /// Process multiple images in batch with original sequential method;
+    /// images already in the embedding cache are not described again
     pub fn embed_images_batch_sync(&self, images: Vec<&[u8]>) -> Result<Vec<Vec<f32>>> {
         let client = self.client.clone();
         let vision_model = self.vision_model.clone();
         let embedding_model = self.embedding_model.clone();
+        let vision_config = self.vision_config.clone();
+        let cache = self.descriptions.clone();
 
-        RUNTIME.block_on(async move {
-            // Step 1: Describe all images
+        let keys: Vec<Option<[u8; 32]>> = images.iter().map(|image_data| self.image_key(image_data, None)).collect();
+        let mut embeddings: Vec<Option<Vec<f32>>> = keys.iter().map(|key| self.cache_get(key.as_ref())).collect();
+        let uncached: Vec<&[u8]> = images
+            .iter()
+            .zip(&embeddings)
+            .filter(|(_, embedding)| embedding.is_none())
+            .map(|(image_data, _)| *image_data)
+            .collect();
+
+        let fresh: Vec<Vec<f32>> = self.last_error.track(RUNTIME.block_on(async move {
+            if uncached.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            // Step 1: Describe all uncached images
             let mut descriptions = Vec::new();
-            for image_data in images {
-                use base64::Engine as _;
-        let image_base64 = base64::engine::general_purpose::STANDARD.encode(image_data);
-                let description = describe_image(&client, &vision_model, &image_base64).await?;
+            for image_data in uncached {
+                let mime_type = vision_config.image_mime_type(image_data)?;
+                let (image_base64, mime_type) = vision_config.encode_image(image_data, mime_type)?;
+                let description =
+                    describe_image(&client, &vision_model, &vision_config, &cache, &image_base64, mime_type).await?;
                 descriptions.push(description);
             }
 
             // Step 2: Batch embed all descriptions
-            client
-                .embed_batch(&embedding_model, descriptions, None)
+            let expected = descriptions.len();
+            telemetry::traced("embed_batch", &embedding_model, client.embed_batch(&embedding_model, descriptions, None))
                 .await
                 .map_err(|e| Error::new_message(format!("Batch embedding failed: {}", e)))
                 .map(|response| {
@@ -190,21 +632,83 @@ impl MultimodalClient {
                         .embeddings
                         .into_iter()
                         .map(|embedding| {
-                            embedding.vector().iter().map(|&v| v as f32).collect()
+                            vector::downcast(embedding.vector(), vision_config.f32_round)
                         })
                         .collect()
                 })
-        })
+                .and_then(|embeddings| check_embedding_count(embeddings, expected))
+        }))?;
+
+        // Fill in and cache the new embeddings in input order
+        let mut fresh = fresh.into_iter();
+        for (key, embedding) in keys.into_iter().zip(embeddings.iter_mut()) {
+            if embedding.is_none() {
+                let computed = fresh.next().expect("one embedding per uncached image");
+                self.cache_insert(key, &computed);
+                *embedding = Some(computed);
+            }
+        }
+        Ok(embeddings.into_iter().flatten().collect())
     }
 
-    /// Process multiple images concurrently for optimal performance
+    /// Process images concurrently, returning one result per image in input
+    /// order; a failed image does not affect the others, and cached images
+    /// are not described again
+    pub fn embed_images_each_sync(&self, images: Vec<Vec<u8>>) -> Vec<Result<Vec<f32>>> {
+        let client = self.client.clone();
+        let vision_model = self.vision_model.clone();
+        let embedding_model = self.embedding_model.clone();
+        let vision_config = self.vision_config.clone();
+        let descriptions = self.descriptions.clone();
+        let concurrency = self.performance_config.max_concurrent_requests;
+
+        let results: Vec<Result<Vec<f32>>> = RUNTIME.block_on(async move {
+            let (client, vision_model, embedding_model, vision_config, descriptions) =
+                (&client, &vision_model, &embedding_model, &vision_config, &descriptions);
+            stream::iter(images)
+                .map(|image_data| async move {
+                    self.cached_async(&image_data, None, async {
+                        let mime_type = vision_config.image_mime_type(&image_data)?;
+                        let (image_base64, mime_type) = vision_config.encode_image(&image_data, mime_type)?;
+                        let description =
+                            describe_image(client, vision_model, vision_config, descriptions, &image_base64, mime_type)
+                                .await?;
+                        telemetry::traced("embed", embedding_model, client.embed(embedding_model, description, None))
+                            .await
+                            .map_err(|e| Error::new_message(format!("Embedding failed: {}", e)))
+                            .and_then(|response| {
+                                response
+                                    .first_embedding()
+                                    .ok_or_else(|| Error::new_message("No embedding in response"))
+                                    .map(|embedding| vector::downcast(embedding.vector(), vision_config.f32_round))
+                            })
+                    })
+                    .await
+                })
+                .buffered(concurrency)
+                .collect()
+                .await
+        });
+
+        let outcome = match results.iter().find_map(|result| result.as_ref().err()) {
+            Some(e) => Err(Error::new_message(e.to_string())),
+            None => Ok(()),
+        };
+        let _ = self.last_error.track(outcome);
+        results
+    }
+
+    /// Process multiple images concurrently for optimal performance, reusing
+    /// cached embeddings
     pub fn embed_images_concurrent_sync(&self, images: Vec<&[u8]>) -> Result<(Vec<Vec<f32>>, ProcessingStats)> {
         let client = self.client.clone();
         let vision_model = self.vision_model.clone();
         let embedding_model = self.embedding_model.clone();
         let config = self.performance_config.clone();
+        let vision_config = self.vision_config.clone();
+        let descriptions = self.descriptions.clone();
 
-        RUNTIME.block_on(async move {
+        self.last_error.track(RUNTIME.block_on(async move {
             let start_time = Instant::now();
             let semaphore = Arc::new(Semaphore::new(config.max_concurrent_requests));
 
@@ -213,22 +717,34 @@ impl MultimodalClient {
                 let client = client.clone();
                 let vision_model = vision_model.clone();
                 let embedding_model = embedding_model.clone();
+                let vision_config = vision_config.clone();
+                let descriptions = descriptions.clone();
                 let semaphore = semaphore.clone();
-                use base64::Engine as _;
-        let image_base64 = base64::engine::general_purpose::STANDARD.encode(image_data);
+                let encoded = vision_config
+                    .image_mime_type(image_data)
+                    .and_then(|mime_type| vision_config.encode_image(image_data, mime_type));
 
-                async move {
+                self.cached_async(image_data, None, async move {
+                    let (image_base64, mime_type) = encoded?;
                     let _permit = semaphore.acquire().await.unwrap();
 
                     // Step 1: Describe image
-                    let description = match describe_image(&client, &vision_model, &image_base64).await {
+                    let description = match describe_image(
+                        &client,
+                        &vision_model,
+                        &vision_config,
+                        &descriptions,
+                        &image_base64,
+                        mime_type,
+                    )
+                    .await
+                    {
                         Ok(desc) => desc,
                         Err(e) => return Err(e),
                     };
 
                     // Step 2: Generate embedding
-                    client
-                        .embed(&embedding_model, description, None)
+                    telemetry::traced("embed", &embedding_model, client.embed(&embedding_model, description, None))
                         .await
                         .map_err(|e| Error::new_message(format!("Embedding failed: {}", e)))
                         .and_then(|response| {
@@ -236,10 +752,10 @@ impl MultimodalClient {
                                 .first_embedding()
                                 .ok_or_else(|| Error::new_message("No embedding in response"))
                                 .map(|embedding| {
-                                    embedding.vector().iter().map(|&v| v as f32).collect()
+                                    vector::downcast(embedding.vector(), vision_config.f32_round)
                                 })
                         })
-                }
+                })
             });
 
             // Collect results
@@ -280,30 +796,83 @@ impl MultimodalClient {
             };
 
             Ok((embeddings, stats))
-        })
+        }))
+    }
+
+    /// Describe several images in one vision request and embed the combined
+    /// description as a single vector
+    pub fn embed_images_combined_sync(&self, images: Vec<&[u8]>) -> Result<Vec<f32>> {
+        if images.is_empty() {
+            return Err(Error::new_message("Input array cannot be empty"));
+        }
+        if images.len() > MAX_COMBINED_IMAGES {
+            return Err(Error::new_message(format!(
+                "At most {} images can be combined, got {}",
+                MAX_COMBINED_IMAGES,
+                images.len()
+            )));
+        }
+        let client = self.client.clone();
+        let vision_model = self.vision_model.clone();
+        let embedding_model = self.embedding_model.clone();
+        let vision_config = self.vision_config.clone();
+        let images_base64 = images
+            .iter()
+            .map(|image_data| {
+                let mime_type = vision_config.image_mime_type(image_data)?;
+                vision_config.encode_image(image_data, mime_type)
+            })
+            .collect::<Result<Vec<(String, &'static str)>>>()?;
+
+        self.last_error.track(RUNTIME.block_on(async move {
+            // Step 1: Describe all images in one request
+            let description =
+                describe_images_combined(&client, &vision_model, &vision_config, &images_base64).await?;
+
+            // Step 2: Embed the combined description
+            telemetry::traced("embed", &embedding_model, client.embed(&embedding_model, description, None))
+                .await
+                .map_err(|e| Error::new_message(format!("Embedding failed: {}", e)))
+                .and_then(|response| {
+                    response
+                        .first_embedding()
+                        .ok_or_else(|| Error::new_message("No embedding in response"))
+                        .map(|embedding| {
+                            vector::downcast(embedding.vector(), vision_config.f32_round)
+                        })
+                })
+        }))
     }
 
     /// Process image with custom prompt
     pub fn embed_image_with_prompt_sync(&self, image_data: &[u8], prompt: &str) -> Result<Vec<f32>> {
+        let mime_type = self.vision_config.image_mime_type(image_data)?;
+        self.cached(image_data, Some(prompt), || self.embed_image_with_prompt_uncached(image_data, mime_type, prompt))
+    }
+
+    fn embed_image_with_prompt_uncached(&self, image_data: &[u8], mime_type: &str, prompt: &str) -> Result<Vec<f32>> {
         let client = self.client.clone();
         let vision_model = self.vision_model.clone();
         let embedding_model = self.embedding_model.clone();
-        use base64::Engine as _;
-        let image_base64 = base64::engine::general_purpose::STANDARD.encode(image_data);
+        let vision_config = self.vision_config.clone();
+        let descriptions = self.descriptions.clone();
+        let (image_base64, mime_type) = vision_config.encode_image(image_data, mime_type)?;
         let prompt = prompt.to_string();
 
-        RUNTIME.block_on(async move {
+        self.last_error.track(RUNTIME.block_on(async move {
             // Step 1: Describe the image with custom prompt
             let description = describe_image_with_prompt(
                 &client,
                 &vision_model,
+                &vision_config,
+                &descriptions,
                 &image_base64,
+                mime_type,
                 &prompt
             ).await?;
 
             // Step 2: Embed the description
-            client
-                .embed(&embedding_model, description, None)
+            telemetry::traced("embed", &embedding_model, client.embed(&embedding_model, description, None))
                 .await
                 .map_err(|e| Error::new_message(format!("Embedding failed: {}", e)))
                 .and_then(|response| {
@@ -311,10 +880,136 @@ impl MultimodalClient {
                         .first_embedding()
                         .ok_or_else(|| Error::new_message("No embedding in response"))
                         .map(|embedding| {
-                            embedding.vector().iter().map(|&v| v as f32).collect()
+                            vector::downcast(embedding.vector(), vision_config.f32_round)
                         })
                 })
-        })
+        }))
+    }
+}
+
+const VISION_SYSTEM_PROMPT: &str = "You are a helpful vision AI. Describe images accurately and concisely \
+     for embedding purposes. Focus on key visual elements, objects, scene context, \
+     colors, and composition.";
+const VISION_PROMPT: &str = "Describe this image in detail for search and embedding purposes:";
+const COMBINED_VISION_PROMPT: &str = "Describe these images together as a single item for search and \
+     embedding purposes, covering what each shows and how they relate:";
+/// Most images accepted by one combined vision request
+pub const MAX_COMBINED_IMAGES: usize = 8;
+/// MIME type assumed for image blobs when the caller does not give one
+const DEFAULT_IMAGE_MIME_TYPE: &str = "image/jpeg";
+
+/// Re-encode an image whose longest side exceeds max_dimension at that size,
+/// keeping its aspect ratio. Images with transparency become PNG, others
+/// JPEG. Returns None when the image already fits or its size cannot be read,
+/// in which case the original bytes are sent.
+pub fn downscale_image(image_data: &[u8], max_dimension: u32) -> Result<Option<(Vec<u8>, &'static str)>> {
+    let reader = || image::ImageReader::new(std::io::Cursor::new(image_data)).with_guessed_format();
+    let fits = match reader().ok().and_then(|reader| reader.into_dimensions().ok()) {
+        Some((width, height)) => width.max(height) <= max_dimension,
+        None => true,
+    };
+    if fits {
+        return Ok(None);
+    }
+
+    let image = reader()
+        .map_err(|e| Error::new_message(format!("Failed to read image: {}", e)))?
+        .decode()
+        .map_err(|e| Error::new_message(format!("Failed to decode image for downscaling: {}", e)))?;
+    let resized = image.resize(max_dimension, max_dimension, image::imageops::FilterType::Triangle);
+
+    let mut encoded = std::io::Cursor::new(Vec::new());
+    let mime_type = if resized.color().has_alpha() {
+        resized
+            .write_to(&mut encoded, image::ImageFormat::Png)
+            .map(|_| "image/png")
+    } else {
+        image::DynamicImage::ImageRgb8(resized.to_rgb8())
+            .write_to(&mut encoded, image::ImageFormat::Jpeg)
+            .map(|_| "image/jpeg")
+    }
+    .map_err(|e| Error::new_message(format!("Failed to re-encode downscaled image: {}", e)))?;
+    Ok(Some((encoded.into_inner(), mime_type)))
+}
+
+/// Image MIME type identified from a blob's leading magic bytes
+pub fn sniff_image_mime_type(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"II*\0", "image/tiff"),
+        (b"MM\0*", "image/tiff"),
+    ];
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Reject MIME types that are not of the form "image/<subtype>"
+pub fn validate_image_mime_type(mime_type: &str) -> Result<()> {
+    match mime_type.split_once('/') {
+        Some(("image", subtype)) if !subtype.is_empty() && !subtype.contains('/') => Ok(()),
+        _ => Err(Error::new_message(format!(
+            "Invalid image MIME type '{}', expected image/*",
+            mime_type
+        ))),
+    }
+}
+
+/// Split a "data:image/png;base64,..." URI into its MIME type and decoded bytes
+pub fn parse_data_uri(uri: &str) -> Result<(String, Vec<u8>)> {
+    let invalid = || Error::new_message("Invalid data URI, expected 'data:image/<type>;base64,<data>'");
+    let (header, payload) = uri
+        .trim()
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+        .ok_or_else(invalid)?;
+    let mime_type = header.strip_suffix(";base64").ok_or_else(invalid)?;
+    // Parameters such as ";charset=..." may precede the base64 marker
+    let mime_type = mime_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    validate_image_mime_type(&mime_type)?;
+
+    use base64::Engine as _;
+    let image_data = base64::engine::general_purpose::STANDARD
+        .decode(payload.trim())
+        .map_err(|e| Error::new_message(format!("Invalid base64 in data URI: {}", e)))?;
+    Ok((mime_type, image_data))
+}
+
+/// Build the chat request sent to the vision model for one image
+fn build_vision_request(
+    system: Option<String>,
+    prompt: &str,
+    image_base64: &str,
+    mime_type: &str,
+) -> ChatRequest {
+    build_multi_image_request(system, prompt, &[(image_base64, mime_type)])
+}
+
+/// Build a chat request carrying every (base64, MIME type) image as its own content part
+fn build_multi_image_request(
+    system: Option<String>,
+    prompt: &str,
+    images: &[(&str, &str)],
+) -> ChatRequest {
+    let mut parts = vec![ContentPart::from_text(prompt)];
+    parts.extend(
+        images
+            .iter()
+            .map(|(image_base64, mime_type)| ContentPart::from_binary_base64(*mime_type, *image_base64, None)),
+    );
+    let chat_req = ChatRequest::new(vec![ChatMessage::user(parts)]);
+
+    match system {
+        Some(system) => chat_req.with_system(system),
+        None => chat_req,
     }
 }
 
@@ -322,22 +1017,48 @@ impl MultimodalClient {
 async fn describe_image(
     client: &GenAiClient,
     vision_model: &str,
+    vision_config: &VisionConfig,
+    descriptions: &Mutex<DescriptionCache>,
     image_base64: &str,
+    mime_type: &str,
+) -> Result<String> {
+    let system = vision_config.system_prompt(Some(VISION_SYSTEM_PROMPT));
+    let key = || description_cache_key(image_base64, vision_model, system.as_deref(), VISION_PROMPT);
+    cached_description(descriptions, key, async {
+        let chat_req = build_vision_request(system.clone(), VISION_PROMPT, image_base64, mime_type);
+
+        let chat_options = vision_config.chat_options();
+        let chat_response = telemetry::traced("vision", vision_model, client.exec_chat(vision_model, chat_req, chat_options.as_ref()))
+            .await
+            .map_err(|e| Error::new_message(format!("Vision analysis failed: {}", e)))?;
+
+        chat_response
+            .first_text()
+            .ok_or_else(|| Error::new_message("No description generated"))
+            .map(|s| s.to_string())
+    })
+    .await
+}
+
+/// Describe several images with one vision request, producing a single description
+async fn describe_images_combined(
+    client: &GenAiClient,
+    vision_model: &str,
+    vision_config: &VisionConfig,
+    images_base64: &[(String, &str)],
 ) -> Result<String> {
-    let chat_req = ChatRequest::new(vec![
-        ChatMessage::system(
-            "You are a helpful vision AI. Describe images accurately and concisely \
-             for embedding purposes. Focus on key visual elements, objects, scene context, \
-             colors, and composition."
-        ),
-        ChatMessage::user(vec![
-            ContentPart::from_text("Describe this image in detail for search and embedding purposes:"),
-            ContentPart::from_binary_base64("image/jpeg", image_base64, None),
-        ])
-    ]);
-
-    let chat_response = client
-        .exec_chat(vision_model, chat_req, None)
+    let images: Vec<(&str, &str)> = images_base64
+        .iter()
+        .map(|(image_base64, mime_type)| (image_base64.as_str(), *mime_type))
+        .collect();
+    let chat_req = build_multi_image_request(
+        vision_config.system_prompt(Some(VISION_SYSTEM_PROMPT)),
+        COMBINED_VISION_PROMPT,
+        &images,
+    );
+
+    let chat_options = vision_config.chat_options();
+    let chat_response = telemetry::traced("vision", vision_model, client.exec_chat(vision_model, chat_req, chat_options.as_ref()))
         .await
         .map_err(|e| Error::new_message(format!("Vision analysis failed: {}", e)))?;
 
@@ -351,25 +1072,28 @@ async fn describe_image(
 async fn describe_image_with_prompt(
     client: &GenAiClient,
     vision_model: &str,
+    vision_config: &VisionConfig,
+    descriptions: &Mutex<DescriptionCache>,
     image_base64: &str,
+    mime_type: &str,
     prompt: &str,
 ) -> Result<String> {
-    let chat_req = ChatRequest::new(vec![
-        ChatMessage::user(vec![
-            ContentPart::from_text(prompt),
-            ContentPart::from_binary_base64("image/jpeg", image_base64, None),
-        ])
-    ]);
-
-    let chat_response = client
-        .exec_chat(vision_model, chat_req, None)
-        .await
-        .map_err(|e| Error::new_message(format!("Vision analysis failed: {}", e)))?;
+    let system = vision_config.system_prompt(None);
+    let key = || description_cache_key(image_base64, vision_model, system.as_deref(), prompt);
+    cached_description(descriptions, key, async {
+        let chat_req = build_vision_request(system.clone(), prompt, image_base64, mime_type);
 
-    chat_response
-        .first_text()
-        .ok_or_else(|| Error::new_message("No description generated"))
-        .map(|s| s.to_string())
+        let chat_options = vision_config.chat_options();
+        let chat_response = telemetry::traced("vision", vision_model, client.exec_chat(vision_model, chat_req, chat_options.as_ref()))
+            .await
+            .map_err(|e| Error::new_message(format!("Vision analysis failed: {}", e)))?;
+
+        chat_response
+            .first_text()
+            .ok_or_else(|| Error::new_message("No description generated"))
+            .map(|s| s.to_string())
+    })
+    .await
 }
 
 /// Configuration for multimodal client
@@ -404,4 +1128,283 @@ impl MultimodalConfig {
             embedding_model: embedding.to_string(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "Objects: ...; Scene: ...; Colors: ...";
+
+    fn template_config() -> VisionConfig {
+        let mut options = HashMap::new();
+        options.insert("description_template".to_string(), TEMPLATE.to_string());
+        VisionConfig::from_options(&options).unwrap()
+    }
+
+    #[test]
+    fn test_description_template_in_vision_requests() {
+        let config = template_config();
+
+        let default_req = build_vision_request(
+            config.system_prompt(Some(VISION_SYSTEM_PROMPT)),
+            VISION_PROMPT,
+            "aGVsbG8=",
+            DEFAULT_IMAGE_MIME_TYPE,
+        );
+        let system = default_req.system.expect("system prompt");
+        assert!(system.starts_with(VISION_SYSTEM_PROMPT));
+        assert!(system.contains(TEMPLATE));
+
+        let prompted_req = build_vision_request(
+            config.system_prompt(None),
+            "What is this?",
+            "aGVsbG8=",
+            DEFAULT_IMAGE_MIME_TYPE,
+        );
+        assert!(prompted_req.system.expect("system prompt").contains(TEMPLATE));
+    }
+
+    #[test]
+    fn test_no_template_keeps_default_prompts() {
+        let config = VisionConfig::default();
+        assert_eq!(
+            config.system_prompt(Some(VISION_SYSTEM_PROMPT)).as_deref(),
+            Some(VISION_SYSTEM_PROMPT)
+        );
+        assert_eq!(config.system_prompt(None), None);
+    }
+
+    #[test]
+    fn test_empty_description_template_rejected() {
+        let mut options = HashMap::new();
+        options.insert("description_template".to_string(), "  ".to_string());
+        assert!(VisionConfig::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_mime_type_reaches_vision_request() {
+        let req = build_vision_request(None, VISION_PROMPT, "aGVsbG8=", "image/png");
+        let debug = format!("{:?}", req);
+        assert!(debug.contains("image/png"));
+        assert!(!debug.contains(DEFAULT_IMAGE_MIME_TYPE));
+    }
+
+    #[test]
+    fn test_validate_image_mime_type() {
+        for valid in ["image/png", "image/webp", "image/svg+xml"] {
+            assert!(validate_image_mime_type(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["", "png", "image/", "text/plain", "image/png/x"] {
+            assert!(validate_image_mime_type(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_multi_image_request_carries_every_image() {
+        let req = build_multi_image_request(
+            None,
+            COMBINED_VISION_PROMPT,
+            &[("Zmlyc3Q=", "image/png"), ("c2Vjb25k", "image/gif")],
+        );
+        assert_eq!(req.messages.len(), 1);
+        let debug = format!("{:?}", req);
+        assert!(debug.contains("Zmlyc3Q="));
+        assert!(debug.contains("c2Vjb25k"));
+        assert!(debug.contains("image/png") && debug.contains("image/gif"));
+    }
+
+    #[test]
+    fn test_parse_data_uri() {
+        let (mime_type, data) = parse_data_uri("data:image/png;base64,iVBORw0KGgo=").unwrap();
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(data, b"\x89PNG\r\n\x1a\n");
+        assert_eq!(sniff_image_mime_type(&data), Some("image/png"));
+
+        let (mime_type, _) = parse_data_uri("data:IMAGE/WEBP;name=x.webp;base64,AA==").unwrap();
+        assert_eq!(mime_type, "image/webp");
+
+        assert!(parse_data_uri("iVBORw0KGgo=").is_err());
+        assert!(parse_data_uri("data:image/png,iVBORw0KGgo=").is_err());
+        assert!(parse_data_uri("data:text/plain;base64,aGk=").is_err());
+        assert!(parse_data_uri("data:image/png;base64,not base64!").is_err());
+    }
+
+    #[test]
+    fn test_downscale_image() {
+        use base64::Engine as _;
+        let png = |image: image::DynamicImage| {
+            let mut data = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut data, image::ImageFormat::Png).unwrap();
+            data.into_inner()
+        };
+        let large = png(image::DynamicImage::ImageRgb8(image::RgbImage::new(2000, 1000)));
+
+        let mut options = HashMap::new();
+        options.insert("max_dimension".to_string(), "512".to_string());
+        let config = VisionConfig::from_options(&options).unwrap();
+        assert_eq!(config.to_options(), options);
+
+        let (image_base64, mime_type) = config.encode_image(&large, "image/png").unwrap();
+        assert_eq!(mime_type, "image/jpeg");
+        let sent = base64::engine::general_purpose::STANDARD.decode(image_base64).unwrap();
+        let sent = image::load_from_memory(&sent).unwrap();
+        assert_eq!((sent.width(), sent.height()), (512, 256));
+
+        // Transparency survives as PNG
+        let transparent = png(image::DynamicImage::ImageRgba8(image::RgbaImage::new(600, 1200)));
+        let (data, mime_type) = downscale_image(&transparent, 512).unwrap().unwrap();
+        assert_eq!(mime_type, "image/png");
+        let sent = image::load_from_memory(&data).unwrap();
+        assert_eq!((sent.width(), sent.height()), (256, 512));
+
+        // Images within the limit, and blobs that cannot be read, go out unchanged
+        let small = png(image::DynamicImage::ImageRgb8(image::RgbImage::new(100, 50)));
+        assert!(downscale_image(&small, 512).unwrap().is_none());
+        assert!(downscale_image(b"just some text", 512).unwrap().is_none());
+        let (image_base64, mime_type) = VisionConfig::default().encode_image(&large, "image/png").unwrap();
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(image_base64, base64::engine::general_purpose::STANDARD.encode(&large));
+
+        options.insert("max_dimension".to_string(), "0".to_string());
+        assert!(VisionConfig::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_image_cache() {
+        let key = image_cache_key(b"image", "llava", "nomic", None);
+        assert_eq!(key, image_cache_key(b"image", "llava", "nomic", None));
+        assert_ne!(key, image_cache_key(b"image", "llava", "nomic", Some("colors")));
+        assert_ne!(key, image_cache_key(b"other", "llava", "nomic", None));
+        assert_ne!(key, image_cache_key(b"image", "llava", "mxbai", None));
+
+        let mut cache = ImageCache::new(2, None);
+        let keys: Vec<[u8; 32]> = (0u8..3).map(|i| image_cache_key(&[i], "v", "e", None)).collect();
+        cache.insert(keys[0], vec![0.0]);
+        cache.insert(keys[1], vec![1.0]);
+        assert_eq!(cache.get(&keys[0]), Some(vec![0.0]));
+        cache.insert(keys[2], vec![2.0]);
+        assert_eq!(cache.get(&keys[0]), None);
+        assert_eq!(cache.get(&keys[2]), Some(vec![2.0]));
+
+        let mut disabled = ImageCache::new(0, None);
+        disabled.insert(keys[0], vec![0.0]);
+        assert_eq!(disabled.get(&keys[0]), None);
+    }
+
+    #[test]
+    fn test_description_cache() {
+        let key = description_cache_key("aW1n", "llava", None, VISION_PROMPT);
+        assert_eq!(key, description_cache_key("aW1n", "llava", None, VISION_PROMPT));
+        assert_ne!(key, description_cache_key("aW1n", "llava", None, "Colors?"));
+        assert_ne!(key, description_cache_key("aW1n", "llava", Some("Be brief"), VISION_PROMPT));
+        assert_ne!(key, description_cache_key("b3RoZXI=", "llava", None, VISION_PROMPT));
+        assert_ne!(key, description_cache_key("aW1n", "bakllava", None, VISION_PROMPT));
+
+        // Two describes of one image send one vision request and reuse its text
+        let descriptions = Mutex::new(DescriptionCache::new(2));
+        let requests = &std::sync::atomic::AtomicUsize::new(0);
+        let describe = move || async move {
+            let n = requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok::<_, Error>(format!("description {}", n))
+        };
+        let first = RUNTIME.block_on(cached_description(&descriptions, || key, describe())).unwrap();
+        let second = RUNTIME.block_on(cached_description(&descriptions, || key, describe())).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(descriptions.lock().unwrap().stats(), CacheStats { hits: 1, misses: 1, entries: 1 });
+
+        // Off by default: every describe reaches the vision model, uncounted
+        let disabled = Mutex::new(DescriptionCache::default());
+        let third = RUNTIME.block_on(cached_description(&disabled, || key, describe())).unwrap();
+        assert_ne!(third, first);
+        assert_eq!(disabled.lock().unwrap().stats(), CacheStats::default());
+
+        let mut cache = DescriptionCache::new(1);
+        cache.insert([0; 32], "a".to_string());
+        cache.insert([1; 32], "b".to_string());
+        assert_eq!(cache.get(&[0; 32]), None);
+        assert_eq!(cache.get(&[1; 32]).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_image_cache_byte_limit() {
+        // Room for 40 bytes: ten f32 elements across entries of any width
+        let mut cache = ImageCache::new(0, Some(40));
+        let keys: Vec<[u8; 32]> = (0u8..6).map(|i| image_cache_key(&[i], "v", "e", None)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            cache.insert(*key, vec![i as f32; 2 + i]);
+            assert!(cache.bytes <= 40, "{} bytes after {} inserts", cache.bytes, i + 1);
+            let counted: usize = cache.entries.values().map(|v| v.len() * 4).sum();
+            assert_eq!(cache.bytes, counted);
+        }
+        // The newest entries survive; the oldest were evicted
+        assert_eq!(cache.get(&keys[5]), Some(vec![5.0; 7]));
+        assert_eq!(cache.get(&keys[0]), None);
+
+        // A vector larger than the whole budget is never cached
+        cache.insert(image_cache_key(b"huge", "v", "e", None), vec![0.0; 11]);
+        assert_eq!(cache.get(&image_cache_key(b"huge", "v", "e", None)), None);
+        assert_eq!(cache.get(&keys[5]), Some(vec![5.0; 7]));
+
+        let mut options = HashMap::new();
+        options.insert("cache_max_bytes".to_string(), "4096".to_string());
+        assert_eq!(VisionConfig::from_options(&options).unwrap().to_options(), options);
+        options.insert("cache_max_bytes".to_string(), "0".to_string());
+        assert!(VisionConfig::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_vision_sampling_options() {
+        let mut options = HashMap::new();
+        assert!(VisionConfig::from_options(&options).unwrap().chat_options().is_none());
+
+        options.insert("vision_temperature".to_string(), "0".to_string());
+        options.insert("vision_seed".to_string(), "42".to_string());
+        let config = VisionConfig::from_options(&options).unwrap();
+        assert_eq!((config.temperature, config.seed), (Some(0.0), Some(42)));
+        let chat_options = config.chat_options().unwrap();
+        assert_eq!((chat_options.temperature, chat_options.seed), (Some(0.0), Some(42)));
+        assert_eq!(VisionConfig::from_options(&config.to_options()).unwrap().to_options(), config.to_options());
+
+        options.insert("vision_temperature".to_string(), "-1".to_string());
+        assert!(VisionConfig::from_options(&options).is_err());
+        options.insert("vision_temperature".to_string(), "0.2".to_string());
+        options.insert("vision_seed".to_string(), "-1".to_string());
+        assert!(VisionConfig::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_image_sniffing() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(sniff_image_mime_type(png), Some("image/png"));
+        assert_eq!(sniff_image_mime_type(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("image/jpeg"));
+        assert_eq!(sniff_image_mime_type(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_image_mime_type(b"RIFF\x24\0\0\0WAVEfmt "), None);
+
+        let config = VisionConfig::default();
+        assert_eq!(config.image_mime_type(png).unwrap(), "image/png");
+        let err = config.image_mime_type(b"just some text").unwrap_err();
+        assert!(err.to_string().contains("not a recognized image"));
+
+        let mut options = HashMap::new();
+        options.insert("allow_unknown_image".to_string(), "true".to_string());
+        let permissive = VisionConfig::from_options(&options).unwrap();
+        assert_eq!(permissive.image_mime_type(b"just some text").unwrap(), DEFAULT_IMAGE_MIME_TYPE);
+        assert_eq!(permissive.to_options(), options);
+    }
+
+    #[test]
+    fn test_combined_images_limits() {
+        let client = MultimodalClient::new(
+            "ollama::llava:7b".to_string(),
+            "ollama::nomic-embed-text".to_string(),
+        )
+        .unwrap();
+        assert!(client.embed_images_combined_sync(vec![]).is_err());
+        let image: &[u8] = b"image";
+        let too_many = vec![image; MAX_COMBINED_IMAGES + 1];
+        let err = client.embed_images_combined_sync(too_many).unwrap_err();
+        assert!(err.to_string().contains("At most"));
+    }
+}
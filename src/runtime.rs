@@ -0,0 +1,173 @@
+/// The tokio runtime shared by every client in the extension
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Env var setting the number of runtime worker threads
+const RUNTIME_THREADS_ENV: &str = "REMBED_RUNTIME_THREADS";
+/// Worker threads used when REMBED_RUNTIME_THREADS is unset; requests are
+/// I/O-bound, so a small pool is enough for an embedded extension
+const DEFAULT_RUNTIME_THREADS: usize = 2;
+/// How long shutdown waits for in-flight tasks before abandoning them
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Env var capping provider requests in flight across all clients and statements
+const MAX_GLOBAL_CONCURRENCY_ENV: &str = "REMBED_MAX_GLOBAL_CONCURRENCY";
+
+/// Process-wide request limit, read on first use; None leaves requests unbounded
+static REQUEST_LIMIT: Lazy<Option<Semaphore>> = Lazy::new(|| {
+    global_concurrency(std::env::var(MAX_GLOBAL_CONCURRENCY_ENV).ok().as_deref()).map(Semaphore::new)
+});
+
+/// Global tokio runtime for async operations, built on first use
+pub static RUNTIME: SharedRuntime = SharedRuntime::new();
+
+/// A lazily built runtime that can be shut down and rebuilt on next use
+pub struct SharedRuntime {
+    runtime: Mutex<Option<Arc<Runtime>>>,
+}
+
+impl SharedRuntime {
+    const fn new() -> Self {
+        Self { runtime: Mutex::new(None) }
+    }
+
+    /// The current runtime, building one if none is running
+    fn get(&self) -> Arc<Runtime> {
+        let mut runtime = self.runtime.lock().unwrap_or_else(|e| e.into_inner());
+        runtime
+            .get_or_insert_with(|| {
+                let threads = runtime_threads(std::env::var(RUNTIME_THREADS_ENV).ok().as_deref());
+                Arc::new(build_runtime(threads).expect("Failed to create tokio runtime"))
+            })
+            .clone()
+    }
+
+    /// Build the runtime now rather than on the first request
+    pub fn warm(&self) {
+        self.get();
+    }
+
+    /// Run a future to completion on the shared runtime
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        // Hold the runtime, not the lock, so calls from several threads overlap
+        self.get().block_on(future)
+    }
+
+    /// Stop the worker threads. Calls still running keep the old runtime
+    /// alive until they finish; the next call builds a fresh one.
+    /// Returns whether a runtime was running.
+    pub fn shutdown(&self) -> bool {
+        let runtime = self.runtime.lock().unwrap_or_else(|e| e.into_inner()).take();
+        match runtime.map(Arc::try_unwrap) {
+            Some(Ok(runtime)) => {
+                runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+                true
+            }
+            Some(Err(_in_use)) => true,
+            None => false,
+        }
+    }
+}
+
+/// Parse the worker thread count, falling back to the default for unset or invalid values
+fn runtime_threads(value: Option<&str>) -> usize {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&threads| threads > 0)
+        .unwrap_or(DEFAULT_RUNTIME_THREADS)
+}
+
+/// Parse the global request cap; unset, zero or invalid values mean unlimited
+fn global_concurrency(value: Option<&str>) -> Option<usize> {
+    value
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&limit| limit > 0)
+}
+
+/// Wait for a request slot under REMBED_MAX_GLOBAL_CONCURRENCY; the slot is
+/// held until the returned permit is dropped
+pub async fn request_permit() -> Option<SemaphorePermit<'static>> {
+    acquire((*REQUEST_LIMIT).as_ref()).await
+}
+
+async fn acquire(limit: Option<&Semaphore>) -> Option<SemaphorePermit<'_>> {
+    match limit {
+        Some(semaphore) => semaphore.acquire().await.ok(),
+        None => None,
+    }
+}
+
+fn build_runtime(threads: usize) -> std::io::Result<Runtime> {
+    Builder::new_multi_thread()
+        .worker_threads(threads)
+        .thread_name("rembed-worker")
+        .enable_all()
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_threads() {
+        assert_eq!(runtime_threads(None), DEFAULT_RUNTIME_THREADS);
+        assert_eq!(runtime_threads(Some("4")), 4);
+        assert_eq!(runtime_threads(Some("0")), DEFAULT_RUNTIME_THREADS);
+        assert_eq!(runtime_threads(Some("many")), DEFAULT_RUNTIME_THREADS);
+    }
+
+    #[test]
+    fn test_build_runtime_honors_thread_count() {
+        let runtime = build_runtime(3).unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 3);
+    }
+
+    #[test]
+    fn test_global_request_limit() {
+        assert_eq!(global_concurrency(None), None);
+        assert_eq!(global_concurrency(Some("2")), Some(2));
+        assert_eq!(global_concurrency(Some("0")), None);
+        assert_eq!(global_concurrency(Some("lots")), None);
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let peak_in_flight = |limit: Option<&Semaphore>| {
+            let (in_flight, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            let call = || async {
+                let _permit = acquire(limit).await;
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            };
+            build_runtime(2).unwrap().block_on(async { tokio::join!(call(), call()) });
+            peak.load(Ordering::SeqCst)
+        };
+
+        assert_eq!(peak_in_flight(Some(&Semaphore::new(1))), 1);
+        assert_eq!(peak_in_flight(None), 2);
+    }
+
+    #[test]
+    fn test_shutdown_and_reinitialize() {
+        let runtime = SharedRuntime::new();
+        assert!(!runtime.shutdown());
+        runtime.warm();
+        assert!(runtime.shutdown());
+
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+        assert!(Arc::ptr_eq(&runtime.get(), &runtime.get()));
+        let first = Arc::downgrade(&runtime.get());
+
+        assert!(runtime.shutdown());
+        assert!(first.upgrade().is_none());
+
+        // The next call builds a fresh runtime
+        assert_eq!(runtime.block_on(async { 2 + 2 }), 4);
+        assert!(runtime.shutdown());
+    }
+}
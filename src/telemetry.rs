@@ -0,0 +1,142 @@
+/// Optional request instrumentation, compiled in with the `tracing` cargo feature.
+/// Spans carry operation, provider, model, latency and outcome only; API keys and
+/// request bodies are never recorded. Every provider request passes through
+/// `traced`, which is also where it waits for a global request permit.
+use std::fmt::Display;
+use std::future::Future;
+
+/// Await a provider request inside a `rembed_request` span describing its outcome
+pub async fn traced<T, E: Display>(
+    operation: &'static str,
+    model: &str,
+    request: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let _permit = crate::runtime::request_permit().await;
+
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+
+        let span = tracing::info_span!(
+            "rembed_request",
+            operation,
+            provider = crate::genai_client::provider_from_model(model),
+            model,
+            latency_ms = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+        let started = std::time::Instant::now();
+        let result = request.instrument(span.clone()).await;
+
+        span.record("latency_ms", started.elapsed().as_millis() as u64);
+        match &result {
+            Ok(_) => {
+                span.record("outcome", "ok");
+            }
+            Err(e) => {
+                span.record("outcome", "error");
+                tracing::warn!(parent: &span, error = %e, "embedding provider request failed");
+            }
+        }
+        result
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (operation, model);
+        request.await
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Collects every span field as (name, debug-formatted value)
+    #[derive(Clone, Default)]
+    struct CapturingSubscriber {
+        spans: Arc<Mutex<Vec<String>>>,
+        fields: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl Visit for CapturingSubscriber {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.fields
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl CapturingSubscriber {
+        fn field(&self, name: &str) -> Option<String> {
+            self.fields
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.clone())
+        }
+    }
+
+    impl Subscriber for CapturingSubscriber {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span.metadata().name().to_string());
+            span.record(&mut self.clone());
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+        fn event(&self, _event: &Event<'_>) {}
+        fn enter(&self, _span: &Id) {}
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn test_traced_emits_span_fields() {
+        let subscriber = CapturingSubscriber::default();
+        let result = tracing::subscriber::with_default(subscriber.clone(), || {
+            futures::executor::block_on(traced(
+                "embed",
+                "ollama::nomic-embed-text",
+                async { Ok::<_, String>(vec![0.5f32]) },
+            ))
+        });
+
+        assert_eq!(result.unwrap(), vec![0.5f32]);
+        assert_eq!(subscriber.spans.lock().unwrap().as_slice(), ["rembed_request"]);
+        assert_eq!(subscriber.field("operation").as_deref(), Some("\"embed\""));
+        assert_eq!(subscriber.field("provider").as_deref(), Some("\"ollama\""));
+        assert_eq!(subscriber.field("model").as_deref(), Some("\"ollama::nomic-embed-text\""));
+        assert_eq!(subscriber.field("outcome").as_deref(), Some("\"ok\""));
+        assert!(subscriber.field("latency_ms").is_some());
+    }
+
+    #[test]
+    fn test_traced_records_errors() {
+        let subscriber = CapturingSubscriber::default();
+        let result = tracing::subscriber::with_default(subscriber.clone(), || {
+            futures::executor::block_on(traced(
+                "vision",
+                "ollama::llava:7b",
+                async { Err::<(), _>("connection refused".to_string()) },
+            ))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(subscriber.field("outcome").as_deref(), Some("\"error\""));
+    }
+}
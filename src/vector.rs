@@ -0,0 +1,814 @@
+/// Helpers for float32 embedding vectors and their blob/base64 encodings
+use sqlite_loadable::{Error, Result};
+use std::str::FromStr;
+use zerocopy::AsBytes;
+
+/// Element type used when emitting embedding blobs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Precision {
+    #[default]
+    F32,
+    F16,
+    /// The provider's values unnarrowed, for high-precision comparisons
+    F64,
+}
+
+impl Precision {
+    /// Option value naming this precision
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precision::F32 => "f32",
+            Precision::F16 => "f16",
+            Precision::F64 => "f64",
+        }
+    }
+
+    /// Bytes per element of blobs in this precision
+    pub fn width(&self) -> usize {
+        match self {
+            Precision::F32 => 4,
+            Precision::F16 => 2,
+            Precision::F64 => 8,
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "f32" => Ok(Precision::F32),
+            "f16" => Ok(Precision::F16),
+            "f64" => Ok(Precision::F64),
+            other => Err(Error::new_message(format!(
+                "Invalid precision '{}', expected 'f32', 'f16' or 'f64'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Layout of emitted embedding blobs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// The elements alone, as sqlite-vec expects
+    #[default]
+    Raw,
+    /// The dimension count as a little-endian u32, then the elements
+    Prefixed,
+}
+
+impl OutputFormat {
+    /// Option value naming this format
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Raw => "raw",
+            OutputFormat::Prefixed => "prefixed",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "raw" => Ok(OutputFormat::Raw),
+            "prefixed" => Ok(OutputFormat::Prefixed),
+            other => Err(Error::new_message(format!(
+                "Invalid output_format '{}', expected 'raw' or 'prefixed'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Byte order of the elements of emitted blobs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Endian {
+    /// The platform's order (little-endian on common platforms)
+    #[default]
+    Native,
+    Little,
+    Big,
+}
+
+impl Endian {
+    /// Option value naming this byte order
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Endian::Native => "native",
+            Endian::Little => "little",
+            Endian::Big => "big",
+        }
+    }
+}
+
+impl FromStr for Endian {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "native" => Ok(Endian::Native),
+            "little" => Ok(Endian::Little),
+            "big" => Ok(Endian::Big),
+            other => Err(Error::new_message(format!(
+                "Invalid endian '{}', expected 'native', 'little' or 'big'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Reorder the elements of bytes from `encode` into `endian` byte order.
+/// Swapping is its own inverse, so this also turns `endian` blobs back into
+/// the order `encode` writes.
+pub fn reorder(mut bytes: Vec<u8>, precision: Precision, endian: Endian) -> Vec<u8> {
+    // f16 elements are always written little-endian, the others natively
+    let encoded_big = precision != Precision::F16 && cfg!(target_endian = "big");
+    let swap = match endian {
+        Endian::Native => false,
+        Endian::Little => encoded_big,
+        Endian::Big => !encoded_big,
+    };
+    if swap {
+        bytes.chunks_exact_mut(precision.width()).for_each(|element| element.reverse());
+    }
+    bytes
+}
+
+/// Decode a blob body in the given precision and byte order into float32 values
+pub fn decode_endian(bytes: &[u8], precision: Precision, endian: Endian) -> Result<Vec<f32>> {
+    let bytes = reorder(bytes.to_vec(), precision, endian);
+    match precision {
+        Precision::F32 => f32_from_bytes(&bytes),
+        Precision::F16 => f32_from_f16_bytes(&bytes),
+        Precision::F64 => f32_from_f64_bytes(&bytes),
+    }
+}
+
+/// Element bytes of a vector preceded by its dimension count
+pub fn with_prefix(dimensions: usize, body: &[u8]) -> Vec<u8> {
+    let mut blob = (dimensions as u32).to_le_bytes().to_vec();
+    blob.extend_from_slice(body);
+    blob
+}
+
+/// The element bytes of a dimension-prefixed blob, checking the prefix
+/// matches a float64, float32 or half precision body
+pub fn strip_prefix(blob: &[u8]) -> Result<&[u8]> {
+    if blob.len() < 4 {
+        return Err(Error::new_message("Blob is too short to carry a dimension prefix"));
+    }
+    let (prefix, body) = blob.split_at(4);
+    let dimensions = u32::from_le_bytes(prefix.try_into().expect("split at 4 bytes")) as usize;
+    if ![8, 4, 2].iter().any(|width| body.len() == dimensions * width) {
+        return Err(Error::new_message(format!(
+            "Dimension prefix {} does not match a {} byte body",
+            dimensions,
+            body.len()
+        )));
+    }
+    Ok(body)
+}
+
+/// How f64 values from the provider are narrowed to f32
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum F32Rounding {
+    /// Round to the nearest f32, ties to even (what `as f32` does)
+    #[default]
+    Nearest,
+    /// Round toward zero, dropping the bits f32 cannot hold
+    Truncate,
+}
+
+impl F32Rounding {
+    /// Option value naming this rounding mode
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            F32Rounding::Nearest => "nearest",
+            F32Rounding::Truncate => "truncate",
+        }
+    }
+
+    /// Narrow one value with this rounding mode
+    pub fn round(&self, value: f64) -> f32 {
+        let nearest = value as f32;
+        match self {
+            F32Rounding::Nearest => nearest,
+            // Step back one ulp toward zero when rounding went outward; the
+            // bit pattern of a float orders its magnitude for either sign
+            F32Rounding::Truncate if (nearest as f64).abs() > value.abs() => {
+                f32::from_bits(nearest.to_bits() - 1)
+            }
+            F32Rounding::Truncate => nearest,
+        }
+    }
+}
+
+impl FromStr for F32Rounding {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "nearest" => Ok(F32Rounding::Nearest),
+            "truncate" => Ok(F32Rounding::Truncate),
+            other => Err(Error::new_message(format!(
+                "Invalid f32_round '{}', expected 'nearest' or 'truncate'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Narrow a provider's f64 embedding to f32 with the given rounding mode
+pub fn downcast(values: &[f64], rounding: F32Rounding) -> Vec<f32> {
+    values.iter().map(|&v| rounding.round(v)).collect()
+}
+
+/// How rembed_pool combines vectors element-wise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolMethod {
+    Mean,
+    Max,
+    Sum,
+}
+
+impl FromStr for PoolMethod {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "mean" => Ok(PoolMethod::Mean),
+            "max" => Ok(PoolMethod::Max),
+            "sum" => Ok(PoolMethod::Sum),
+            other => Err(Error::new_message(format!(
+                "Invalid pooling method '{}', expected 'mean', 'max' or 'sum'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Encode an embedding as blob bytes in the requested precision
+pub fn encode(embedding: &[f32], precision: Precision) -> Vec<u8> {
+    match precision {
+        Precision::F32 => embedding.as_bytes().to_vec(),
+        Precision::F16 => f16_bytes(embedding),
+        Precision::F64 => embedding.iter().map(|&v| f64::from(v)).collect::<Vec<f64>>().as_bytes().to_vec(),
+    }
+}
+
+/// Encode an embedding of the provider's f64 values in the requested
+/// precision, narrowing them with rounding unless it is F64
+pub fn encode_wide(embedding: &[f64], precision: Precision, rounding: F32Rounding) -> Vec<u8> {
+    match precision {
+        Precision::F64 => embedding.as_bytes().to_vec(),
+        precision => encode(&downcast(embedding, rounding), precision),
+    }
+}
+
+/// Convert float32 values to little-endian IEEE 754 half precision bytes
+pub fn f16_bytes(embedding: &[f32]) -> Vec<u8> {
+    embedding
+        .iter()
+        .flat_map(|&v| half::f16::from_f32(v).to_le_bytes())
+        .collect()
+}
+
+/// Decode little-endian half precision bytes into float32 values
+pub fn f32_from_f16_bytes(bytes: &[u8]) -> Result<Vec<f32>> {
+    if bytes.len() % 2 != 0 {
+        return Err(Error::new_message(format!(
+            "f16 blob byte length {} is not a multiple of 2",
+            bytes.len()
+        )));
+    }
+
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|chunk| half::f16::from_le_bytes([chunk[0], chunk[1]]).to_f32())
+        .collect())
+}
+
+/// Decode little-endian float32 bytes into a vector
+pub fn f32_from_bytes(bytes: &[u8]) -> Result<Vec<f32>> {
+    if bytes.len() % 4 != 0 {
+        return Err(Error::new_message(format!(
+            "Embedding byte length {} is not a multiple of 4",
+            bytes.len()
+        )));
+    }
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
+}
+
+/// Decode little-endian float64 bytes, narrowing them to float32
+pub fn f32_from_f64_bytes(bytes: &[u8]) -> Result<Vec<f32>> {
+    if bytes.len() % 8 != 0 {
+        return Err(Error::new_message(format!(
+            "f64 blob byte length {} is not a multiple of 8",
+            bytes.len()
+        )));
+    }
+
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| f64::from_le_bytes(chunk.try_into().expect("chunks of 8 bytes")) as f32)
+        .collect())
+}
+
+/// Decode a vector expected to have `dimensions` values, reading float64
+/// when its length matches that width and float32 otherwise
+pub fn f32_from_bytes_of_width(bytes: &[u8], dimensions: usize) -> Result<Vec<f32>> {
+    if dimensions > 0 && bytes.len() == dimensions * 8 {
+        f32_from_f64_bytes(bytes)
+    } else {
+        f32_from_bytes(bytes)
+    }
+}
+
+/// The `len` dimensions of a vector starting at `start`, failing on
+/// negative or out-of-range bounds
+pub fn slice(vector: &[f32], start: i64, len: i64) -> Result<&[f32]> {
+    if start < 0 || len < 0 {
+        return Err(Error::new_message(format!(
+            "Slice start ({}) and length ({}) must not be negative",
+            start, len
+        )));
+    }
+    let (start, len) = (start as usize, len as usize);
+    match start.checked_add(len) {
+        Some(end) if end <= vector.len() => Ok(&vector[start..end]),
+        _ => Err(Error::new_message(format!(
+            "Slice of {} dimensions at {} is out of range for a {}-dimension vector",
+            len,
+            start,
+            vector.len()
+        ))),
+    }
+}
+
+/// Decode a base64 string of packed little-endian float32 values, as returned
+/// by OpenAI's `encoding_format=base64` and by `rembed_batch`
+pub fn f32_from_base64(encoded: &str) -> Result<Vec<f32>> {
+    f32_from_bytes(&bytes_from_base64(encoded)?)
+}
+
+/// Decode standard base64 into bytes
+pub fn bytes_from_base64(encoded: &str) -> Result<Vec<u8>> {
+    use base64::Engine as _;
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| Error::new_message(format!("Base64 decode failed: {}", e)))
+}
+
+/// Fail unless two vectors share a dimension
+pub fn check_dimensions(expected: usize, actual: usize) -> Result<()> {
+    if expected != actual {
+        return Err(Error::new_message(format!(
+            "Dimension mismatch: expected {}, got {}",
+            expected, actual
+        )));
+    }
+    Ok(())
+}
+
+/// Cosine similarity of two equal-length vectors (0.0 if either is all zeros)
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Result<f32> {
+    check_dimensions(a.len(), b.len())?;
+    let (mut dot, mut norm_a, mut norm_b) = (0f32, 0f32, 0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return Ok(0.0);
+    }
+    Ok(dot / (norm_a.sqrt() * norm_b.sqrt()))
+}
+
+/// Most vectors rembed_distance_matrix accepts, bounding its n² output
+pub const MAX_DISTANCE_MATRIX_VECTORS: usize = 512;
+
+/// Pairwise distance used by rembed_distance_matrix; smaller is closer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    /// 1 - cosine similarity
+    Cosine,
+    /// Euclidean distance
+    L2,
+    /// Negative inner product, as sqlite-vec and pgvector order it
+    Dot,
+}
+
+impl FromStr for DistanceMetric {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "cosine" => Ok(DistanceMetric::Cosine),
+            "l2" => Ok(DistanceMetric::L2),
+            "dot" => Ok(DistanceMetric::Dot),
+            other => Err(Error::new_message(format!(
+                "Invalid metric '{}', expected 'cosine', 'l2' or 'dot'",
+                other
+            ))),
+        }
+    }
+}
+
+impl DistanceMetric {
+    /// Distance between two equal-length vectors
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> Result<f32> {
+        check_dimensions(a.len(), b.len())?;
+        Ok(match self {
+            DistanceMetric::Cosine => 1.0 - cosine_similarity(a, b)?,
+            DistanceMetric::L2 => a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt(),
+            DistanceMetric::Dot => -a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>(),
+        })
+    }
+}
+
+/// Distances between every pair of vectors, as rows of a symmetric matrix
+pub fn distance_matrix(vectors: &[Vec<f32>], metric: DistanceMetric) -> Result<Vec<Vec<f32>>> {
+    if vectors.len() > MAX_DISTANCE_MATRIX_VECTORS {
+        return Err(Error::new_message(format!(
+            "Distance matrix of {} vectors exceeds the limit of {}",
+            vectors.len(),
+            MAX_DISTANCE_MATRIX_VECTORS
+        )));
+    }
+    let mut matrix = vec![vec![0.0; vectors.len()]; vectors.len()];
+    for i in 0..vectors.len() {
+        for j in i..vectors.len() {
+            let distance = metric
+                .distance(&vectors[i], &vectors[j])
+                .map_err(|e| Error::new_message(format!("Vectors {} and {}: {}", i, j, e)))?;
+            matrix[i][j] = distance;
+            matrix[j][i] = distance;
+        }
+    }
+    Ok(matrix)
+}
+
+/// Combine equal-length vectors element-wise
+pub fn pool(vectors: &[Vec<f32>], method: PoolMethod) -> Result<Vec<f32>> {
+    let first = vectors
+        .first()
+        .ok_or_else(|| Error::new_message("Cannot pool an empty set of vectors"))?;
+    let dimensions = first.len();
+
+    let mut pooled = first.clone();
+    for vector in &vectors[1..] {
+        check_dimensions(dimensions, vector.len())?;
+        for (acc, &v) in pooled.iter_mut().zip(vector) {
+            *acc = match method {
+                PoolMethod::Max => acc.max(v),
+                PoolMethod::Mean | PoolMethod::Sum => *acc + v,
+            };
+        }
+    }
+
+    if method == PoolMethod::Mean {
+        let count = vectors.len() as f32;
+        pooled.iter_mut().for_each(|v| *v /= count);
+    }
+    Ok(pooled)
+}
+
+/// Element-wise mean of equal-length vectors, scaled to unit length
+pub fn mean_pool_normalized(vectors: &[Vec<f32>]) -> Result<Vec<f32>> {
+    let first = vectors
+        .first()
+        .ok_or_else(|| Error::new_message("Cannot pool an empty set of vectors"))?;
+    let dimensions = first.len();
+
+    let mut pooled = vec![0f32; dimensions];
+    for vector in vectors {
+        check_dimensions(dimensions, vector.len())?;
+        for (acc, v) in pooled.iter_mut().zip(vector) {
+            *acc += v;
+        }
+    }
+
+    let count = vectors.len() as f32;
+    pooled.iter_mut().for_each(|v| *v /= count);
+    Ok(normalize(pooled))
+}
+
+/// Running element-wise sum of equal-length vectors, added one at a time
+#[derive(Debug, Default)]
+pub struct Centroid {
+    sum: Vec<f32>,
+    count: usize,
+}
+
+impl Centroid {
+    /// Add a vector, failing if its length differs from the earlier ones
+    pub fn add(&mut self, vector: &[f32]) -> Result<()> {
+        if self.count == 0 {
+            self.sum = vector.to_vec();
+        } else {
+            check_dimensions(self.sum.len(), vector.len())?;
+            for (acc, v) in self.sum.iter_mut().zip(vector) {
+                *acc += v;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Element-wise mean of the added vectors, None if none were added
+    pub fn mean(&self) -> Option<Vec<f32>> {
+        if self.count == 0 {
+            return None;
+        }
+        let count = self.count as f32;
+        Some(self.sum.iter().map(|v| v / count).collect())
+    }
+}
+
+/// Allowed deviation of the L2 norm from 1.0 for a vector to count as normalized
+pub const NORMALIZED_TOLERANCE: f32 = 1e-3;
+
+/// Fail if a vector is useless for similarity search: all zeros, or
+/// holding NaN or infinite values
+pub fn check_degenerate(vector: &[f64]) -> Result<()> {
+    if vector.iter().any(|v| !v.is_finite()) {
+        return Err(Error::new_message("Degenerate embedding: contains NaN or infinite values"));
+    }
+    if vector.iter().all(|v| *v == 0.0) {
+        return Err(Error::new_message("Degenerate embedding: all zeros"));
+    }
+    Ok(())
+}
+
+/// Whether a vector has unit L2 norm within NORMALIZED_TOLERANCE
+pub fn is_normalized(vector: &[f32]) -> bool {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    (norm - 1.0).abs() <= NORMALIZED_TOLERANCE
+}
+
+/// Float types vectors are scaled and fitted in
+pub trait Element: Copy + Default + PartialOrd + std::ops::Mul<Output = Self> + std::ops::DivAssign + std::iter::Sum {
+    fn sqrt(self) -> Self;
+}
+
+impl Element for f32 {
+    fn sqrt(self) -> Self {
+        f32::sqrt(self)
+    }
+}
+
+impl Element for f64 {
+    fn sqrt(self) -> Self {
+        f64::sqrt(self)
+    }
+}
+
+/// Scale a vector to unit L2 norm; zero vectors are returned unchanged
+pub fn normalize<T: Element>(mut vector: Vec<T>) -> Vec<T> {
+    let norm = vector.iter().map(|&v| v * v).sum::<T>().sqrt();
+    if norm > T::default() {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+/// Fit a vector to exactly target_dim dimensions: longer vectors are
+/// truncated and renormalized, shorter ones are zero-padded, which keeps
+/// their norm and cosine similarities unchanged
+pub fn fit_dimensions<T: Element>(mut vector: Vec<T>, target_dim: usize) -> Vec<T> {
+    if vector.len() > target_dim {
+        vector.truncate(target_dim);
+        normalize(vector)
+    } else {
+        vector.resize(target_dim, T::default());
+        vector
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f32_from_base64_matches_json_array() {
+        let json_vector: Vec<f32> = serde_json::from_str("[0.5, -1.25, 3.0, 0.0]").unwrap();
+        let bytes: Vec<u8> = json_vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+        use base64::Engine as _;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+
+        assert_eq!(f32_from_base64(&encoded).unwrap(), json_vector);
+    }
+
+    #[test]
+    fn test_f16_round_trip_within_tolerance() {
+        let original = vec![0.0f32, 1.0, -1.0, 0.333_333, -0.125, 12.75, 1e-3];
+        let encoded = encode(&original, Precision::F16);
+        assert_eq!(encoded.len(), original.len() * 2);
+
+        let decoded = f32_from_f16_bytes(&encoded).unwrap();
+        for (a, b) in original.iter().zip(decoded.iter()) {
+            // f16 has an 11-bit significand: relative error is at most 2^-11
+            assert!((a - b).abs() <= a.abs() * 2f32.powi(-11) + 1e-7, "{} vs {}", a, b);
+        }
+        assert!(f32_from_f16_bytes(&[0, 0, 0]).is_err());
+        assert!("f8".parse::<Precision>().is_err());
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]).unwrap() - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).unwrap().abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 1.0], &[-1.0, -1.0]).unwrap() + 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]).unwrap(), 0.0);
+        assert!(cosine_similarity(&[1.0, 0.0], &[1.0, 0.0, 0.0]).is_err());
+    }
+
+    #[test]
+    fn test_mean_pool_normalized() {
+        let pooled = mean_pool_normalized(&[vec![1.0, 0.0], vec![0.0, 1.0]]).unwrap();
+        let expected = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((pooled[0] - expected).abs() < 1e-6);
+        assert!((pooled[1] - expected).abs() < 1e-6);
+
+        assert!(mean_pool_normalized(&[]).is_err());
+        assert!(mean_pool_normalized(&[vec![1.0], vec![1.0, 2.0]]).is_err());
+    }
+
+    #[test]
+    fn test_centroid() {
+        let mut centroid = Centroid::default();
+        assert_eq!(centroid.mean(), None);
+        centroid.add(&[2.0, 0.0]).unwrap();
+        centroid.add(&[0.0, 2.0]).unwrap();
+        assert_eq!(centroid.mean(), Some(vec![1.0, 1.0]));
+
+        let err = centroid.add(&[1.0, 2.0, 3.0]).unwrap_err();
+        assert!(err.to_string().contains("Dimension mismatch"));
+        assert_eq!(centroid.mean(), Some(vec![1.0, 1.0]));
+    }
+
+    #[test]
+    fn test_f32_from_bytes_rejects_misaligned_length() {
+        assert!(f32_from_bytes(&[0, 0, 128]).is_err());
+        assert!(f32_from_base64("AAAAAAA=").is_err());
+    }
+
+    #[test]
+    fn test_f64_blobs() {
+        let wide = [0.1f64, 0.2, 0.3];
+        let blob = encode_wide(&wide, Precision::F64, F32Rounding::Nearest);
+        assert_eq!(blob.len(), wide.len() * 8);
+        assert_eq!(blob, wide.as_bytes());
+        assert_eq!(f32_from_f64_bytes(&blob).unwrap(), vec![0.1f32, 0.2, 0.3]);
+        assert_eq!(encode_wide(&wide, Precision::F32, F32Rounding::Nearest).len(), wide.len() * 4);
+        assert_eq!(encode(&[1.0, 2.0], Precision::F64), [1.0f64, 2.0].as_bytes());
+        assert!(f32_from_f64_bytes(&[0; 12]).is_err());
+        assert_eq!(strip_prefix(&with_prefix(2, &encode(&[1.0, 2.0], Precision::F64))).unwrap().len(), 16);
+        assert_eq!("f64".parse::<Precision>().unwrap(), Precision::F64);
+
+        // A float64 query compares against float32 candidates of its dimension
+        let query = f32_from_f64_bytes([3.0f64, 4.0].as_bytes()).unwrap();
+        let candidate = f32_from_bytes_of_width(encode(&[6.0, 8.0], Precision::F32).as_slice(), query.len()).unwrap();
+        assert!((cosine_similarity(&query, &candidate).unwrap() - 1.0).abs() < 1e-6);
+        let candidate = f32_from_bytes_of_width([0.0f64, 1.0].as_bytes(), query.len()).unwrap();
+        assert!((cosine_similarity(&query, &candidate).unwrap() - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_distance_matrix() {
+        let vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![3.0, 4.0]];
+        for (metric, expected) in [
+            ("cosine", [[0.0, 1.0, 0.4], [1.0, 0.0, 0.2], [0.4, 0.2, 0.0]]),
+            ("l2", [[0.0, 2f32.sqrt(), 20f32.sqrt()], [2f32.sqrt(), 0.0, 18f32.sqrt()], [20f32.sqrt(), 18f32.sqrt(), 0.0]]),
+            ("dot", [[-1.0, 0.0, -3.0], [0.0, -1.0, -4.0], [-3.0, -4.0, -25.0]]),
+        ] {
+            let matrix = distance_matrix(&vectors, metric.parse().unwrap()).unwrap();
+            for i in 0..3 {
+                for j in 0..3 {
+                    assert_eq!(matrix[i][j], matrix[j][i]);
+                    assert!((matrix[i][j] - expected[i][j]).abs() < 1e-6, "{} [{}][{}]", metric, i, j);
+                }
+            }
+        }
+
+        assert!(distance_matrix(&[vec![1.0], vec![1.0, 2.0]], DistanceMetric::L2).is_err());
+        let too_many = vec![vec![1.0]; MAX_DISTANCE_MATRIX_VECTORS + 1];
+        assert!(distance_matrix(&too_many, DistanceMetric::L2).is_err());
+        assert!("manhattan".parse::<DistanceMetric>().is_err());
+    }
+
+    #[test]
+    fn test_slice() {
+        let vector = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(slice(&vector, 1, 2).unwrap(), &[2.0, 3.0]);
+        assert_eq!(slice(&vector, 0, 4).unwrap(), &vector);
+        assert!(slice(&vector, 4, 0).unwrap().is_empty());
+        assert!(slice(&vector, 3, 2).is_err());
+        assert!(slice(&vector, -1, 2).is_err());
+        assert!(slice(&vector, 0, -1).is_err());
+        assert!(slice(&vector, i64::MAX, i64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_prefixed_blobs() {
+        let blob = with_prefix(3, &encode(&[1.0, 2.0, 3.0], Precision::F32));
+        assert_eq!(&blob[..4], &3u32.to_le_bytes());
+        assert_eq!(strip_prefix(&blob).unwrap(), encode(&[1.0, 2.0, 3.0], Precision::F32).as_slice());
+
+        let half = with_prefix(2, &encode(&[1.0, 2.0], Precision::F16));
+        assert_eq!(strip_prefix(&half).unwrap(), f16_bytes(&[1.0, 2.0]).as_slice());
+
+        assert!(strip_prefix(&[1, 0]).is_err());
+        assert!(strip_prefix(&encode(&[1.0, 2.0], Precision::F32)).is_err());
+        assert_eq!("prefixed".parse::<OutputFormat>().unwrap(), OutputFormat::Prefixed);
+        assert!("framed".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_endian() {
+        let values = [1.0, -2.5];
+        for precision in [Precision::F32, Precision::F16, Precision::F64] {
+            let big = reorder(encode(&values, precision), precision, Endian::Big);
+            let width = precision.width();
+            assert_eq!(big.len(), values.len() * width);
+            // The sign bit leads a big-endian element
+            assert_eq!(big[width] & 0x80, 0x80, "{:?}", precision);
+            assert_eq!(decode_endian(&big, precision, Endian::Big).unwrap(), values, "{:?}", precision);
+
+            let little = reorder(encode(&values, precision), precision, Endian::Little);
+            assert_eq!(little[2 * width - 1] & 0x80, 0x80, "{:?}", precision);
+            assert_eq!(decode_endian(&little, precision, Endian::Little).unwrap(), values);
+        }
+        assert_eq!(reorder(encode(&values, Precision::F32), Precision::F32, Endian::Big), [
+            1.0f32.to_be_bytes(),
+            (-2.5f32).to_be_bytes(),
+        ].concat());
+        assert_eq!("big".parse::<Endian>().unwrap(), Endian::Big);
+        assert!("middle".parse::<Endian>().is_err());
+    }
+
+    #[test]
+    fn test_f32_rounding() {
+        // Just past halfway between 1.0 and the next f32 up
+        let value = 1.0 + 2f64.powi(-24) + 2f64.powi(-40);
+        let up = 1.0 + f32::EPSILON;
+        assert_eq!(F32Rounding::Nearest.round(value), up);
+        assert_eq!(F32Rounding::Truncate.round(value), 1.0);
+        assert_eq!(F32Rounding::Nearest.round(-value), -up);
+        assert_eq!(F32Rounding::Truncate.round(-value), -1.0);
+
+        // Values that already round toward zero agree in both modes
+        let below = 1.0 + 2f64.powi(-25);
+        assert_eq!(F32Rounding::Truncate.round(below), F32Rounding::Nearest.round(below));
+        assert_eq!(F32Rounding::Truncate.round(0.5), 0.5);
+        assert_eq!(F32Rounding::Truncate.round(1e300), f32::MAX);
+
+        assert_eq!(downcast(&[value, 0.25], F32Rounding::Truncate), vec![1.0, 0.25]);
+        assert_eq!("truncate".parse::<F32Rounding>().unwrap(), F32Rounding::Truncate);
+        assert!("floor".parse::<F32Rounding>().is_err());
+    }
+
+    #[test]
+    fn test_fit_dimensions() {
+        let truncated = fit_dimensions(vec![3.0, 4.0, 12.0], 2);
+        assert_eq!(truncated.len(), 2);
+        assert!(is_normalized(&truncated));
+        assert_eq!(truncated, vec![0.6, 0.8]);
+
+        let padded = fit_dimensions(vec![0.6, 0.8], 4);
+        assert_eq!(padded.len(), 4);
+        assert_eq!(padded, vec![0.6, 0.8, 0.0, 0.0]);
+
+        assert_eq!(fit_dimensions(vec![0.6, 0.8], 2), vec![0.6, 0.8]);
+    }
+
+    #[test]
+    fn test_is_normalized() {
+        assert!(is_normalized(&[0.6, 0.8]));
+        assert!(is_normalized(&normalize(vec![3.0, 4.0, 12.0])));
+        assert!(!is_normalized(&[3.0, 4.0]));
+        assert!(!is_normalized(&[0.0, 0.0]));
+        assert!(!is_normalized(&[]));
+    }
+
+    #[test]
+    fn test_pool_methods() {
+        let vectors = vec![vec![1.0, -2.0, 3.0], vec![3.0, 0.0, -1.0], vec![2.0, 5.0, 1.0]];
+        assert_eq!(pool(&vectors, PoolMethod::Mean).unwrap(), vec![2.0, 1.0, 1.0]);
+        assert_eq!(pool(&vectors, PoolMethod::Max).unwrap(), vec![3.0, 5.0, 3.0]);
+        assert_eq!(pool(&vectors, PoolMethod::Sum).unwrap(), vec![6.0, 3.0, 3.0]);
+
+        assert!(pool(&[], PoolMethod::Mean).is_err());
+        assert!(pool(&[vec![1.0], vec![1.0, 2.0]], PoolMethod::Sum).is_err());
+        assert!("median".parse::<PoolMethod>().is_err());
+    }
+}